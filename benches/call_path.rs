@@ -0,0 +1,209 @@
+//! Benchmarks for the call path: signature parsing, Cif construction, calls
+//! with and without the symbol cache, and a full round trip over loopback.
+//!
+//! Run with `cargo bench`; pass a substring to run matching benchmarks only,
+//! e.g. `cargo bench -- call/`.
+
+// The server is a binary crate, so the modules under test are compiled in directly.
+// Their unit tests are not run here, which leaves the tests' imports unused.
+#[allow(dead_code)]
+#[path = "../src/capture.rs"]
+mod capture;
+#[allow(dead_code)]
+#[path = "../src/debuginfo.rs"]
+mod debuginfo;
+#[allow(dead_code)]
+#[path = "../src/demangle.rs"]
+mod demangle;
+#[allow(dead_code)]
+#[path = "../src/encoding.rs"]
+mod encoding;
+#[allow(dead_code)]
+#[path = "../src/exports.rs"]
+mod exports;
+#[allow(dead_code)]
+#[path = "../src/handles.rs"]
+mod handles;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/integer.rs"]
+mod integer;
+#[allow(dead_code)]
+#[path = "../src/invoke.rs"]
+mod invoke;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/longdouble.rs"]
+mod longdouble;
+#[allow(dead_code)]
+#[path = "../src/registry.rs"]
+mod registry;
+#[allow(dead_code)]
+#[path = "../src/schema.rs"]
+mod schema;
+#[allow(dead_code)]
+#[path = "../src/signals.rs"]
+mod signals;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/signature.rs"]
+mod signature;
+
+use encoding::StringEncoding;
+use invoke::{CallOptions, VoidReply, invoke_function};
+use libffi::middle::{Cif, Type};
+use libloading::Library;
+use registry::{LibraryRegistry, LoadOptions, Serialization};
+use signature::{CallingConvention, parse_signature};
+use std::hint::black_box;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const WARM_UP: Duration = Duration::from_millis(200);
+const SAMPLES: usize = 20;
+const SAMPLE_TIME: Duration = Duration::from_millis(50);
+
+/// Times `f` over several samples and prints the median, fastest and slowest
+/// per-iteration time.
+fn bench(filter: Option<&str>, name: &str, mut f: impl FnMut()) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+    let warm_up = Instant::now();
+    while warm_up.elapsed() < WARM_UP {
+        f();
+    }
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            let mut iterations = 0;
+            while start.elapsed() < SAMPLE_TIME {
+                f();
+                iterations += 1;
+            }
+            start.elapsed() / iterations
+        })
+        .collect();
+    samples.sort();
+    println!(
+        "{:<32} median {:>12?}   [{:?} .. {:?}]",
+        name,
+        samples[SAMPLES / 2],
+        samples[0],
+        samples[SAMPLES - 1]
+    );
+}
+
+/// Builds the C fixture library, as the integration tests do.
+fn fixture_lib() -> String {
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bench_lib_linux.so");
+    let status = Command::new("cc")
+        .args(["-shared", "-fPIC", "-O2", "-o"])
+        .arg(&out)
+        .arg("testlib/lib_linux.c")
+        .status()
+        .expect("Couldn't run cc");
+    assert!(status.success(), "Couldn't build testlib/lib_linux.c");
+    out.to_string_lossy().into_owned()
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; anything else is a name filter.
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let filter = filter.as_deref();
+    let lib_path = fixture_lib();
+
+    bench(filter, "parse_signature", || {
+        black_box(parse_signature(
+            black_box("int,int(stdcall)->int"),
+            CallingConvention::Cdecl,
+        ))
+        .unwrap();
+    });
+
+    bench(filter, "cif/new", || {
+        black_box(Cif::new([Type::i32(), Type::i32()], Type::i32()));
+    });
+
+    let signature = parse_signature("int,int->int", CallingConvention::Cdecl).unwrap();
+    let options = CallOptions {
+        encoding: StringEncoding::Utf8,
+        max_result_bytes: 1024 * 1024,
+        discard_result: false,
+        float_bits: false,
+        hex: false,
+        dry_run: false,
+        void_reply: VoidReply::Ok,
+        trace: false,
+        time: false,
+        capture: false,
+        char_signed: true,
+        lenient_args: false,
+        return_names: None,
+        handles: Default::default(),
+        enums: Default::default(),
+    };
+
+    let registry = LibraryRegistry::new(
+        unsafe { Library::new(&lib_path) }.unwrap(),
+        lib_path.clone().into(),
+        LoadOptions::default(),
+        Serialization::default(),
+    );
+    let (lib, _) = registry.resolve("AddNumbers").unwrap();
+    bench(filter, "call/cached symbol", || {
+        let result = invoke_function(&lib, "AddNumbers", &signature, &["1", "2"], &options);
+        black_box(result).unwrap();
+    });
+
+    // A fresh registry has an empty cache, so every call resolves the symbol.
+    bench(filter, "call/uncached symbol", || {
+        let registry = LibraryRegistry::new(
+            unsafe { Library::new(&lib_path) }.unwrap(),
+            lib_path.clone().into(),
+            LoadOptions::default(),
+            Serialization::default(),
+        );
+        let (lib, _) = registry.resolve("AddNumbers").unwrap();
+        let result = invoke_function(&lib, "AddNumbers", &signature, &["1", "2"], &options);
+        black_box(result).unwrap();
+    });
+
+    if filter.is_none_or(|filter| "round_trip/loopback".contains(filter)) {
+        round_trip(filter, &lib_path);
+    }
+}
+
+/// One request and response over a loopback connection to a real server.
+fn round_trip(filter: Option<&str>, lib_path: &str) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(lib_path)
+        .arg(port.to_string())
+        .arg("--machine")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut ready = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut ready)
+        .unwrap();
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    bench(filter, "round_trip/loopback", || {
+        stream
+            .write_all(b"call AddNumbers sig:int,int->int 1 2\n")
+            .unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "3\n");
+    });
+
+    child.kill().ok();
+    child.wait().ok();
+}
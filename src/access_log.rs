@@ -0,0 +1,160 @@
+use crate::capture;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Append-only record of every request, one `key=value` line each:
+///
+/// `ts=2026-10-14T12:00:00.123Z peer=127.0.0.1:50312 cmd=call fn=AddNumbers status=ok latency_us=85`
+///
+/// Failed requests add `error=<code>` when the error carries an `E_` code.
+/// Unlike the status output, the format is stable so audit tooling can parse it.
+pub struct AccessLog {
+    sink: Mutex<Sink>,
+}
+
+enum Sink {
+    Stdout,
+    File {
+        path: String,
+        file: File,
+        written: u64,
+        /// Rotate to `<path>.1` once the file would grow past this many bytes.
+        max_bytes: Option<u64>,
+    },
+}
+
+/// One finished request, as recorded in the log.
+pub struct AccessEntry<'a> {
+    pub peer: Option<&'a str>,
+    pub command: &'a str,
+    pub function: Option<&'a str>,
+    pub result: &'a Result<String, String>,
+    pub latency: Duration,
+}
+
+impl AccessLog {
+    /// Opens `path` for appending, or logs to stdout when `path` is `-`.
+    pub fn open(path: &str, max_bytes: Option<u64>) -> Result<AccessLog, String> {
+        let sink = if path == "-" {
+            Sink::Stdout
+        } else {
+            let file = open_append(path)?;
+            let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+            Sink::File {
+                path: path.to_string(),
+                file,
+                written,
+                max_bytes,
+            }
+        };
+        Ok(AccessLog {
+            sink: Mutex::new(sink),
+        })
+    }
+
+    /// Appends `entry`. Logging failures are reported on stderr but never fail the request.
+    pub fn record(&self, entry: &AccessEntry) {
+        let line = format_entry(SystemTime::now(), entry);
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = sink.write_line(&line) {
+            capture::log(format_args!("Failed to write access log: {}", e));
+        }
+    }
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        match self {
+            Sink::Stdout => capture::server_stdout(line).map_err(|e| e.to_string()),
+            Sink::File {
+                path,
+                file,
+                written,
+                max_bytes,
+            } => {
+                let len = line.len() as u64;
+                if let Some(max) = *max_bytes
+                    && *written > 0
+                    && *written + len > max
+                {
+                    let rotated = format!("{}.1", path);
+                    std::fs::rename(&*path, &rotated)
+                        .map_err(|e| format!("rotating to {}: {}", rotated, e))?;
+                    *file = open_append(path)?;
+                    *written = 0;
+                }
+                file.write_all(line.as_bytes()).map_err(|e| e.to_string())?;
+                *written += len;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn open_append(path: &str) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open access log {}: {}", path, e))
+}
+
+fn format_entry(now: SystemTime, entry: &AccessEntry) -> String {
+    let peer = entry.peer.unwrap_or("-");
+    let mut line = format!(
+        "ts={} peer={} cmd={} fn={}",
+        format_timestamp(now),
+        peer,
+        entry.command,
+        entry.function.unwrap_or("-")
+    );
+    match entry.result {
+        Ok(_) => line.push_str(" status=ok"),
+        Err(err) => {
+            line.push_str(" status=err");
+            if let Some(code) = err
+                .split_whitespace()
+                .next()
+                .filter(|c| c.starts_with("E_"))
+            {
+                line.push_str(&format!(" error={}", code));
+            }
+        }
+    }
+    line.push_str(&format!(" latency_us={}\n", entry.latency.as_micros()));
+    line
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with millisecond precision.
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
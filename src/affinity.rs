@@ -0,0 +1,58 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A set of CPU numbers given as a list like `0,2-3`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuList(Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid CPU list: {}", s);
+        let mut cpus = Vec::new();
+        for part in s.split(',') {
+            let (first, last) = part.split_once('-').unwrap_or((part, part));
+            let first: usize = first.trim().parse().map_err(|_| invalid())?;
+            let last: usize = last.trim().parse().map_err(|_| invalid())?;
+            if first > last {
+                return Err(invalid());
+            }
+            cpus.extend(first..=last);
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(CpuList(cpus))
+    }
+}
+
+impl fmt::Display for CpuList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cpus: Vec<String> = self.0.iter().map(usize::to_string).collect();
+        f.write_str(&cpus.join(","))
+    }
+}
+
+impl CpuList {
+    /// Restricts the calling thread, and the threads it spawns afterwards, to
+    /// these CPUs.
+    #[cfg(target_os = "linux")]
+    pub fn pin_current_thread(&self) -> Result<(), String> {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        let capacity = 8 * size_of::<libc::cpu_set_t>();
+        for &cpu in &self.0 {
+            if cpu >= capacity {
+                return Err(format!("CPU {} is out of range", cpu));
+            }
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        if unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) } != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn pin_current_thread(&self) -> Result<(), String> {
+        Err("CPU pinning is only supported on Linux".into())
+    }
+}
@@ -0,0 +1,200 @@
+//! `+capture`: what a call writes to the process's stdout and stderr, read
+//! back for the client instead of interleaving with the server's own output.
+//!
+//! Both descriptors belong to the whole process, so they are pointed at
+//! unlinked temporary files for the duration of the call, one capturing call
+//! at a time. Files rather than pipes, so a function that prints more than a
+//! pipe holds cannot block on its own output.
+//!
+//! Anything else that writes to descriptors 1 or 2 meanwhile is captured
+//! too, such as a library called by another connection. The server's own
+//! output therefore goes through copies of the descriptors kept at startup
+//! ([`keep_server_output`]), which a capture leaves alone: use [`log`] and
+//! [`server_stdout`] rather than `eprintln!` and `println!` once connections
+//! are served.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+/// The server's own stdout and stderr, duplicated before any call ran.
+static SERVER_OUTPUT: OnceLock<(File, File)> = OnceLock::new();
+
+/// Keeps copies of stdout and stderr for the server's own output, so that it
+/// reaches them while a capturing call has the originals redirected. A
+/// stream that cannot be duplicated, such as a closed one, is written to
+/// directly.
+pub fn keep_server_output() {
+    #[cfg(unix)]
+    {
+        use std::os::fd::BorrowedFd;
+        let duplicate = |fd| -> io::Result<File> {
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+            Ok(File::from(fd))
+        };
+        if let (Ok(stdout), Ok(stderr)) = (
+            duplicate(libc::STDOUT_FILENO),
+            duplicate(libc::STDERR_FILENO),
+        ) {
+            SERVER_OUTPUT.set((stdout, stderr)).ok();
+        }
+    }
+}
+
+/// Writes `text` to the server's own stdout in one piece, so that output of
+/// different threads does not interleave within it.
+pub fn server_stdout(text: &str) -> io::Result<()> {
+    match SERVER_OUTPUT.get() {
+        Some((stdout, _)) => (&*stdout).write_all(text.as_bytes()),
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(text.as_bytes())?;
+            stdout.flush()
+        }
+    }
+}
+
+/// Writes `line` and a newline to the server's own stderr, as `eprintln!`
+/// would to stderr.
+pub fn log(line: fmt::Arguments) {
+    let text = format!("{}\n", line);
+    let _ = match SERVER_OUTPUT.get() {
+        Some((_, stderr)) => (&*stderr).write_all(text.as_bytes()),
+        None => io::stderr().write_all(text.as_bytes()),
+    };
+}
+
+/// The bytes a call wrote to each stream.
+pub struct Captured {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Runs `call` with stdout and stderr redirected, returning its result and
+/// what it wrote. Fails before calling if the redirection cannot be set up.
+#[cfg(unix)]
+pub fn capture<T>(call: impl FnOnce() -> T) -> io::Result<(T, Captured)> {
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::Mutex;
+
+    static CAPTURING: Mutex<()> = Mutex::new(());
+    let _capturing = CAPTURING.lock().unwrap_or_else(|e| e.into_inner());
+
+    // Whatever is already buffered was written before the call.
+    flush_all();
+    let mut stdout = temp_file()?;
+    let mut stderr = temp_file()?;
+    let result = {
+        let _stdout = Redirect::new(libc::STDOUT_FILENO, &stdout)?;
+        let _stderr = Redirect::new(libc::STDERR_FILENO, &stderr)?;
+        let result = call();
+        // C stdio buffers output to a file until it is flushed.
+        flush_all();
+        result
+    };
+
+    let mut captured = Captured {
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+    for (file, bytes) in [
+        (&mut stdout, &mut captured.stdout),
+        (&mut stderr, &mut captured.stderr),
+    ] {
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(bytes)?;
+    }
+    Ok((result, captured))
+}
+
+/// There are no Unix descriptors to redirect; nothing is called.
+#[cfg(not(unix))]
+pub fn capture<T>(_call: impl FnOnce() -> T) -> io::Result<(T, Captured)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "+capture is only supported on Unix",
+    ))
+}
+
+/// Flushes both Rust's and C's buffered output.
+#[cfg(unix)]
+fn flush_all() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    unsafe { libc::fflush(std::ptr::null_mut()) };
+}
+
+/// A new file that is already unlinked, so it vanishes once closed.
+#[cfg(unix)]
+fn temp_file() -> io::Result<File> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "dllbridge32-capture-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+/// Points `fd` at a file until dropped, then restores what it referred to.
+#[cfg(unix)]
+struct Redirect {
+    fd: libc::c_int,
+    saved: libc::c_int,
+}
+
+#[cfg(unix)]
+impl Redirect {
+    fn new(fd: libc::c_int, to: &File) -> io::Result<Redirect> {
+        use std::os::fd::AsRawFd;
+
+        let saved = unsafe { libc::dup(fd) };
+        if saved < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::dup2(to.as_raw_fd(), fd) } < 0 {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(saved) };
+            return Err(e);
+        }
+        Ok(Redirect { fd, saved })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Redirect {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dup2(self.saved, self.fd);
+            libc::close(self.saved);
+        }
+    }
+}
+
+/// Escapes captured text as one reply field: a backslash, a space and each
+/// control character become `\\`, `\xHH` or `\n`, `\r`, `\t`, the escapes
+/// `--delimiter` accepts.
+pub fn escape_field(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ' ' => out.push_str("\\x20"),
+            c if c.is_ascii_control() => out.push_str(&format!("\\x{:02x}", c as u8)),
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,616 @@
+use crate::affinity::CpuList;
+use crate::encoding::StringEncoding;
+use crate::fault::FaultPlan;
+use crate::invoke::VoidReply;
+use crate::listener::ListenSpec;
+use crate::privileges::PrivilegeDrop;
+use crate::rate_limit::RateLimit;
+use crate::registry::OpenFlags;
+use crate::signature::CallingConvention;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Server settings collected from the command line.
+#[derive(Debug)]
+pub struct Config {
+    pub dll_path: String,
+    /// Where to accept connections: the positional port on 127.0.0.1, unless
+    /// only `--listen` was given, followed by every `--listen`.
+    pub listen: Vec<ListenSpec>,
+    /// Upper bound on the number of arguments a single call may pass.
+    pub max_args: usize,
+    /// Longest `sig:` a request may send, in bytes once its tokens are joined.
+    pub max_signature_length: usize,
+    /// Whether clients may send library bodies with `upload`.
+    pub allow_upload: bool,
+    /// Directory uploads are written to; the system temp dir if unset.
+    pub temp_dir: Option<PathBuf>,
+    /// Encoding of `str` arguments and returns unless a call overrides it.
+    pub string_encoding: StringEncoding,
+    /// How long a client waits for a call before getting `E_TIMEOUT`; `None` waits forever.
+    pub call_timeout: Option<Duration>,
+    /// Largest per-call `timeout:<ms>` a client may request.
+    pub max_call_timeout: Duration,
+    /// Send a timed-out call's thread `SIGALRM` to interrupt the system call
+    /// it is blocked in.
+    pub interrupt_on_timeout: bool,
+    /// Cap on the bytes read from a returned string.
+    pub max_result_bytes: usize,
+    /// How many calls each connection's `history` keeps; 0 keeps none.
+    pub history_size: usize,
+    /// File of trusted `<function> = <signature>` declarations.
+    pub schema_path: Option<String>,
+    /// Identity and root directory to switch to after binding.
+    pub privileges: PrivilegeDrop,
+    /// Print a single parseable `EVENT ready ...` line instead of human status output.
+    pub machine: bool,
+    /// Convention used by signatures that do not name one.
+    pub default_convention: CallingConvention,
+    /// Where to append one access-log line per request; `-` is stdout.
+    pub access_log: Option<String>,
+    /// Size at which the access log file is rotated to `<path>.1`.
+    pub access_log_max_bytes: Option<u64>,
+    /// Send an unsolicited `PING` line after a connection has been idle this long.
+    pub keepalive: Option<Duration>,
+    /// Serve Prometheus metrics over HTTP on this port of 127.0.0.1.
+    pub metrics_port: Option<u16>,
+    /// Set `TCP_NODELAY` on accepted connections so small replies are not delayed.
+    pub nodelay: bool,
+    /// Set `SO_REUSEADDR` on the listener so a restart can rebind its port
+    /// while old connections linger in `TIME_WAIT`.
+    pub reuse_address: bool,
+    /// Environment variables set before the library is loaded, in order.
+    pub env: Vec<(String, String)>,
+    /// Process working directory, changed to before the library is loaded.
+    pub working_dir: Option<PathBuf>,
+    /// Most calls that may run in one library at a time; more get `E_LIB_BUSY`.
+    pub max_concurrent_calls_per_lib: Option<usize>,
+    /// How fast each connection may send requests; more get `E_RATE_LIMITED`.
+    pub rate_limit: Option<RateLimit>,
+    /// Whether `char` is signed; by default, as the host's C compilers have it.
+    pub char_signed: bool,
+    /// Coerce `int` and `float` arguments in the other type's syntax instead
+    /// of refusing them, noting each in the reply.
+    pub lenient_args: bool,
+    /// Serve a single connection, then exit.
+    pub once: bool,
+    /// Greet and prompt clients, answering in a human-friendly format.
+    pub interactive: bool,
+    /// Faults to inject into replies, for testing clients. Left out of the
+    /// usage text on purpose.
+    pub fault_inject: Option<FaultPlan>,
+    /// Whether clients may call raw code addresses with `callat`.
+    pub allow_raw_pointers: bool,
+    /// Let every client use `+trace`, not just those that sent `auth`.
+    pub allow_trace: bool,
+    /// Bytes that end each request and response line.
+    pub delimiter: Vec<u8>,
+    /// Bytes written before every reply line; empty for none.
+    pub response_prefix: Vec<u8>,
+    /// Bytes written after every reply line, before the delimiter.
+    pub response_suffix: Vec<u8>,
+    /// Validate calls without ever calling into the library.
+    pub no_call: bool,
+    /// Most iterations a single `bench` request may run.
+    pub max_bench_iterations: u64,
+    /// CPUs the accept loop and every handler thread are restricted to.
+    pub pin_cpu: Option<CpuList>,
+    /// Fall back to a case-insensitive match against the export table.
+    pub case_insensitive_symbols: bool,
+    /// Read signatures the client and schema omit from DWARF debug info.
+    pub debug_info: bool,
+    /// Resolve every symbol a library uses when it is loaded (`RTLD_NOW`)
+    /// instead of on first call.
+    pub bind_now: bool,
+    /// Make a library's symbols available to the libraries loaded after it
+    /// (`RTLD_GLOBAL`).
+    pub global_symbols: bool,
+    /// File whose first line is the token that unlocks `admin` commands.
+    pub admin_token_file: Option<String>,
+    /// What a successful `void` call replies.
+    pub void_reply: VoidReply,
+    /// Protocol version spoken to clients: 2 marks successful replies with
+    /// `OK`, 1 sends bare results as older servers did.
+    pub protocol: u32,
+}
+
+pub const USAGE: &str = "<path_to_dll> [port] [options]
+
+Options:
+  --max-args N            reject calls with more than N arguments (default 64)
+  --max-signature-length N  reject sig: signatures longer than N bytes (default 4096)
+  --allow-upload          enable the upload command
+  --temp-dir DIR          write uploaded libraries to DIR (default: system temp dir)
+  --string-encoding ENC   encoding of str values (utf-8, latin1, cp1252)
+  --call-timeout MS       give up waiting for a call after MS milliseconds
+  --max-call-timeout MS   largest per-call timeout:<ms> (default 60000)
+  --interrupt-on-timeout  send SIGALRM to a timed-out call's thread to break a blocking syscall (Unix)
+  --max-result-bytes N    longest string return to read (default 1048576)
+  --history-size N        calls kept per connection for history and replay (default 100)
+  --schema FILE           load trusted function signatures from FILE
+  --default-convention C  convention for signatures without one (default cdecl)
+  --user USER             switch to USER (name or uid) after binding
+  --group GROUP           switch to GROUP (name or gid) after binding
+  --chroot DIR            chroot into DIR after binding
+  --machine               print one parseable startup line instead of status text
+  --access-log FILE       append one line per request to FILE (- for stdout)
+  --access-log-max-bytes N  rotate the access log to FILE.1 beyond N bytes
+  --keepalive MS          send PING on connections idle for MS milliseconds
+  --metrics-port PORT     serve Prometheus metrics at http://127.0.0.1:PORT/metrics
+  --no-nodelay            leave Nagle's algorithm enabled on connections
+  --no-reuseaddr          bind without SO_REUSEADDR (Unix)
+  --max-concurrent-calls-per-lib N  refuse calls beyond N running in one library
+  --rate-limit N          allow each connection N requests per second
+  --rate-limit-burst N    let a connection send N requests back to back (default: the rate)
+  --listen SPEC           also listen on tcp://HOST:PORT or unix:///PATH (repeatable)
+  --char-signed, --char-unsigned  treat char as -128..127 or 0..255 (default: as the host's C)
+  --lenient-args          truncate decimals for int and accept integer literals for float arguments
+  --once                  serve one connection, then exit when it closes
+  --interactive           greet, prompt and label results for telnet/nc users
+  --allow-raw-pointers    let clients call code addresses with callat
+  --allow-trace           let clients use +trace without auth
+  --delimiter D           end requests and replies with D, e.g. '\\0' (default '\\n')
+  --response-prefix P     write P before every reply line, e.g. '\\x02'
+  --response-suffix S     write S after every reply line, before the delimiter
+  --no-call               dry run: validate calls and reply DRYRUN resolved instead
+  --max-bench-iterations N  most iterations one bench request may run (default 10000)
+  --env KEY=VALUE         set an environment variable before loading (repeatable)
+  --working-dir DIR       change to DIR before loading, for libraries using relative paths
+  --pin-cpu LIST          run all server threads on CPUs LIST, e.g. 0,2-3 (Linux)
+  --case-insensitive-symbols  match export names ignoring case if exact lookup fails
+  --debug-info            read missing signatures from the library's DWARF debug info
+  --bind-now              resolve all of a library's symbols when it is loaded (Unix)
+  --global-symbols        let libraries loaded later resolve against each library (Unix)
+  --admin-token-file FILE enable admin commands, unlocked by the token in FILE
+  --void-reply MODE       reply to void calls: ok (default), empty or void
+  --protocol V            2 (default) starts successful replies with OK; 1 sends bare results";
+
+impl Config {
+    /// How libraries are opened unless a request says otherwise.
+    pub fn open_flags(&self) -> OpenFlags {
+        OpenFlags {
+            bind_now: self.bind_now,
+            global: self.global_symbols,
+        }
+    }
+
+    /// The settings in effect as `(key, value)` pairs, in a fixed order and
+    /// with defaults filled in; `-` marks an unset option. Values of `--env`
+    /// variables are redacted, since they often carry credentials.
+    pub fn effective(&self) -> Vec<(&'static str, String)> {
+        fn or_unset<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "-".to_string(), |value| value.to_string())
+        }
+        fn millis(duration: Option<Duration>) -> String {
+            or_unset(duration.map(|duration| duration.as_millis()))
+        }
+        let listen: Vec<String> = self.listen.iter().map(ToString::to_string).collect();
+        let env: Vec<String> = self
+            .env
+            .iter()
+            .map(|(key, _)| format!("{}=<redacted>", key))
+            .collect();
+        vec![
+            ("dll", self.dll_path.clone()),
+            ("listen", listen.join(",")),
+            ("max_args", self.max_args.to_string()),
+            (
+                "max_signature_length",
+                self.max_signature_length.to_string(),
+            ),
+            ("allow_upload", self.allow_upload.to_string()),
+            (
+                "temp_dir",
+                or_unset(self.temp_dir.as_ref().map(|dir| dir.display())),
+            ),
+            ("string_encoding", self.string_encoding.name().to_string()),
+            ("call_timeout_ms", millis(self.call_timeout)),
+            ("max_call_timeout_ms", millis(Some(self.max_call_timeout))),
+            (
+                "interrupt_on_timeout",
+                self.interrupt_on_timeout.to_string(),
+            ),
+            ("max_result_bytes", self.max_result_bytes.to_string()),
+            ("history_size", self.history_size.to_string()),
+            ("schema", or_unset(self.schema_path.as_ref())),
+            ("user", or_unset(self.privileges.user.as_ref())),
+            ("group", or_unset(self.privileges.group.as_ref())),
+            ("chroot", or_unset(self.privileges.chroot.as_ref())),
+            ("machine", self.machine.to_string()),
+            ("default_convention", self.default_convention.to_string()),
+            ("access_log", or_unset(self.access_log.as_ref())),
+            ("access_log_max_bytes", or_unset(self.access_log_max_bytes)),
+            ("keepalive_ms", millis(self.keepalive)),
+            ("metrics_port", or_unset(self.metrics_port)),
+            ("nodelay", self.nodelay.to_string()),
+            ("reuse_address", self.reuse_address.to_string()),
+            (
+                "env",
+                if env.is_empty() {
+                    "-".into()
+                } else {
+                    env.join(",")
+                },
+            ),
+            (
+                "working_dir",
+                or_unset(self.working_dir.as_ref().map(|dir| dir.display())),
+            ),
+            (
+                "max_concurrent_calls_per_lib",
+                or_unset(self.max_concurrent_calls_per_lib),
+            ),
+            (
+                "rate_limit",
+                or_unset(self.rate_limit.map(|l| l.per_second)),
+            ),
+            (
+                "rate_limit_burst",
+                or_unset(self.rate_limit.map(|l| l.burst)),
+            ),
+            ("char_signed", self.char_signed.to_string()),
+            ("lenient_args", self.lenient_args.to_string()),
+            ("once", self.once.to_string()),
+            ("interactive", self.interactive.to_string()),
+            ("fault_inject", self.fault_inject.is_some().to_string()),
+            ("allow_raw_pointers", self.allow_raw_pointers.to_string()),
+            ("allow_trace", self.allow_trace.to_string()),
+            (
+                "delimiter",
+                String::from_utf8_lossy(&self.delimiter)
+                    .escape_debug()
+                    .to_string(),
+            ),
+            (
+                "response_prefix",
+                String::from_utf8_lossy(&self.response_prefix)
+                    .escape_debug()
+                    .to_string(),
+            ),
+            (
+                "response_suffix",
+                String::from_utf8_lossy(&self.response_suffix)
+                    .escape_debug()
+                    .to_string(),
+            ),
+            ("no_call", self.no_call.to_string()),
+            (
+                "max_bench_iterations",
+                self.max_bench_iterations.to_string(),
+            ),
+            ("pin_cpu", or_unset(self.pin_cpu.as_ref())),
+            (
+                "case_insensitive_symbols",
+                self.case_insensitive_symbols.to_string(),
+            ),
+            ("debug_info", self.debug_info.to_string()),
+            ("bind_now", self.bind_now.to_string()),
+            ("global_symbols", self.global_symbols.to_string()),
+            ("admin_token_file", or_unset(self.admin_token_file.as_ref())),
+            (
+                "void_reply",
+                match self.void_reply {
+                    VoidReply::Empty => "empty",
+                    VoidReply::Ok => "ok",
+                    VoidReply::Void => "void",
+                }
+                .to_string(),
+            ),
+            ("protocol", self.protocol.to_string()),
+        ]
+    }
+
+    /// Parses the process arguments (without the program name).
+    pub fn from_args<I>(args: I) -> Result<Config, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut positional = Vec::new();
+        let mut max_args = 64;
+        let mut max_signature_length = 4096;
+        let mut allow_upload = false;
+        let mut temp_dir = None;
+        let mut string_encoding = StringEncoding::Utf8;
+        let mut call_timeout = None;
+        let mut max_call_timeout = Duration::from_secs(60);
+        let mut interrupt_on_timeout = false;
+        let mut max_result_bytes = 1024 * 1024;
+        let mut history_size = 100;
+        let mut schema_path = None;
+        let mut privileges = PrivilegeDrop::default();
+        let mut machine = false;
+        let mut default_convention = CallingConvention::Cdecl;
+        let mut access_log = None;
+        let mut access_log_max_bytes = None;
+        let mut keepalive = None;
+        let mut metrics_port = None;
+        let mut nodelay = true;
+        let mut reuse_address = true;
+        let mut pin_cpu = None;
+        let mut env = Vec::new();
+        let mut working_dir = None;
+        let mut max_bench_iterations = 10_000;
+        let mut no_call = false;
+        let mut delimiter = b"\n".to_vec();
+        let mut response_prefix = Vec::new();
+        let mut response_suffix = Vec::new();
+        let mut max_concurrent_calls_per_lib = None;
+        let mut rate_limit: Option<f64> = None;
+        let mut rate_limit_burst: Option<f64> = None;
+        let mut allow_trace = false;
+        let mut allow_raw_pointers = false;
+        let mut once = false;
+        let mut char_signed = std::ffi::c_char::MIN != 0;
+        let mut lenient_args = false;
+        let mut interactive = false;
+        let mut fault_inject = None;
+        let mut listen = Vec::new();
+        let mut case_insensitive_symbols = false;
+        let mut debug_info = false;
+        let mut bind_now = false;
+        let mut global_symbols = false;
+        let mut admin_token_file = None;
+        let mut void_reply = VoidReply::Ok;
+        let mut protocol = 2;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--max-args" => max_args = parse_value(&arg, args.next())?,
+                "--max-signature-length" => {
+                    max_signature_length = parse_value(&arg, args.next())?;
+                    if max_signature_length == 0 {
+                        return Err("--max-signature-length must be at least 1".into());
+                    }
+                }
+                "--allow-upload" => allow_upload = true,
+                "--temp-dir" => temp_dir = Some(parse_value::<PathBuf>(&arg, args.next())?),
+                "--machine" => machine = true,
+                "--no-nodelay" => nodelay = false,
+                "--no-reuseaddr" => reuse_address = false,
+                "--allow-trace" => allow_trace = true,
+                "--allow-raw-pointers" => allow_raw_pointers = true,
+                "--once" => once = true,
+                "--char-signed" => char_signed = true,
+                "--char-unsigned" => char_signed = false,
+                "--lenient-args" => lenient_args = true,
+                "--interactive" => interactive = true,
+                "--fault-inject" => {
+                    let spec: String = parse_value(&arg, args.next())?;
+                    fault_inject = Some(spec.parse()?);
+                }
+                "--no-call" => no_call = true,
+                "--case-insensitive-symbols" => case_insensitive_symbols = true,
+                "--debug-info" => debug_info = true,
+                "--bind-now" => bind_now = true,
+                "--interrupt-on-timeout" => interrupt_on_timeout = true,
+                "--global-symbols" => global_symbols = true,
+                "--string-encoding" => string_encoding = parse_value(&arg, args.next())?,
+                "--call-timeout" => {
+                    let millis = parse_value(&arg, args.next())?;
+                    if millis == 0 {
+                        return Err("--call-timeout must be at least 1 ms".into());
+                    }
+                    call_timeout = Some(Duration::from_millis(millis));
+                }
+                "--max-result-bytes" => max_result_bytes = parse_value(&arg, args.next())?,
+                "--history-size" => history_size = parse_value(&arg, args.next())?,
+                "--default-convention" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    default_convention = value.parse()?;
+                }
+                "--schema" => schema_path = Some(parse_value(&arg, args.next())?),
+                "--access-log" => access_log = Some(parse_value(&arg, args.next())?),
+                "--access-log-max-bytes" => {
+                    access_log_max_bytes = Some(parse_value(&arg, args.next())?)
+                }
+                "--keepalive" => {
+                    let millis = parse_value(&arg, args.next())?;
+                    if millis == 0 {
+                        return Err("--keepalive must be at least 1 ms".into());
+                    }
+                    keepalive = Some(Duration::from_millis(millis));
+                }
+                "--metrics-port" => metrics_port = Some(parse_value(&arg, args.next())?),
+                "--max-concurrent-calls-per-lib" => {
+                    let max = parse_value(&arg, args.next())?;
+                    if max == 0 {
+                        return Err("--max-concurrent-calls-per-lib must be at least 1".into());
+                    }
+                    max_concurrent_calls_per_lib = Some(max);
+                }
+                "--rate-limit" => {
+                    let rate: f64 = parse_value(&arg, args.next())?;
+                    if !(rate > 0.0 && rate.is_finite()) {
+                        return Err("--rate-limit must be a positive number".into());
+                    }
+                    rate_limit = Some(rate);
+                }
+                "--rate-limit-burst" => {
+                    let burst: u32 = parse_value(&arg, args.next())?;
+                    if burst == 0 {
+                        return Err("--rate-limit-burst must be at least 1".into());
+                    }
+                    rate_limit_burst = Some(burst.into());
+                }
+                "--listen" => listen.push(parse_value(&arg, args.next())?),
+                "--delimiter" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    delimiter = parse_bytes(&arg, &value)?;
+                }
+                "--response-prefix" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    response_prefix = parse_bytes(&arg, &value)?;
+                }
+                "--response-suffix" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    response_suffix = parse_bytes(&arg, &value)?;
+                }
+                "--max-bench-iterations" => max_bench_iterations = parse_value(&arg, args.next())?,
+                "--env" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    match value.split_once('=') {
+                        Some((key, value))
+                            if !key.is_empty() && !key.contains('\0') && !value.contains('\0') =>
+                        {
+                            env.push((key.to_string(), value.to_string()))
+                        }
+                        _ => return Err(format!("--env expects KEY=VALUE, got {}", value)),
+                    }
+                }
+                "--working-dir" => working_dir = Some(parse_value::<PathBuf>(&arg, args.next())?),
+                "--pin-cpu" => pin_cpu = Some(parse_value(&arg, args.next())?),
+                "--admin-token-file" => admin_token_file = Some(parse_value(&arg, args.next())?),
+                "--protocol" => {
+                    protocol = parse_value(&arg, args.next())?;
+                    if !(1..=2).contains(&protocol) {
+                        return Err(format!("Unsupported protocol version: {}", protocol));
+                    }
+                }
+                "--void-reply" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    void_reply = value.parse()?;
+                }
+                "--user" => privileges.user = Some(parse_value(&arg, args.next())?),
+                "--group" => privileges.group = Some(parse_value(&arg, args.next())?),
+                "--chroot" => privileges.chroot = Some(parse_value(&arg, args.next())?),
+                "--max-call-timeout" => {
+                    let millis = parse_value(&arg, args.next())?;
+                    if millis == 0 {
+                        return Err("--max-call-timeout must be at least 1 ms".into());
+                    }
+                    max_call_timeout = Duration::from_millis(millis);
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(format!("Unknown option: {}", flag));
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let dll_path = positional.next().ok_or("Missing path to DLL")?;
+        let port = positional.next();
+        if let Some(extra) = positional.next() {
+            return Err(format!("Unexpected argument: {}", extra));
+        }
+        // A marker containing the delimiter would end the frame it marks.
+        for (flag, marker) in [
+            ("--response-prefix", &response_prefix),
+            ("--response-suffix", &response_suffix),
+        ] {
+            if marker
+                .windows(delimiter.len())
+                .any(|window| window == delimiter)
+            {
+                return Err(format!("{} must not contain the delimiter", flag));
+            }
+        }
+        let rate_limit = match (rate_limit, rate_limit_burst) {
+            (Some(per_second), burst) => Some(RateLimit {
+                per_second,
+                burst: burst.unwrap_or(per_second.ceil()),
+            }),
+            (None, Some(_)) => return Err("--rate-limit-burst requires --rate-limit".into()),
+            (None, None) => None,
+        };
+        if port.is_some() || listen.is_empty() {
+            let port = port.as_deref().unwrap_or("5000");
+            listen.insert(0, ListenSpec::Tcp(format!("127.0.0.1:{}", port)));
+        }
+
+        Ok(Config {
+            dll_path,
+            listen,
+            max_args,
+            max_signature_length,
+            allow_upload,
+            temp_dir,
+            string_encoding,
+            call_timeout,
+            max_call_timeout,
+            interrupt_on_timeout,
+            max_result_bytes,
+            history_size,
+            schema_path,
+            privileges,
+            machine,
+            default_convention,
+            access_log,
+            access_log_max_bytes,
+            keepalive,
+            metrics_port,
+            nodelay,
+            reuse_address,
+            env,
+            working_dir,
+            max_concurrent_calls_per_lib,
+            rate_limit,
+            char_signed,
+            lenient_args,
+            once,
+            interactive,
+            fault_inject,
+            allow_raw_pointers,
+            allow_trace,
+            delimiter,
+            response_prefix,
+            response_suffix,
+            no_call,
+            max_bench_iterations,
+            pin_cpu,
+            case_insensitive_symbols,
+            debug_info,
+            bind_now,
+            global_symbols,
+            admin_token_file,
+            void_reply,
+            protocol,
+        })
+    }
+}
+
+/// Decodes the value of `flag` (`--delimiter`, `--response-prefix` or
+/// `--response-suffix`), which may spell bytes as `\0`, `\n`, `\r`,
+/// `\t`, `\\` or `\xHH`.
+fn parse_bytes(flag: &str, text: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("Invalid value for {}: {}", flag, text);
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let (&escape, tail) = rest.split_first().ok_or_else(invalid)?;
+        rest = tail;
+        bytes.push(match escape {
+            b'0' => 0,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'\\' => b'\\',
+            b'x' => {
+                let hex = rest.get(..2).ok_or_else(invalid)?;
+                rest = &rest[2..];
+                std::str::from_utf8(hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(invalid)?
+            }
+            _ => return Err(invalid()),
+        });
+    }
+    if bytes.is_empty() {
+        return Err(format!("{} must not be empty", flag));
+    }
+    Ok(bytes)
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
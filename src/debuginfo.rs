@@ -0,0 +1,675 @@
+//! Reads function prototypes from a library's DWARF debug info, so functions
+//! can be called without a `sig:` when the library was built with `-g`.
+//!
+//! Only what is needed for prototypes is decoded: the `.debug_info` tree of
+//! compile units (DWARF 2 to 5), with names from `.debug_str`,
+//! `.debug_line_str` and `.debug_str_offsets`. Parameter and return types are
+//! mapped onto the supported types: 4-byte integers and enums to `int`,
+//! 4-byte floats to `float`, 1-byte integers to `char`, pointers to `char` to
+//! `str` and other pointers to `ptr`. Functions using any other type, or
+//! taking variable arguments, are left out.
+
+use crate::exports;
+use crate::signature::SupportedType;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Parameter and return types of a function, as declared in the debug info.
+#[derive(Debug, Clone)]
+pub struct Prototype {
+    pub param_types: Vec<SupportedType>,
+    pub return_type: SupportedType,
+}
+
+/// Prototypes of the external functions described by the debug info of the
+/// library at `path`, keyed by their symbol name.
+pub fn prototypes(path: &Path) -> Result<HashMap<String, Prototype>, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if !data.starts_with(b"\x7fELF") {
+        return Err("Debug info can only be read from ELF (DWARF) libraries".into());
+    }
+    let section = |name| exports::elf_section(&data, name).map(Option::unwrap_or_default);
+    let dwarf = Dwarf {
+        info: section(".debug_info")?,
+        abbrev: section(".debug_abbrev")?,
+        str: section(".debug_str")?,
+        line_str: section(".debug_line_str")?,
+        str_offsets: section(".debug_str_offsets")?,
+        big_endian: exports::elf_is_big_endian(&data),
+    };
+    if dwarf.info.is_empty() {
+        return Err(format!("{} has no DWARF debug info", path.display()));
+    }
+    dwarf.prototypes()
+}
+
+fn malformed() -> String {
+    "Malformed DWARF debug info".into()
+}
+
+const DW_TAG_ENUMERATION_TYPE: u64 = 0x04;
+const DW_TAG_FORMAL_PARAMETER: u64 = 0x05;
+const DW_TAG_POINTER_TYPE: u64 = 0x0f;
+const DW_TAG_TYPEDEF: u64 = 0x16;
+const DW_TAG_UNSPECIFIED_PARAMETERS: u64 = 0x18;
+const DW_TAG_BASE_TYPE: u64 = 0x24;
+const DW_TAG_CONST_TYPE: u64 = 0x26;
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+const DW_TAG_VOLATILE_TYPE: u64 = 0x35;
+const DW_TAG_RESTRICT_TYPE: u64 = 0x37;
+const DW_TAG_ATOMIC_TYPE: u64 = 0x47;
+
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_BYTE_SIZE: u64 = 0x0b;
+const DW_AT_ABSTRACT_ORIGIN: u64 = 0x31;
+const DW_AT_DECLARATION: u64 = 0x3c;
+const DW_AT_ENCODING: u64 = 0x3e;
+const DW_AT_EXTERNAL: u64 = 0x3f;
+const DW_AT_SPECIFICATION: u64 = 0x47;
+const DW_AT_TYPE: u64 = 0x49;
+const DW_AT_LINKAGE_NAME: u64 = 0x6e;
+const DW_AT_STR_OFFSETS_BASE: u64 = 0x72;
+
+const DW_ATE_FLOAT: u64 = 0x04;
+const DW_ATE_SIGNED: u64 = 0x05;
+const DW_ATE_SIGNED_CHAR: u64 = 0x06;
+const DW_ATE_UNSIGNED: u64 = 0x07;
+const DW_ATE_UNSIGNED_CHAR: u64 = 0x08;
+
+struct Dwarf<'a> {
+    info: &'a [u8],
+    abbrev: &'a [u8],
+    str: &'a [u8],
+    line_str: &'a [u8],
+    str_offsets: &'a [u8],
+    big_endian: bool,
+}
+
+/// A bounds-checked position in a DWARF section.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8], pos: usize, big_endian: bool) -> Self {
+        Cursor {
+            data,
+            pos,
+            big_endian,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).ok_or_else(malformed)?;
+        let bytes = self.data.get(self.pos..end).ok_or_else(malformed)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// An unsigned integer of `len` (at most 8) bytes.
+    fn uint(&mut self, len: usize) -> Result<u64, String> {
+        let bytes = self.take(len)?;
+        let fold = |acc: u64, &b: &u8| (acc << 8) | b as u64;
+        Ok(if self.big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    }
+
+    fn uleb(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.take(1)?[0];
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Result<i64, String> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.take(1)?[0];
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn c_str(&mut self) -> Result<String, String> {
+        let rest = self.data.get(self.pos..).ok_or_else(malformed)?;
+        let len = rest.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+        self.pos += len + 1;
+        Ok(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+}
+
+fn usize_of(value: u64) -> Result<usize, String> {
+    usize::try_from(value).map_err(|_| malformed())
+}
+
+/// An attribute specification in an abbreviation.
+struct AttributeSpec {
+    name: u64,
+    form: u64,
+    implicit_const: i64,
+}
+
+struct Abbreviation {
+    tag: u64,
+    has_children: bool,
+    attributes: Vec<AttributeSpec>,
+}
+
+/// The attribute values this reader keeps.
+#[derive(Debug, Clone)]
+enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    String(String),
+    /// An index into `.debug_str_offsets`, resolved against the unit's base.
+    StringIndex(u64),
+    /// The absolute `.debug_info` offset of another entry.
+    Reference(usize),
+    Flag(bool),
+    Other,
+}
+
+impl Value {
+    fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Unsigned(v) => Some(v),
+            Value::Signed(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A debugging information entry.
+struct Entry {
+    tag: u64,
+    attributes: Vec<(u64, Value)>,
+    children: Vec<usize>,
+}
+
+impl Entry {
+    fn attribute(&self, name: u64) -> Option<&Value> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v)
+    }
+}
+
+/// The entries of one compile unit, keyed by their `.debug_info` offset.
+struct Unit {
+    entries: HashMap<usize, Entry>,
+    /// Top-level entries, children of the compile unit entry.
+    roots: Vec<usize>,
+    str_offsets_base: usize,
+    offset_size: usize,
+}
+
+impl<'a> Dwarf<'a> {
+    fn cursor(&self, data: &'a [u8], pos: usize) -> Cursor<'a> {
+        Cursor::new(data, pos, self.big_endian)
+    }
+
+    fn prototypes(&self) -> Result<HashMap<String, Prototype>, String> {
+        let mut prototypes = HashMap::new();
+        let mut pos = 0;
+        while pos < self.info.len() {
+            let (unit, next) = self.parse_unit(pos)?;
+            pos = next;
+            if let Some(unit) = unit {
+                self.collect_prototypes(&unit, &mut prototypes)?;
+            }
+        }
+        Ok(prototypes)
+    }
+
+    /// Parses the unit at `start`, returning it (or `None` for unit kinds
+    /// without functions) and the offset of the next unit.
+    fn parse_unit(&self, start: usize) -> Result<(Option<Unit>, usize), String> {
+        let mut c = self.cursor(self.info, start);
+        let mut length = c.uint(4)?;
+        let offset_size = if length == 0xffff_ffff {
+            length = c.uint(8)?;
+            8
+        } else {
+            4
+        };
+        let end = c.pos.checked_add(usize_of(length)?).ok_or_else(malformed)?;
+        if end > self.info.len() {
+            return Err(malformed());
+        }
+        let version = c.uint(2)?;
+        let (abbrev_offset, address_size) = match version {
+            2..=4 => {
+                let abbrev = c.uint(offset_size)?;
+                (abbrev, c.uint(1)?)
+            }
+            5 => {
+                let unit_type = c.uint(1)?;
+                let address_size = c.uint(1)?;
+                let abbrev = c.uint(offset_size)?;
+                // Only full and partial compile units describe functions here.
+                if !matches!(unit_type, 0x01 | 0x03) {
+                    return Ok((None, end));
+                }
+                (abbrev, address_size)
+            }
+            _ => return Ok((None, end)),
+        };
+        let abbreviations = self.parse_abbreviations(usize_of(abbrev_offset)?)?;
+
+        let mut unit = Unit {
+            entries: HashMap::new(),
+            roots: Vec::new(),
+            // DWARF 5 puts the first offset right after the 8-byte table header.
+            str_offsets_base: 8,
+            offset_size,
+        };
+        let context = FormContext {
+            unit_start: start,
+            version,
+            address_size: usize_of(address_size)?,
+            offset_size,
+        };
+        // Entries whose children are being read, innermost last.
+        let mut parents: Vec<usize> = Vec::new();
+        let mut c = Cursor::new(&self.info[..end], c.pos, self.big_endian);
+        while c.pos < end {
+            let offset = c.pos;
+            let code = c.uleb()?;
+            if code == 0 {
+                parents.pop();
+                continue;
+            }
+            let abbreviation = abbreviations.get(&code).ok_or_else(malformed)?;
+            let attributes = abbreviation
+                .attributes
+                .iter()
+                .map(|spec| {
+                    Ok((
+                        spec.name,
+                        self.read_value(&mut c, spec, spec.form, &context)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            match parents.last() {
+                Some(&parent) => {
+                    if let Some(parent) = unit.entries.get_mut(&parent) {
+                        parent.children.push(offset);
+                    }
+                    if parents.len() == 1 {
+                        unit.roots.push(offset);
+                    }
+                }
+                // The unit's own entry.
+                None => {
+                    if let Some(base) = attributes
+                        .iter()
+                        .find(|(n, _)| *n == DW_AT_STR_OFFSETS_BASE)
+                        .and_then(|(_, v)| v.as_u64())
+                    {
+                        unit.str_offsets_base = usize_of(base)?;
+                    }
+                }
+            }
+            unit.entries.insert(
+                offset,
+                Entry {
+                    tag: abbreviation.tag,
+                    attributes,
+                    children: Vec::new(),
+                },
+            );
+            if abbreviation.has_children {
+                parents.push(offset);
+            }
+        }
+        Ok((Some(unit), end))
+    }
+
+    fn parse_abbreviations(&self, offset: usize) -> Result<HashMap<u64, Abbreviation>, String> {
+        let mut c = self.cursor(self.abbrev, offset);
+        let mut abbreviations = HashMap::new();
+        loop {
+            let code = c.uleb()?;
+            if code == 0 {
+                return Ok(abbreviations);
+            }
+            let tag = c.uleb()?;
+            let has_children = c.uint(1)? != 0;
+            let mut attributes = Vec::new();
+            loop {
+                let name = c.uleb()?;
+                let form = c.uleb()?;
+                if name == 0 && form == 0 {
+                    break;
+                }
+                let implicit_const = if form == 0x21 { c.sleb()? } else { 0 };
+                attributes.push(AttributeSpec {
+                    name,
+                    form,
+                    implicit_const,
+                });
+            }
+            abbreviations.insert(
+                code,
+                Abbreviation {
+                    tag,
+                    has_children,
+                    attributes,
+                },
+            );
+        }
+    }
+
+    /// Reads (or skips) one attribute value of the given form.
+    fn read_value(
+        &self,
+        c: &mut Cursor<'a>,
+        spec: &AttributeSpec,
+        form: u64,
+        context: &FormContext,
+    ) -> Result<Value, String> {
+        let offset = context.offset_size;
+        let unit_ref = |value: u64| {
+            usize_of(value)?
+                .checked_add(context.unit_start)
+                .map(Value::Reference)
+                .ok_or_else(malformed)
+        };
+        Ok(match form {
+            0x01 => Value::Unsigned(c.uint(context.address_size)?),
+            0x03 => {
+                let len = c.uint(2)?;
+                skip(c, len)?
+            }
+            0x04 => {
+                let len = c.uint(4)?;
+                skip(c, len)?
+            }
+            0x05 => Value::Unsigned(c.uint(2)?),
+            0x06 => Value::Unsigned(c.uint(4)?),
+            0x07 => Value::Unsigned(c.uint(8)?),
+            0x08 => Value::String(c.c_str()?),
+            0x09 | 0x18 => {
+                let len = c.uleb()?;
+                skip(c, len)?
+            }
+            0x0a => {
+                let len = c.uint(1)?;
+                skip(c, len)?
+            }
+            0x0b => Value::Unsigned(c.uint(1)?),
+            0x0c => Value::Flag(c.uint(1)? != 0),
+            0x0d => Value::Signed(c.sleb()?),
+            0x0e => self.string_at(self.str, c.uint(offset)?)?,
+            0x0f => Value::Unsigned(c.uleb()?),
+            0x10 => {
+                // DWARF 2 sized `ref_addr` like an address.
+                let size = if context.version == 2 {
+                    context.address_size
+                } else {
+                    offset
+                };
+                Value::Reference(usize_of(c.uint(size)?)?)
+            }
+            0x11 => unit_ref(c.uint(1)?)?,
+            0x12 => unit_ref(c.uint(2)?)?,
+            0x13 => unit_ref(c.uint(4)?)?,
+            0x14 => unit_ref(c.uint(8)?)?,
+            0x15 => unit_ref(c.uleb()?)?,
+            0x16 => {
+                let form = c.uleb()?;
+                self.read_value(c, spec, form, context)?
+            }
+            0x17 | 0x1d => skip(c, offset as u64)?,
+            0x19 => Value::Flag(true),
+            0x1a => Value::StringIndex(c.uleb()?),
+            0x1b | 0x22 | 0x23 => skip_uleb(c)?,
+            0x1c => skip(c, 4)?,
+            0x1e => skip(c, 16)?,
+            0x1f => self.string_at(self.line_str, c.uint(offset)?)?,
+            0x20 | 0x24 => skip(c, 8)?,
+            0x21 => Value::Signed(spec.implicit_const),
+            0x25 => Value::StringIndex(c.uint(1)?),
+            0x26 => Value::StringIndex(c.uint(2)?),
+            0x27 => Value::StringIndex(c.uint(3)?),
+            0x28 => Value::StringIndex(c.uint(4)?),
+            0x29 => skip(c, 1)?,
+            0x2a => skip(c, 2)?,
+            0x2b => skip(c, 3)?,
+            0x2c => skip(c, 4)?,
+            _ => return Err(format!("Unsupported DWARF form 0x{:x}", form)),
+        })
+    }
+
+    fn string_at(&self, section: &'a [u8], offset: u64) -> Result<Value, String> {
+        self.cursor(section, usize_of(offset)?)
+            .c_str()
+            .map(Value::String)
+    }
+
+    /// The name of `entry`, preferring the linkage (symbol) name.
+    fn name(&self, unit: &Unit, entry: &Entry) -> Result<Option<String>, String> {
+        let value = entry
+            .attribute(DW_AT_LINKAGE_NAME)
+            .or_else(|| entry.attribute(DW_AT_NAME));
+        match value {
+            Some(Value::String(name)) => Ok(Some(name.clone())),
+            Some(Value::StringIndex(index)) => {
+                let position = unit
+                    .str_offsets_base
+                    .checked_add(usize_of(*index)?.saturating_mul(unit.offset_size))
+                    .ok_or_else(malformed)?;
+                let offset = self
+                    .cursor(self.str_offsets, position)
+                    .uint(unit.offset_size)?;
+                match self.string_at(self.str, offset)? {
+                    Value::String(name) => Ok(Some(name)),
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn collect_prototypes(
+        &self,
+        unit: &Unit,
+        prototypes: &mut HashMap<String, Prototype>,
+    ) -> Result<(), String> {
+        for offset in &unit.roots {
+            let Some(entry) = unit.entries.get(offset) else {
+                continue;
+            };
+            if entry.tag != DW_TAG_SUBPROGRAM
+                || matches!(entry.attribute(DW_AT_DECLARATION), Some(Value::Flag(true)))
+            {
+                continue;
+            }
+            // Out-of-line instances and definitions of declared functions
+            // keep their name, type and linkage on the entry they refer to.
+            let origin = [DW_AT_ABSTRACT_ORIGIN, DW_AT_SPECIFICATION]
+                .iter()
+                .find_map(|&a| match entry.attribute(a) {
+                    Some(Value::Reference(target)) => unit.entries.get(target),
+                    _ => None,
+                });
+            let described = |name: u64| {
+                entry
+                    .attribute(name)
+                    .or_else(|| origin.and_then(|o| o.attribute(name)))
+            };
+            if !matches!(described(DW_AT_EXTERNAL), Some(Value::Flag(true))) {
+                continue;
+            }
+            let name = match self.name(unit, entry)? {
+                Some(name) => Some(name),
+                None => origin.map(|o| self.name(unit, o)).transpose()?.flatten(),
+            };
+            let Some(name) = name else {
+                continue;
+            };
+            if let Some(prototype) = self.prototype(unit, entry, origin, described(DW_AT_TYPE)) {
+                prototypes.insert(name, prototype);
+            }
+        }
+        Ok(())
+    }
+
+    /// The prototype of a function entry, or `None` if it uses a type that
+    /// cannot be mapped.
+    fn prototype(
+        &self,
+        unit: &Unit,
+        entry: &Entry,
+        origin: Option<&Entry>,
+        return_type: Option<&Value>,
+    ) -> Option<Prototype> {
+        let children = |e: &Entry| -> Vec<&Entry> {
+            e.children
+                .iter()
+                .filter_map(|c| unit.entries.get(c))
+                .filter(|c| {
+                    matches!(
+                        c.tag,
+                        DW_TAG_FORMAL_PARAMETER | DW_TAG_UNSPECIFIED_PARAMETERS
+                    )
+                })
+                .collect()
+        };
+        let mut params = children(entry);
+        if params.is_empty()
+            && let Some(origin) = origin
+        {
+            params = children(origin);
+        }
+        let param_types = params
+            .iter()
+            .map(|param| {
+                if param.tag == DW_TAG_UNSPECIFIED_PARAMETERS {
+                    return None;
+                }
+                let ty = param.attribute(DW_AT_TYPE).or_else(|| {
+                    match param.attribute(DW_AT_ABSTRACT_ORIGIN) {
+                        Some(Value::Reference(target)) => {
+                            unit.entries.get(target)?.attribute(DW_AT_TYPE)
+                        }
+                        _ => None,
+                    }
+                })?;
+                self.map_type(unit, ty, 0)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let return_type = match return_type {
+            Some(ty) => self.map_type(unit, ty, 0)?,
+            None => SupportedType::Void,
+        };
+        Some(Prototype {
+            param_types,
+            return_type,
+        })
+    }
+
+    /// Maps the type entry `value` refers to onto a supported type.
+    fn map_type(&self, unit: &Unit, value: &Value, depth: usize) -> Option<SupportedType> {
+        let entry = self.type_entry(unit, value, depth)?;
+        match entry.tag {
+            DW_TAG_BASE_TYPE => {
+                let encoding = entry.attribute(DW_AT_ENCODING)?.as_u64()?;
+                let size = entry.attribute(DW_AT_BYTE_SIZE)?.as_u64()?;
+                match (encoding, size) {
+                    (DW_ATE_FLOAT, 4) => Some(SupportedType::Float),
+                    (DW_ATE_SIGNED | DW_ATE_UNSIGNED, 4) => Some(SupportedType::Int),
+                    (
+                        DW_ATE_SIGNED | DW_ATE_UNSIGNED | DW_ATE_SIGNED_CHAR | DW_ATE_UNSIGNED_CHAR,
+                        1,
+                    ) => Some(SupportedType::Char),
+                    _ => None,
+                }
+            }
+            DW_TAG_ENUMERATION_TYPE => {
+                (entry.attribute(DW_AT_BYTE_SIZE)?.as_u64()? == 4).then_some(SupportedType::Int)
+            }
+            DW_TAG_POINTER_TYPE => {
+                let pointee = entry
+                    .attribute(DW_AT_TYPE)
+                    .and_then(|ty| self.type_entry(unit, ty, depth + 1));
+                let is_char = pointee.is_some_and(|p| {
+                    p.tag == DW_TAG_BASE_TYPE
+                        && matches!(
+                            p.attribute(DW_AT_ENCODING).and_then(Value::as_u64),
+                            Some(DW_ATE_SIGNED_CHAR | DW_ATE_UNSIGNED_CHAR)
+                        )
+                });
+                Some(if is_char {
+                    SupportedType::Str
+                } else {
+                    SupportedType::Ptr
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The entry `value` refers to, looking through typedefs and qualifiers.
+    fn type_entry<'u>(&self, unit: &'u Unit, value: &Value, depth: usize) -> Option<&'u Entry> {
+        // Guards against reference cycles in corrupt input.
+        if depth > 32 {
+            return None;
+        }
+        let Value::Reference(offset) = value else {
+            return None;
+        };
+        let entry = unit.entries.get(offset)?;
+        match entry.tag {
+            DW_TAG_TYPEDEF | DW_TAG_CONST_TYPE | DW_TAG_VOLATILE_TYPE | DW_TAG_RESTRICT_TYPE
+            | DW_TAG_ATOMIC_TYPE => {
+                // A qualifier without a type qualifies `void`.
+                let inner = entry.attribute(DW_AT_TYPE)?;
+                self.type_entry(unit, inner, depth + 1)
+            }
+            _ => Some(entry),
+        }
+    }
+}
+
+/// Unit properties that decide how forms are sized.
+struct FormContext {
+    unit_start: usize,
+    version: u64,
+    address_size: usize,
+    offset_size: usize,
+}
+
+fn skip(c: &mut Cursor, len: u64) -> Result<Value, String> {
+    c.take(usize_of(len)?)?;
+    Ok(Value::Other)
+}
+
+fn skip_uleb(c: &mut Cursor) -> Result<Value, String> {
+    c.uleb()?;
+    Ok(Value::Other)
+}
@@ -0,0 +1,187 @@
+//! Demangles the Itanium C++ ABI names GCC and Clang give free functions,
+//! e.g. `_ZN4math3addEii` to `math::add(int, int)`, printed the way
+//! `c++filt` prints them. Only what such names need is understood:
+//! namespaces, class names, builtin types, pointers, references, `const`,
+//! `volatile` and substitutions. Templates, member functions and every other
+//! construct make [`demangle`] return `None`.
+
+/// The demangled prototype of `symbol`, or `None` if it is not a mangled
+/// name of the supported kind.
+pub fn demangle(symbol: &str) -> Option<String> {
+    let mut parser = Parser {
+        input: symbol.strip_prefix("_Z")?.as_bytes(),
+        pos: 0,
+        substitutions: Vec::new(),
+    };
+    let name = parser.name(false)?;
+    let params = parser.params()?;
+    Some(format!("{}({})", name, params))
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// Names and types a later `S_`/`S<n>_` may refer back to, in order.
+    substitutions: Vec<String>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// A function name or, with `is_type`, a class name, which unlike a
+    /// function name may itself be referred back to.
+    fn name(&mut self, is_type: bool) -> Option<String> {
+        let (name, substituted) = if self.eat(b'N') {
+            self.nested_name()?
+        } else if self.input[self.pos..].starts_with(b"St") {
+            self.pos += 2;
+            (format!("std::{}", self.source_name()?), false)
+        } else {
+            (self.source_name()?, false)
+        };
+        if is_type && !substituted {
+            self.substitutions.push(name.clone());
+        }
+        Some(name)
+    }
+
+    /// `N <component>... E`, after the `N`; also whether the whole name is a
+    /// substitution.
+    fn nested_name(&mut self) -> Option<(String, bool)> {
+        let mut prefix: Option<(String, bool)> = None;
+        while !self.eat(b'E') {
+            // Every prefix extended by another component can be referred back to.
+            if let Some((prefix, false)) = &prefix {
+                self.substitutions.push(prefix.clone());
+            }
+            let (component, substituted) = if self.peek() == Some(b'S') {
+                self.substitution()?
+            } else {
+                (self.source_name()?, false)
+            };
+            prefix = Some(match prefix {
+                Some((prefix, _)) => (format!("{}::{}", prefix, component), false),
+                None => (component, substituted),
+            });
+        }
+        prefix
+    }
+
+    /// `<length><identifier>`.
+    fn source_name(&mut self) -> Option<String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|byte| byte.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let length: usize = std::str::from_utf8(&self.input[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()?;
+        let end = self.pos.checked_add(length)?;
+        let identifier = std::str::from_utf8(self.input.get(self.pos..end)?).ok()?;
+        self.pos = end;
+        Some(identifier.to_string())
+    }
+
+    /// `St`, whose `std` is never referred back to, or `S_`, `S0_`, `S1_`...,
+    /// the substitutions in order with a base-36 index after the first.
+    fn substitution(&mut self) -> Option<(String, bool)> {
+        if !self.eat(b'S') {
+            return None;
+        }
+        if self.eat(b't') {
+            return Some(("std".to_string(), true));
+        }
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|byte| byte.is_ascii_digit() || byte.is_ascii_uppercase())
+        {
+            self.pos += 1;
+        }
+        let digits = std::str::from_utf8(&self.input[start..self.pos]).ok()?;
+        if !self.eat(b'_') {
+            return None;
+        }
+        let index = match digits {
+            "" => 0,
+            digits => usize::from_str_radix(digits, 36).ok()? + 1,
+        };
+        Some((self.substitutions.get(index)?.clone(), true))
+    }
+
+    /// The parameter types up to the end of the name; `v` alone is none.
+    fn params(&mut self) -> Option<String> {
+        if &self.input[self.pos..] == b"v" {
+            return Some(String::new());
+        }
+        let mut params = Vec::new();
+        while self.pos < self.input.len() {
+            params.push(self.parameter_type()?);
+        }
+        (!params.is_empty()).then(|| params.join(", "))
+    }
+
+    fn parameter_type(&mut self) -> Option<String> {
+        let byte = self.peek()?;
+        if let Some(builtin) = builtin(byte) {
+            self.pos += 1;
+            return Some(builtin.to_string());
+        }
+        let (suffix, inner) = match byte {
+            b'P' => ("*", true),
+            b'R' => ("&", true),
+            b'O' => ("&&", true),
+            b'K' => (" const", true),
+            b'V' => (" volatile", true),
+            _ => ("", false),
+        };
+        if inner {
+            self.pos += 1;
+            let ty = format!("{}{}", self.parameter_type()?, suffix);
+            self.substitutions.push(ty.clone());
+            return Some(ty);
+        }
+        if byte == b'S' && self.input.get(self.pos + 1) != Some(&b't') {
+            return self.substitution().map(|(ty, _)| ty);
+        }
+        self.name(true)
+    }
+}
+
+fn builtin(code: u8) -> Option<&'static str> {
+    Some(match code {
+        b'v' => "void",
+        b'w' => "wchar_t",
+        b'b' => "bool",
+        b'c' => "char",
+        b'a' => "signed char",
+        b'h' => "unsigned char",
+        b's' => "short",
+        b't' => "unsigned short",
+        b'i' => "int",
+        b'j' => "unsigned int",
+        b'l' => "long",
+        b'm' => "unsigned long",
+        b'x' => "long long",
+        b'y' => "unsigned long long",
+        b'n' => "__int128",
+        b'o' => "unsigned __int128",
+        b'f' => "float",
+        b'd' => "double",
+        b'e' => "long double",
+        b'g' => "__float128",
+        b'z' => "...",
+        _ => return None,
+    })
+}
@@ -0,0 +1,114 @@
+use std::str::FromStr;
+
+/// Character encodings used to marshal `str` arguments and returns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StringEncoding {
+    Utf8,
+    /// ISO-8859-1: every byte maps to the code point of the same value.
+    Latin1,
+    /// Windows-1252, the usual ANSI codepage of western-European Windows.
+    Cp1252,
+}
+
+/// Canonical names of the supported encodings.
+pub const ENCODING_NAMES: &[&str] = &["utf-8", "latin1", "cp1252"];
+
+impl StringEncoding {
+    /// The encoding's canonical name from [`ENCODING_NAMES`].
+    pub fn name(self) -> &'static str {
+        ENCODING_NAMES[self as usize]
+    }
+}
+
+impl FromStr for StringEncoding {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utf-8" | "utf8" | "65001" => Ok(StringEncoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" | "28591" => Ok(StringEncoding::Latin1),
+            "cp1252" | "windows-1252" | "1252" => Ok(StringEncoding::Cp1252),
+            _ => Err(format!("Unsupported string encoding: {}", s)),
+        }
+    }
+}
+
+/// Code points of Windows-1252 bytes 0x80..=0x9F; `None` marks undefined bytes.
+const CP1252_HIGH: [Option<char>; 32] = [
+    Some('\u{20AC}'),
+    None,
+    Some('\u{201A}'),
+    Some('\u{0192}'),
+    Some('\u{201E}'),
+    Some('\u{2026}'),
+    Some('\u{2020}'),
+    Some('\u{2021}'),
+    Some('\u{02C6}'),
+    Some('\u{2030}'),
+    Some('\u{0160}'),
+    Some('\u{2039}'),
+    Some('\u{0152}'),
+    None,
+    Some('\u{017D}'),
+    None,
+    None,
+    Some('\u{2018}'),
+    Some('\u{2019}'),
+    Some('\u{201C}'),
+    Some('\u{201D}'),
+    Some('\u{2022}'),
+    Some('\u{2013}'),
+    Some('\u{2014}'),
+    Some('\u{02DC}'),
+    Some('\u{2122}'),
+    Some('\u{0161}'),
+    Some('\u{203A}'),
+    Some('\u{0153}'),
+    None,
+    Some('\u{017E}'),
+    Some('\u{0178}'),
+];
+
+impl StringEncoding {
+    /// Converts UTF-8 text from the client into bytes for the library.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, String> {
+        match self {
+            StringEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            StringEncoding::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c).map_err(|_| unmappable(c, self)))
+                .collect(),
+            StringEncoding::Cp1252 => text
+                .chars()
+                .map(|c| match u8::try_from(c) {
+                    Ok(b) if !(0x80..=0x9F).contains(&b) => Ok(b),
+                    _ => CP1252_HIGH
+                        .iter()
+                        .position(|&high| high == Some(c))
+                        .map(|i| 0x80 + i as u8)
+                        .ok_or_else(|| unmappable(c, self)),
+                })
+                .collect(),
+        }
+    }
+
+    /// Converts bytes returned by the library into UTF-8 text for the client.
+    pub fn decode(self, bytes: &[u8]) -> Result<String, String> {
+        match self {
+            StringEncoding::Utf8 => String::from_utf8(bytes.to_vec())
+                .map_err(|_| "Returned string is not valid UTF-8".to_string()),
+            StringEncoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+            StringEncoding::Cp1252 => bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => CP1252_HIGH[(b - 0x80) as usize]
+                        .ok_or_else(|| format!("Byte 0x{:02X} is undefined in cp1252", b)),
+                    _ => Ok(b as char),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn unmappable(c: char, encoding: StringEncoding) -> String {
+    format!("Character {:?} cannot be encoded as {:?}", c, encoding)
+}
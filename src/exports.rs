@@ -0,0 +1,425 @@
+//! Lists the symbols a library file exports, by reading its ELF dynamic symbol
+//! table or PE export directory directly; the OS loader offers no portable
+//! way to enumerate them. Also lists the libraries a file depends on and
+//! gives access to ELF sections for debug info.
+
+use std::path::Path;
+
+/// Names exported by the library at `path`.
+pub fn exported_symbols(path: &Path) -> Result<Vec<String>, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    match data.get(..4) {
+        Some([0x7f, b'E', b'L', b'F']) => elf_symbols(&data),
+        Some([b'M', b'Z', ..]) => pe_symbols(&data),
+        _ => Err(format!(
+            "Cannot list exports of {}: unrecognized file format",
+            path.display()
+        )),
+    }
+}
+
+/// The libraries a library needs loaded first, and where it asks for them
+/// to be looked for.
+#[derive(Debug, Default)]
+pub struct Imports {
+    /// ELF `DT_NEEDED` entries or the DLLs in the PE import directory.
+    pub libraries: Vec<String>,
+    /// The directories of ELF `DT_RUNPATH`, or of `DT_RPATH` when there is
+    /// no `DT_RUNPATH`, with `$ORIGIN` left as written. Empty for PE.
+    pub search_path: Vec<String>,
+}
+
+/// What the library at `path` imports.
+pub fn imports(path: &Path) -> Result<Imports, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    match data.get(..4) {
+        Some([0x7f, b'E', b'L', b'F']) => elf_imports(&data),
+        Some([b'M', b'Z', ..]) => Ok(Imports {
+            libraries: pe_imports(&data)?,
+            search_path: Vec::new(),
+        }),
+        _ => Err(format!(
+            "Cannot list imports of {}: unrecognized file format",
+            path.display()
+        )),
+    }
+}
+
+fn malformed() -> String {
+    "Malformed library file".into()
+}
+
+/// Bounds-checked little- or big-endian reads from the file image.
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N], String> {
+        let end = offset.checked_add(N).ok_or_else(malformed)?;
+        let bytes = self.data.get(offset..end).ok_or_else(malformed)?;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, String> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, String> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, String> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    /// A 32- or 64-bit word, depending on the file's class.
+    fn word(&self, offset: usize, wide: bool) -> Result<usize, String> {
+        let value = if wide {
+            self.u64(offset)?
+        } else {
+            self.u32(offset)? as u64
+        };
+        usize::try_from(value).map_err(|_| malformed())
+    }
+
+    /// Clamps an offset taken from the file to the image, so adding a field
+    /// offset to it cannot overflow and out-of-range reads fail cleanly.
+    fn clamp(&self, offset: usize) -> usize {
+        offset.min(self.data.len())
+    }
+
+    /// The null-terminated string starting at `offset`.
+    fn c_str(&self, offset: usize) -> Result<String, String> {
+        let rest = self.data.get(offset..).ok_or_else(malformed)?;
+        let len = rest.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+        Ok(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+}
+
+const SHT_DYNAMIC: u32 = 6;
+const SHT_DYNSYM: u32 = 11;
+const DT_NULL: usize = 0;
+const DT_NEEDED: usize = 1;
+const DT_RPATH: usize = 15;
+const DT_RUNPATH: usize = 29;
+const SHN_UNDEF: u16 = 0;
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// The fields of an ELF section header that are needed here.
+struct SectionHeader {
+    name: usize,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+    entsize: usize,
+}
+
+/// An ELF image's class, byte order and section headers.
+struct Elf<'a> {
+    r: Reader<'a>,
+    wide: bool,
+    sections: Vec<SectionHeader>,
+    /// Index of the section holding section names.
+    shstrndx: usize,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> Result<Elf<'a>, String> {
+        let wide = match data.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(malformed()),
+        };
+        let r = Reader {
+            data,
+            big_endian: data.get(5) == Some(&2),
+        };
+        let (shoff, shentsize, shnum, shstrndx) = if wide {
+            (
+                r.word(0x28, true)?,
+                r.u16(0x3a)?,
+                r.u16(0x3c)?,
+                r.u16(0x3e)?,
+            )
+        } else {
+            (
+                r.word(0x20, false)?,
+                r.u16(0x2e)?,
+                r.u16(0x30)?,
+                r.u16(0x32)?,
+            )
+        };
+        let sections = (0..shnum as usize)
+            .map(|index| {
+                let base = r.clamp(shoff.saturating_add(index.saturating_mul(shentsize as usize)));
+                Ok(if wide {
+                    SectionHeader {
+                        name: r.u32(base)? as usize,
+                        kind: r.u32(base + 4)?,
+                        offset: r.clamp(r.word(base + 0x18, true)?),
+                        size: r.word(base + 0x20, true)?,
+                        link: r.u32(base + 0x28)? as usize,
+                        entsize: r.word(base + 0x38, true)?,
+                    }
+                } else {
+                    SectionHeader {
+                        name: r.u32(base)? as usize,
+                        kind: r.u32(base + 4)?,
+                        offset: r.clamp(r.word(base + 0x10, false)?),
+                        size: r.word(base + 0x14, false)?,
+                        link: r.u32(base + 0x18)? as usize,
+                        entsize: r.word(base + 0x24, false)?,
+                    }
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Elf {
+            r,
+            wide,
+            sections,
+            shstrndx: shstrndx as usize,
+        })
+    }
+
+    fn section(&self, index: usize) -> Result<&SectionHeader, String> {
+        self.sections.get(index).ok_or_else(malformed)
+    }
+
+    /// The contents of the section called `name`, if there is one.
+    fn section_data(&self, name: &str) -> Result<Option<&'a [u8]>, String> {
+        let names = self.section(self.shstrndx)?.offset;
+        for section in &self.sections {
+            if self
+                .r
+                .c_str(self.r.clamp(names.saturating_add(section.name)))?
+                == name
+            {
+                let end = section
+                    .offset
+                    .checked_add(section.size)
+                    .ok_or_else(malformed)?;
+                return self
+                    .r
+                    .data
+                    .get(section.offset..end)
+                    .map(Some)
+                    .ok_or_else(malformed);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The contents of the section called `name` in the ELF image `data`, e.g.
+/// `.debug_info`; `None` if the image has no such section.
+pub fn elf_section<'a>(data: &'a [u8], name: &str) -> Result<Option<&'a [u8]>, String> {
+    Elf::parse(data)?.section_data(name)
+}
+
+/// Whether an ELF image is big-endian.
+pub fn elf_is_big_endian(data: &[u8]) -> bool {
+    data.get(5) == Some(&2)
+}
+
+/// Defined global or weak functions and objects in `.dynsym`.
+fn elf_symbols(data: &[u8]) -> Result<Vec<String>, String> {
+    let elf = Elf::parse(data)?;
+    let r = &elf.r;
+    let mut names = Vec::new();
+    for section in elf.sections.iter().filter(|s| s.kind == SHT_DYNSYM) {
+        let strtab = elf.section(section.link)?.offset;
+        if section.entsize == 0 {
+            return Err(malformed());
+        }
+        let end = r.clamp(section.offset.saturating_add(section.size));
+        for symbol in (section.offset..end).step_by(section.entsize) {
+            let (info, shndx) = if elf.wide {
+                (r.bytes::<1>(symbol + 4)?[0], r.u16(symbol + 6)?)
+            } else {
+                (r.bytes::<1>(symbol + 12)?[0], r.u16(symbol + 14)?)
+            };
+            let binding = info >> 4;
+            let kind = info & 0xf;
+            if shndx == SHN_UNDEF
+                || !matches!(binding, STB_GLOBAL | STB_WEAK)
+                || !matches!(kind, STT_FUNC | STT_OBJECT)
+            {
+                continue;
+            }
+            let name = r.c_str(r.clamp(strtab.saturating_add(r.u32(symbol)? as usize)))?;
+            if !name.is_empty() {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Libraries named by `DT_NEEDED` entries in the dynamic section, and the
+/// search path of its `DT_RUNPATH` or `DT_RPATH`.
+fn elf_imports(data: &[u8]) -> Result<Imports, String> {
+    let elf = Elf::parse(data)?;
+    let r = &elf.r;
+    let mut names = Vec::new();
+    let (mut runpath, mut rpath) = (None, None);
+    for section in elf.sections.iter().filter(|s| s.kind == SHT_DYNAMIC) {
+        let strtab = elf.section(section.link)?.offset;
+        if section.entsize == 0 {
+            return Err(malformed());
+        }
+        let end = r.clamp(section.offset.saturating_add(section.size));
+        for entry in (section.offset..end).step_by(section.entsize) {
+            let word = if elf.wide { 8 } else { 4 };
+            let tag = r.word(entry, elf.wide)?;
+            if tag == DT_NULL {
+                break;
+            }
+            if ![DT_NEEDED, DT_RPATH, DT_RUNPATH].contains(&tag) {
+                continue;
+            }
+            let value = r.word(entry + word, elf.wide)?;
+            let value = r.c_str(r.clamp(strtab.saturating_add(value)))?;
+            match tag {
+                DT_NEEDED => names.push(value),
+                DT_RPATH => rpath = Some(value),
+                _ => runpath = Some(value),
+            }
+        }
+    }
+    // The loader ignores `DT_RPATH` in a library that has `DT_RUNPATH`.
+    Ok(Imports {
+        libraries: names,
+        search_path: runpath
+            .or(rpath)
+            .map(|path| {
+                path.split(':')
+                    .filter(|dir| !dir.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+/// A PE image's data directories and section table.
+struct Pe<'a> {
+    r: Reader<'a>,
+    data_directories: usize,
+    sections: usize,
+    section_count: usize,
+}
+
+impl<'a> Pe<'a> {
+    fn parse(data: &'a [u8]) -> Result<Pe<'a>, String> {
+        let r = Reader {
+            data,
+            big_endian: false,
+        };
+        let pe = r.clamp(r.u32(0x3c)? as usize);
+        if r.bytes::<4>(pe)? != *b"PE\0\0" {
+            return Err(malformed());
+        }
+        let coff = pe + 4;
+        let section_count = r.u16(coff + 2)? as usize;
+        let optional = coff + 20;
+        let optional_size = r.u16(coff + 16)? as usize;
+        let data_directories = match r.u16(optional)? {
+            0x10b => optional + 96,
+            0x20b => optional + 112,
+            _ => return Err(malformed()),
+        };
+        Ok(Pe {
+            sections: r.clamp(optional + optional_size),
+            r,
+            data_directories,
+            section_count,
+        })
+    }
+
+    /// The RVA of data directory `index`, e.g. 0 for exports; 0 if absent.
+    fn directory(&self, index: usize) -> Result<usize, String> {
+        Ok(self.r.u32(self.data_directories + index * 8)? as usize)
+    }
+
+    /// The file offset an RVA is stored at.
+    fn to_offset(&self, rva: usize) -> Result<usize, String> {
+        let r = &self.r;
+        for index in 0..self.section_count {
+            let header = r.clamp(self.sections.saturating_add(index.saturating_mul(40)));
+            let virtual_size = r.u32(header + 8)? as usize;
+            let virtual_address = r.u32(header + 12)? as usize;
+            let raw_size = r.u32(header + 16)? as usize;
+            let raw_offset = r.u32(header + 20)? as usize;
+            let end = virtual_address.saturating_add(virtual_size.max(raw_size));
+            if (virtual_address..end).contains(&rva) {
+                return Ok(r.clamp((rva - virtual_address).saturating_add(raw_offset)));
+            }
+        }
+        Err(malformed())
+    }
+}
+
+/// DLLs named in the PE import directory.
+fn pe_imports(data: &[u8]) -> Result<Vec<String>, String> {
+    let pe = Pe::parse(data)?;
+    let import_rva = pe.directory(1)?;
+    if import_rva == 0 {
+        return Ok(Vec::new());
+    }
+    let r = &pe.r;
+    let mut names = Vec::new();
+    // Descriptors are 20 bytes each; an all-zero one ends the table.
+    let mut descriptor = pe.to_offset(import_rva)?;
+    loop {
+        let name_rva = r.u32(descriptor + 12)? as usize;
+        if name_rva == 0 {
+            break;
+        }
+        names.push(r.c_str(pe.to_offset(name_rva)?)?);
+        descriptor = r.clamp(descriptor + 20);
+    }
+    Ok(names)
+}
+
+/// Names in the PE export directory.
+fn pe_symbols(data: &[u8]) -> Result<Vec<String>, String> {
+    let pe = Pe::parse(data)?;
+    let export_rva = pe.directory(0)?;
+    if export_rva == 0 {
+        return Ok(Vec::new());
+    }
+    let r = &pe.r;
+    let directory = pe.to_offset(export_rva)?;
+    let name_count = r.u32(directory + 24)? as usize;
+    let names = pe.to_offset(r.u32(directory + 32)? as usize)?;
+    (0..name_count)
+        .map(|i| {
+            r.c_str(
+                pe.to_offset(r.u32(r.clamp(names.saturating_add(i.saturating_mul(4))))? as usize)?,
+            )
+        })
+        .collect()
+}
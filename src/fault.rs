@@ -0,0 +1,107 @@
+//! `--fault-inject`: deterministic faults for testing how clients handle
+//! errors and broken replies. The faults are produced on the real request
+//! path, so they look exactly like the genuine article.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// What happens to an affected request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// The request is not executed and is answered `ERR <code> injected fault`.
+    Error(String),
+    /// The request is executed, but only the first half of its reply is
+    /// written, without a delimiter, and the connection is closed.
+    Truncate,
+}
+
+/// Which of a connection's requests a fault affects, counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// Every `N`th request: `N`, `2N`, `3N`...
+    Every(u64),
+    /// Only the `N`th request.
+    Once(u64),
+}
+
+impl Schedule {
+    fn applies_to(self, request: u64) -> bool {
+        match self {
+            Schedule::Every(n) => request.is_multiple_of(n),
+            Schedule::Once(n) => request == n,
+        }
+    }
+}
+
+/// The faults given to `--fault-inject`, e.g.
+/// `error:E_BUSY@every:3,truncate@once:5`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultPlan(Vec<(Fault, Schedule)>);
+
+impl FaultPlan {
+    /// The fault for a connection's `request`th request, if any; the first
+    /// listed wins when several are due.
+    pub fn fault_for(&self, request: u64) -> Option<&Fault> {
+        self.0
+            .iter()
+            .find(|(_, schedule)| schedule.applies_to(request))
+            .map(|(fault, _)| fault)
+    }
+}
+
+impl FromStr for FaultPlan {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = |rule: &str| {
+            format!(
+                "Invalid fault: {} (expected error:<CODE>@<when> or truncate@<when>, \
+                 <when> being every:<N> or once:<N>)",
+                rule
+            )
+        };
+        let rules = spec
+            .split(',')
+            .map(|rule| {
+                let (fault, when) = rule.split_once('@').ok_or_else(|| invalid(rule))?;
+                let fault = match fault.split_once(':') {
+                    Some(("error", code)) if is_error_code(code) => Fault::Error(code.into()),
+                    None if fault == "truncate" => Fault::Truncate,
+                    _ => return Err(invalid(rule)),
+                };
+                let (kind, n) = when.split_once(':').ok_or_else(|| invalid(rule))?;
+                let n = n
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| invalid(rule))?;
+                let schedule = match kind {
+                    "every" => Schedule::Every(n),
+                    "once" => Schedule::Once(n),
+                    _ => return Err(invalid(rule)),
+                };
+                Ok((fault, schedule))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(FaultPlan(rules))
+    }
+}
+
+/// `E_` followed by upper-case letters, digits and underscores.
+fn is_error_code(code: &str) -> bool {
+    code.strip_prefix("E_").is_some_and(|rest| {
+        !rest.is_empty()
+            && rest
+                .bytes()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+    })
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::Error(code) => write!(f, "error:{}", code),
+            Fault::Truncate => write!(f, "truncate"),
+        }
+    }
+}
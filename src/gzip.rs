@@ -0,0 +1,181 @@
+//! A small gzip (RFC 1952) encoder for compressed replies. It finds repeats
+//! with LZ77 and codes them with DEFLATE's fixed Huffman tables, which need
+//! no tables in the stream and do well on the repetitive text of long replies.
+
+/// How far back a repeat may start.
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Earlier occurrences of a 3-byte prefix tried before settling for the best so far.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// `data` as a complete gzip member.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // No file name or timestamp; the OS byte is "unknown".
+    let header = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    let mut bits = BitWriter {
+        out: header,
+        acc: 0,
+        count: 0,
+    };
+    // One final block with the fixed codes.
+    bits.write(1, 1);
+    bits.write(1, 2);
+    deflate(data, &mut bits);
+    bits.literal(256);
+    let mut out = bits.finish();
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn deflate(data: &[u8], bits: &mut BitWriter) {
+    // The latest position of each hashed 3-byte prefix, and for every
+    // position the previous one with the same hash.
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &head, &prev);
+        let end = if length >= MIN_MATCH {
+            bits.copy(length, distance);
+            pos + length
+        } else {
+            bits.literal(data[pos] as usize);
+            pos + 1
+        };
+        while pos < end {
+            if let Some(hash) = hash(data, pos) {
+                prev[pos] = head[hash];
+                head[hash] = pos;
+            }
+            pos += 1;
+        }
+    }
+}
+
+fn hash(data: &[u8], pos: usize) -> Option<usize> {
+    let bytes = data.get(pos..pos + MIN_MATCH)?;
+    let hash = (bytes[0] as usize) << 10 ^ (bytes[1] as usize) << 5 ^ bytes[2] as usize;
+    Some(hash & ((1 << HASH_BITS) - 1))
+}
+
+/// The longest earlier repeat of the bytes at `pos`, as `(length, distance)`.
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    let Some(hash) = hash(data, pos) else {
+        return (0, 0);
+    };
+    let ahead = &data[pos..(pos + MAX_MATCH).min(data.len())];
+    let mut best = (0, 0);
+    let mut candidate = head[hash];
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || pos - candidate > WINDOW {
+            break;
+        }
+        // A repeat may run into the bytes it copies; the decoder copies byte by byte.
+        let length = data[candidate..]
+            .iter()
+            .zip(ahead)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best.0 {
+            best = (length, pos - candidate);
+            if length == ahead.len() {
+                break;
+            }
+        }
+        candidate = prev[candidate];
+    }
+    best
+}
+
+/// Packs DEFLATE's bit fields, least significant bit first.
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: usize, count: u32) {
+        self.acc |= (value as u32) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit.
+    fn code(&mut self, code: usize, length: u32) {
+        self.write(
+            ((code as u32).reverse_bits() >> (32 - length)) as usize,
+            length,
+        );
+    }
+
+    /// A literal byte, the end of the block (256) or a length symbol.
+    fn literal(&mut self, symbol: usize) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASE
+            .iter()
+            .rposition(|&base| base <= length)
+            .unwrap();
+        self.literal(257 + index);
+        self.write(length - LENGTH_BASE[index], LENGTH_EXTRA[index]);
+        let index = DISTANCE_BASE
+            .iter()
+            .rposition(|&base| base <= distance)
+            .unwrap();
+        self.code(index, 5);
+        self.write(distance - DISTANCE_BASE[index], DISTANCE_EXTRA[index]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,104 @@
+//! `admin open`: files the server opens for its clients, so a library that
+//! operates on a descriptor or `HANDLE` is given one valid in this process.
+//! Each file is named by an `fd:<n>` token, which `handle` arguments accept
+//! in place of the native value; clients never see or send descriptor
+//! numbers.
+//!
+//! A file stays open until `admin close` or the server exits. Closing only
+//! forgets the token: a call already running with the file keeps it open
+//! until it returns.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The native form of a handle: a descriptor on Unix, a `HANDLE` elsewhere.
+#[cfg(unix)]
+pub type NativeHandle = std::os::fd::RawFd;
+#[cfg(windows)]
+pub type NativeHandle = std::os::windows::io::RawHandle;
+
+/// The value a call is given for `file`.
+pub fn native(file: &File) -> NativeHandle {
+    #[cfg(unix)]
+    {
+        std::os::fd::AsRawFd::as_raw_fd(file)
+    }
+    #[cfg(windows)]
+    {
+        std::os::windows::io::AsRawHandle::as_raw_handle(file)
+    }
+}
+
+/// The files opened with `admin open`, by the number in their token.
+#[derive(Default)]
+pub struct Handles {
+    next: AtomicU64,
+    files: Mutex<HashMap<u64, Arc<File>>>,
+}
+
+impl Handles {
+    /// Opens `path` in `mode` (`read`, `write`, `append` or `readwrite`) and
+    /// returns its token. `write` and `append` create a missing file, and
+    /// `write` truncates an existing one.
+    pub fn open(&self, path: &str, mode: &str) -> Result<String, String> {
+        let mut options = File::options();
+        match mode {
+            "read" => options.read(true),
+            "write" => options.write(true).create(true).truncate(true),
+            "append" => options.append(true).create(true),
+            "readwrite" => options.read(true).write(true),
+            _ => {
+                return Err(format!(
+                    "Unknown open mode: {} (expected read, write, append or readwrite)",
+                    mode
+                ));
+            }
+        };
+        let file = options
+            .open(path)
+            .map_err(|e| format!("E_HANDLE cannot open {}: {}", path, e))?;
+        let number = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        self.files.lock().unwrap().insert(number, Arc::new(file));
+        Ok(format!("fd:{}", number))
+    }
+
+    /// Forgets the file named by `token`, closing it once no call uses it.
+    pub fn close(&self, token: &str) -> Result<(), String> {
+        let number = parse_token(token)?;
+        self.files
+            .lock()
+            .unwrap()
+            .remove(&number)
+            .map(|_| ())
+            .ok_or_else(|| unknown(token))
+    }
+
+    /// The file named by `token`, kept open for as long as it is held.
+    pub fn get(&self, token: &str) -> Result<Arc<File>, String> {
+        let number = parse_token(token)?;
+        self.files
+            .lock()
+            .unwrap()
+            .get(&number)
+            .cloned()
+            .ok_or_else(|| unknown(token))
+    }
+}
+
+fn parse_token(token: &str) -> Result<u64, String> {
+    token
+        .strip_prefix("fd:")
+        .and_then(|number| number.parse().ok())
+        .ok_or_else(|| {
+            format!(
+                "E_HANDLE '{}' is not a handle token; open the file with admin open",
+                token
+            )
+        })
+}
+
+fn unknown(token: &str) -> String {
+    format!("E_HANDLE unknown handle {}", token)
+}
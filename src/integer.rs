@@ -0,0 +1,149 @@
+//! The one parser behind every integer argument, whatever its width, so all
+//! of them accept the same syntax and fail with the same errors.
+
+use std::fmt;
+
+/// An integer type [`parse_int`] can produce.
+pub trait Integer: Copy {
+    const MIN: i128;
+    const MAX: i128;
+    /// Converts a value already checked to lie in `MIN..=MAX`.
+    fn from_i128(value: i128) -> Self;
+}
+
+macro_rules! integer {
+    ($($ty:ty),*) => {$(
+        impl Integer for $ty {
+            const MIN: i128 = <$ty>::MIN as i128;
+            const MAX: i128 = <$ty>::MAX as i128;
+            fn from_i128(value: i128) -> Self {
+                value as $ty
+            }
+        }
+    )*};
+}
+
+integer!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+/// Why a token is not a value of the requested width.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntError {
+    /// Not an integer at all: no digits, a digit outside the radix, or stray text.
+    Parse,
+    /// A well-formed integer that does not fit the type.
+    Range { min: i128, max: i128 },
+}
+
+impl IntError {
+    /// The protocol error for `token` given as a `type_name`, e.g.
+    /// `E_RANGE Argument parsing error: '300' is out of range for char (-128 to 127)`.
+    pub fn describe(self, token: &str, type_name: impl fmt::Display) -> String {
+        match self {
+            IntError::Parse => format!(
+                "E_PARSE Argument parsing error: '{}' is not a valid {}",
+                token, type_name
+            ),
+            IntError::Range { min, max } => format!(
+                "E_RANGE Argument parsing error: '{}' is out of range for {} ({} to {})",
+                token, type_name, min, max
+            ),
+        }
+    }
+}
+
+/// Parses `[+|-][0x|0o|0b]<digits>` as a `T`. Radix prefixes may be upper or
+/// lower case; the digits must all belong to the radix, and the value,
+/// including its sign, must fit `T`.
+pub fn parse_int<T: Integer>(token: &str) -> Result<T, IntError> {
+    let (negative, unsigned) = match token.as_bytes().first() {
+        Some(b'-') => (true, &token[1..]),
+        Some(b'+') => (false, &token[1..]),
+        _ => (false, token),
+    };
+    let prefix = unsigned.get(..2).map(str::to_ascii_lowercase);
+    let (radix, digits) = match prefix.as_deref() {
+        Some("0x") => (16, &unsigned[2..]),
+        Some("0o") => (8, &unsigned[2..]),
+        Some("0b") => (2, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+    // `from_str_radix` would also take a second sign.
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(IntError::Parse);
+    }
+    let range = IntError::Range {
+        min: T::MIN,
+        max: T::MAX,
+    };
+    // Only digits are left, so the one failure is a magnitude beyond `u128`.
+    let magnitude = u128::from_str_radix(digits, radix).map_err(|_| range)?;
+    let value = i128::try_from(magnitude).map_err(|_| range)?;
+    let value = if negative { -value } else { value };
+    if !(T::MIN..=T::MAX).contains(&value) {
+        return Err(range);
+    }
+    Ok(T::from_i128(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_limits_of_each_width() {
+        assert_eq!(parse_int::<i8>("-128"), Ok(i8::MIN));
+        assert_eq!(parse_int::<i8>("-0x80"), Ok(i8::MIN));
+        assert_eq!(parse_int::<u32>("4294967295"), Ok(u32::MAX));
+        assert_eq!(parse_int::<u32>("0xFFFFFFFF"), Ok(u32::MAX));
+        assert_eq!(parse_int::<i32>("-0x10"), Ok(-16));
+        assert_eq!(parse_int::<i32>("+0B101"), Ok(5));
+        assert_eq!(parse_int::<i32>("-0o17"), Ok(-15));
+    }
+
+    #[test]
+    fn one_past_a_limit_is_out_of_range() {
+        let i8_range = Err(IntError::Range {
+            min: -128,
+            max: 127,
+        });
+        assert_eq!(parse_int::<i8>("-129"), i8_range);
+        assert_eq!(parse_int::<i8>("128"), i8_range);
+        let u32_range = Err(IntError::Range {
+            min: 0,
+            max: u32::MAX.into(),
+        });
+        assert_eq!(parse_int::<u32>("4294967296"), u32_range);
+        assert_eq!(parse_int::<u32>("0x100000000"), u32_range);
+        assert_eq!(parse_int::<u32>("-1"), u32_range);
+        // Even more digits than `u128` holds are a range error, not a parse error.
+        assert!(matches!(
+            parse_int::<u64>(&"9".repeat(40)),
+            Err(IntError::Range { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_digits() {
+        for token in [
+            "", "-", "+", "0x", "-0x", "0b", "--1", "+-1", "0x-1", "0q12", "0b102", "1_000", " 1",
+        ] {
+            assert_eq!(parse_int::<i32>(token), Err(IntError::Parse), "{:?}", token);
+        }
+    }
+
+    #[test]
+    fn describes_errors_with_their_code() {
+        assert_eq!(
+            IntError::Parse.describe("0x", "int"),
+            "E_PARSE Argument parsing error: '0x' is not a valid int"
+        );
+        assert_eq!(
+            IntError::Range {
+                min: -128,
+                max: 127
+            }
+            .describe("300", "char"),
+            "E_RANGE Argument parsing error: '300' is out of range for char (-128 to 127)"
+        );
+    }
+}
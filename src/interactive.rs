@@ -0,0 +1,49 @@
+//! The `--interactive` presentation for people typing at the server with
+//! `telnet` or `nc`: a greeting on connect, a prompt before each command,
+//! labelled results and `?` for help. Machine clients never see any of it
+//! unless the server is started with the flag.
+
+use crate::COMMANDS;
+use crate::config::Config;
+
+/// Written, without a delimiter, whenever the server is ready for a command.
+pub const PROMPT: &str = "dllbridge32> ";
+
+/// The lines sent when a client connects.
+pub fn greeting(config: &Config) -> String {
+    format!(
+        "Welcome to dllbridge32 {}, serving {}\nType ? for a list of commands, ? <command> for its usage.",
+        env!("CARGO_PKG_VERSION"),
+        config.dll_path
+    )
+}
+
+/// `? [command]`: every command's usage, one per line, or just the usage of
+/// `command`.
+pub fn help(tokens: &[&str]) -> String {
+    match tokens {
+        [] => {
+            let usages: Vec<String> = COMMANDS
+                .iter()
+                .map(|command| format!("  {}", command.usage))
+                .collect();
+            format!("Commands:\n{}", usages.join("\n"))
+        }
+        [name] => match COMMANDS.iter().find(|command| command.name == *name) {
+            Some(command) => format!("Usage: {}", command.usage),
+            None => format_reply(Err(format!(
+                "Unknown command: {} (type ? for a list)",
+                name
+            ))),
+        },
+        _ => format_reply(Err("Usage: ? [command]".into())),
+    }
+}
+
+/// Labels a reply for reading: `= <result>` or `error: <message>`.
+pub fn format_reply(reply: Result<String, String>) -> String {
+    match reply {
+        Ok(result) => format!("= {}", result),
+        Err(err) => format!("error: {}", err),
+    }
+}
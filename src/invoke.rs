@@ -0,0 +1,922 @@
+use crate::capture;
+use crate::encoding::StringEncoding;
+use crate::handles::{self, Handles, NativeHandle};
+use crate::integer::{IntError, parse_int};
+use crate::longdouble::LongDouble;
+use crate::registry::LoadedLibrary;
+use crate::schema::Enums;
+use crate::signals::CallThread;
+use crate::signature::{BufferFormat, FunctionSignature, ParamMode, SupportedType, format_param};
+use libffi::middle::{Arg, Cif, CodePtr, Type};
+use std::collections::HashMap;
+use std::ffi::{CString, c_char, c_void};
+use std::fs::File;
+use std::str::FromStr;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Per-call settings that influence how values are marshaled.
+#[derive(Clone)]
+pub struct CallOptions {
+    pub encoding: StringEncoding,
+    /// Longest string return that will be read before answering `E_RESULT_TOO_LARGE`.
+    pub max_result_bytes: usize,
+    /// Call as if the function returned `void` and reply `OK`, never reading the result.
+    pub discard_result: bool,
+    /// Exchange `float` values as the hex form of their IEEE-754 bits, e.g. `0x3f800000`.
+    pub float_bits: bool,
+    /// Follow `int` and `char` results with their hex form at the type's
+    /// width, e.g. `255 (0x000000FF)`.
+    pub hex: bool,
+    /// Resolve and validate everything, including the arguments, but reply
+    /// `DRYRUN resolved` instead of calling the function.
+    pub dry_run: bool,
+    /// What a successful `void` call replies.
+    pub void_reply: VoidReply,
+    /// Precede the reply with a trace of the resolved symbol, the marshaled
+    /// arguments and the raw return value.
+    pub trace: bool,
+    /// Reply `result=<reply> time_us=<N>`, timing only the foreign call.
+    pub time: bool,
+    /// Follow the reply with what the call wrote to the process's stdout and
+    /// stderr, as `stdout=<text> stderr=<text>`.
+    pub capture: bool,
+    /// Whether `char` values range over `-128..=127` rather than `0..=255`.
+    pub char_signed: bool,
+    /// Retry an `int` or `float` argument that does not parse with [`coerce`],
+    /// and follow the reply with `coerced=` for each one that was.
+    pub lenient_args: bool,
+    /// Symbolic names of `int` return values; a named result is followed by
+    /// its name, e.g. `1 (NOT_FOUND)`.
+    pub return_names: Option<Arc<HashMap<i32, String>>>,
+    /// The files `handle` arguments refer to.
+    pub handles: Arc<Handles>,
+    /// The enums `arg:<Name>:<NAME>` arguments to `int` parameters name.
+    pub enums: Arc<Enums>,
+}
+
+/// The reply to a successful call of a `void` function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoidReply {
+    /// An empty line.
+    Empty,
+    /// `OK`, as other commands without a result reply.
+    Ok,
+    /// The literal `void`.
+    Void,
+}
+
+impl FromStr for VoidReply {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "empty" => Ok(VoidReply::Empty),
+            "ok" => Ok(VoidReply::Ok),
+            "void" => Ok(VoidReply::Void),
+            _ => Err(format!("Unknown void reply mode: {}", s)),
+        }
+    }
+}
+
+impl VoidReply {
+    fn text(self) -> &'static str {
+        match self {
+            VoidReply::Empty => "",
+            VoidReply::Ok => "OK",
+            VoidReply::Void => "void",
+        }
+    }
+}
+
+/// An argument converted from its wire form, kept alive for the duration of the call.
+enum ArgValue {
+    Int(i32),
+    Float(f32),
+    Char(i8),
+    LongDouble(LongDouble),
+    // `ptr` refers into `bytes`, which owns the data.
+    Str {
+        bytes: CString,
+        ptr: *const c_char,
+    },
+    /// A `str_len` argument: the bytes without a terminator and their count,
+    /// passed as two native arguments.
+    Counted {
+        bytes: Vec<u8>,
+        ptr: *const c_char,
+        len: usize,
+    },
+    Ptr(*const c_void),
+    /// A `handle` argument: the native value of a file opened with `admin
+    /// open`, with the file itself held open until the call returns.
+    Handle {
+        _file: Arc<File>,
+        native: NativeHandle,
+    },
+    /// A zeroed `buf:` buffer; `ptr` points at the start of `bytes`.
+    Buffer {
+        bytes: Vec<u8>,
+        ptr: *mut c_void,
+    },
+    /// A zeroed `out struct{...}` of `size` bytes; `ptr` points at the start
+    /// of `_storage`, which is aligned for any field type.
+    Struct {
+        _storage: Vec<ReturnBuffer>,
+        ptr: *mut c_void,
+        size: usize,
+    },
+    /// Server-owned storage for an `out` or `inout` parameter, passed by
+    /// address; `ptr` points at the value inside `_target`.
+    Slot {
+        target: Box<ArgValue>,
+        ptr: *mut c_void,
+    },
+}
+
+impl ArgValue {
+    /// Moves `target` into storage the function can write through.
+    fn slot(target: ArgValue) -> ArgValue {
+        let mut target = Box::new(target);
+        let ptr = match &mut *target {
+            ArgValue::Int(v) => v as *mut i32 as *mut c_void,
+            ArgValue::Float(v) => v as *mut f32 as *mut c_void,
+            ArgValue::Char(v) => v as *mut i8 as *mut c_void,
+            ArgValue::LongDouble(v) => v as *mut LongDouble as *mut c_void,
+            ArgValue::Str { ptr, .. } => ptr as *mut *const c_char as *mut c_void,
+            ArgValue::Ptr(p) => p as *mut *const c_void as *mut c_void,
+            ArgValue::Counted { .. } => unreachable!("str_len is never an out-parameter"),
+            ArgValue::Handle { .. } => unreachable!("handle is never an out-parameter"),
+            ArgValue::Buffer { .. } | ArgValue::Struct { .. } => {
+                unreachable!("buffers are passed as they are")
+            }
+            ArgValue::Slot { .. } => unreachable!("slots are not nested"),
+        };
+        ArgValue::Slot { target, ptr }
+    }
+
+    /// The initial value of an `out` parameter; a `str` starts as a null `char*`.
+    fn zero(ty: SupportedType) -> ArgValue {
+        match ty {
+            SupportedType::Int => ArgValue::Int(0),
+            SupportedType::Float => ArgValue::Float(0.0),
+            SupportedType::Char => ArgValue::Char(0),
+            SupportedType::LongDouble => ArgValue::LongDouble(LongDouble::ZERO),
+            _ => ArgValue::Ptr(std::ptr::null()),
+        }
+    }
+
+    fn buffer(size: usize) -> ArgValue {
+        let mut bytes = vec![0; size];
+        let ptr = bytes.as_mut_ptr() as *mut c_void;
+        ArgValue::Buffer { bytes, ptr }
+    }
+
+    fn structure(size: usize) -> ArgValue {
+        let mut storage: Vec<ReturnBuffer> = (0..size.div_ceil(size_of::<ReturnBuffer>()))
+            .map(|_| ReturnBuffer([0; 16]))
+            .collect();
+        let ptr = storage.as_mut_ptr() as *mut c_void;
+        ArgValue::Struct {
+            _storage: storage,
+            ptr,
+            size,
+        }
+    }
+
+    fn slot_ptr(&self) -> *const c_void {
+        match self {
+            ArgValue::Slot { ptr, .. } => *ptr,
+            _ => unreachable!("not an out-parameter"),
+        }
+    }
+
+    /// Converts the argument for parameter `position` (1-based).
+    fn parse(
+        ty: SupportedType,
+        token: &str,
+        position: usize,
+        options: &CallOptions,
+    ) -> Result<ArgValue, String> {
+        let parse_error = || {
+            format!(
+                "E_PARSE Argument parsing error: '{}' is not a valid {}",
+                token, ty
+            )
+        };
+        match ty {
+            SupportedType::Int => match options.enums.resolve(token) {
+                Some(value) => value.map(ArgValue::Int),
+                None => parse_int(token)
+                    .map(ArgValue::Int)
+                    .map_err(|e| e.describe(token, ty)),
+            },
+            SupportedType::Float if options.float_bits => match parse_float_bits(token) {
+                Ok(value) => Ok(ArgValue::Float(value)),
+                Err(FloatBitsError::TooWide) => Err(format!(
+                    "E_RANGE Argument parsing error: '{}' is a bit pattern wider than float (32 bits)",
+                    token
+                )),
+                Err(FloatBitsError::Parse) => Err(parse_error()),
+            },
+            SupportedType::Float => token
+                .parse()
+                .map(ArgValue::Float)
+                .map_err(|_| parse_error()),
+            SupportedType::Char => match parse_char(token, options.char_signed) {
+                Ok(v) => Ok(ArgValue::Char(v)),
+                Err(IntError::Parse) if token.len() == 1 && token.is_ascii() => {
+                    Ok(ArgValue::Char(token.as_bytes()[0] as i8))
+                }
+                Err(e) => Err(e.describe(token, ty)),
+            },
+            SupportedType::Str => {
+                let bytes = options.encoding.encode(token)?;
+                let bytes = CString::new(bytes).map_err(|e| {
+                    format!(
+                        "E_NUL interior null in string arg {} at byte {}",
+                        position,
+                        e.nul_position()
+                    )
+                })?;
+                let ptr = bytes.as_ptr();
+                Ok(ArgValue::Str { bytes, ptr })
+            }
+            SupportedType::StrLen { nul } => {
+                let mut bytes = options.encoding.encode(token)?;
+                let len = bytes.len();
+                if nul {
+                    bytes.push(0);
+                }
+                let ptr = bytes.as_ptr() as *const c_char;
+                Ok(ArgValue::Counted { bytes, ptr, len })
+            }
+            SupportedType::Ptr if token == "null" => Ok(ArgValue::Ptr(std::ptr::null())),
+            SupportedType::Ptr => parse_int::<usize>(token)
+                .map(|address| ArgValue::Ptr(address as *const c_void))
+                .map_err(|e| e.describe(token, ty)),
+            SupportedType::Handle => {
+                let file = options.handles.get(token)?;
+                let native = handles::native(&file);
+                Ok(ArgValue::Handle {
+                    _file: file,
+                    native,
+                })
+            }
+            SupportedType::Void => Err("void cannot be used as a parameter type".into()),
+            SupportedType::LongDouble => LongDouble::parse(token)
+                .map(ArgValue::LongDouble)
+                .ok_or_else(parse_error),
+        }
+    }
+
+    /// The value and its raw native form for `+trace`. Only server-owned
+    /// storage is shown, never memory the pointers refer to.
+    fn trace(&self) -> String {
+        match self {
+            ArgValue::Int(v) => format!("{} raw=0x{:08x}", v, *v as u32),
+            ArgValue::Float(v) => format!("{} raw=0x{:08x}", v, v.to_bits()),
+            ArgValue::Char(v) => format!("{} raw=0x{:02x}", v, *v as u8),
+            ArgValue::LongDouble(v) => format!("{} raw={}", v, v.raw_hex()),
+            ArgValue::Str { bytes, ptr } => format!(
+                "\"{}\" raw={} bytes={}",
+                escape_line(&String::from_utf8_lossy(bytes.to_bytes())),
+                format_pointer(*ptr as *const c_void),
+                bytes.as_bytes_with_nul().len()
+            ),
+            // `bytes=` counts a terminator the length leaves out.
+            ArgValue::Counted { bytes, ptr, len } => format!(
+                "\"{}\" raw={} len={} bytes={}",
+                escape_line(&String::from_utf8_lossy(&bytes[..*len])),
+                format_pointer(*ptr as *const c_void),
+                len,
+                bytes.len()
+            ),
+            ArgValue::Ptr(ptr) => format!("raw={}", format_pointer(*ptr)),
+            ArgValue::Handle { native, .. } => format!("raw={:?}", native),
+            ArgValue::Buffer { bytes, ptr } => format!(
+                "buffer={} size={}",
+                format_pointer(*ptr as *const c_void),
+                bytes.len()
+            ),
+            ArgValue::Struct { ptr, size, .. } => format!(
+                "struct={} size={}",
+                format_pointer(*ptr as *const c_void),
+                size
+            ),
+            ArgValue::Slot { target, ptr } => {
+                format!("slot={} initial={}", format_pointer(*ptr), target.trace())
+            }
+        }
+    }
+
+    /// The native arguments the value is passed as; two for a counted string.
+    fn as_args(&self) -> Vec<Arg> {
+        match self {
+            ArgValue::Int(v) => vec![Arg::new(v)],
+            ArgValue::Float(v) => vec![Arg::new(v)],
+            ArgValue::Char(v) => vec![Arg::new(v)],
+            ArgValue::LongDouble(v) => vec![Arg::new(v)],
+            ArgValue::Str { ptr, .. } => vec![Arg::new(ptr)],
+            ArgValue::Counted { ptr, len, .. } => vec![Arg::new(ptr), Arg::new(len)],
+            ArgValue::Ptr(ptr) => vec![Arg::new(ptr)],
+            ArgValue::Handle { native, .. } => vec![Arg::new(native)],
+            ArgValue::Buffer { ptr, .. } => vec![Arg::new(ptr)],
+            ArgValue::Struct { ptr, .. } => vec![Arg::new(ptr)],
+            ArgValue::Slot { ptr, .. } => vec![Arg::new(ptr)],
+        }
+    }
+}
+
+/// Formats an address as a fixed-width hex token, e.g. `0x000055d0c8a4e2a0`.
+pub fn format_pointer(ptr: *const c_void) -> String {
+    format!(
+        "0x{:0width$x}",
+        ptr as usize,
+        width = 2 * size_of::<usize>()
+    )
+}
+
+/// Parses a token produced by [`format_pointer`]; `null` and `0` are also accepted.
+pub fn parse_pointer(token: &str) -> Option<*const c_void> {
+    if token == "null" {
+        return Some(std::ptr::null());
+    }
+    parse_int::<usize>(token)
+        .ok()
+        .map(|address| address as *const c_void)
+}
+
+/// Why a `+bits` float argument was refused.
+enum FloatBitsError {
+    /// Not `0x` followed by hex digits.
+    Parse,
+    /// Hex digits for more than 32 bits, such as a `double` pattern.
+    TooWide,
+}
+
+/// Parses a `0x`-prefixed IEEE-754 bit pattern without any decimal rounding,
+/// so exact values such as specific NaN payloads reach the function unchanged.
+fn parse_float_bits(token: &str) -> Result<f32, FloatBitsError> {
+    let hex = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+        .filter(|hex| !hex.is_empty() && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or(FloatBitsError::Parse)?;
+    u32::from_str_radix(hex, 16)
+        .map(f32::from_bits)
+        .map_err(|_| FloatBitsError::TooWide)
+}
+
+/// Large enough for any supported return value, including libffi's
+/// widening of small integer returns to a full `ffi_arg`.
+#[repr(C, align(16))]
+struct ReturnBuffer([u8; 16]);
+
+/// Prepares `cif` again for `abi`. `Cif::set_abi` only swaps the field, but
+/// libffi lays out arguments when the CIF is prepared, so a convention that
+/// passes them differently needs a fresh `ffi_prep_cif`.
+fn prepare_abi(cif: &mut Cif, abi: libffi::low::ffi_abi) -> Result<(), String> {
+    let raw = cif.as_raw_ptr();
+    unsafe {
+        libffi::low::prep_cif(
+            raw,
+            abi,
+            (*raw).nargs as usize,
+            (*raw).rtype,
+            (*raw).arg_types,
+        )
+    }
+    .map_err(|e| format!("Failed to prepare call for ABI {}: {:?}", abi, e))
+}
+
+/// Makes the call. With `trace`, also records the ABI, each argument and
+/// the raw return as lines of `+trace` output.
+fn dynamic_invoke(
+    func_ptr: *const c_void,
+    signature: &FunctionSignature,
+    args: &[&str],
+    options: &CallOptions,
+    mut trace: Option<&mut Vec<String>>,
+) -> Result<String, String> {
+    // Out-parameters, including an array's length, are supplied by the server.
+    let server_supplied = |index: usize| {
+        !signature.param_modes[index].takes_argument()
+            || signature.array_length_param == Some(index)
+    };
+    let params = signature.param_types.len();
+    let expected = (0..params).filter(|&i| !server_supplied(i)).count();
+    if args.len() != expected {
+        // The parameters the client passes side by side with what it sent.
+        let params: Vec<String> = (0..params)
+            .filter(|&i| !server_supplied(i))
+            .map(|i| format_param(signature.param_types[i], &signature.param_modes[i]))
+            .collect();
+        return Err(format!(
+            "Expected {} arguments, got {}: expected ({}), got [{}]",
+            expected,
+            args.len(),
+            params.join(","),
+            args.join(",")
+        ));
+    }
+
+    let mut args = args.iter();
+    let mut coerced = Vec::new();
+    let values = signature
+        .param_types
+        .iter()
+        .zip(&signature.param_modes)
+        .enumerate()
+        .map(|(index, (&ty, mode))| {
+            if signature.array_length_param == Some(index) {
+                return Ok(ArgValue::slot(ArgValue::Int(0)));
+            }
+            if *mode == ParamMode::Out {
+                return Ok(ArgValue::slot(ArgValue::zero(ty)));
+            }
+            if let ParamMode::Struct(layout) = mode {
+                if layout.size > options.max_result_bytes {
+                    return Err(format!(
+                        "E_RESULT_TOO_LARGE struct parameter {} exceeds {} bytes",
+                        index + 1,
+                        options.max_result_bytes
+                    ));
+                }
+                return Ok(ArgValue::structure(layout.size));
+            }
+            if let ParamMode::Buffer { size, .. } = *mode {
+                if size > options.max_result_bytes {
+                    return Err(format!(
+                        "E_RESULT_TOO_LARGE buffer parameter {} exceeds {} bytes",
+                        index + 1,
+                        options.max_result_bytes
+                    ));
+                }
+                return Ok(ArgValue::buffer(size));
+            }
+            let token = args.next().expect("argument count checked above");
+            let value = match ArgValue::parse(ty, token, index + 1, options) {
+                // A coercion that does not fit either is refused as written.
+                Err(e) if options.lenient_args => {
+                    let (coercion, value) = coerce(ty, token)
+                        .and_then(|coercion| {
+                            let value = ArgValue::parse(ty, &coercion, index + 1, options).ok()?;
+                            Some((coercion, value))
+                        })
+                        .ok_or(e)?;
+                    coerced.push(format!("arg[{}]:{}->{}", index + 1, token, coercion));
+                    value
+                }
+                value => value?,
+            };
+            Ok(match mode {
+                ParamMode::InOut => ArgValue::slot(value),
+                _ => value,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let arg_types = values
+        .iter()
+        .zip(&signature.param_types)
+        .flat_map(|(value, &ty)| match value {
+            ArgValue::Slot { .. } => vec![Ok(Type::pointer())],
+            ArgValue::Counted { .. } => vec![Ok(Type::pointer()), Ok(Type::usize())],
+            _ => vec![ty.ffi_type()],
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let return_type = if options.discard_result {
+        SupportedType::Void
+    } else if signature.array_length_param.is_some() {
+        SupportedType::Ptr
+    } else {
+        signature.return_type
+    };
+    // Integer-class returns are received as the full 64-bit register value
+    // (EDX:EAX on 32-bit x86), so a trace can show bits the declared type
+    // drops; formatting then reads only the declared width.
+    let native_return = match return_type {
+        SupportedType::Int | SupportedType::Char | SupportedType::Str | SupportedType::Ptr => {
+            Type::u64()
+        }
+        _ => return_type.ffi_type()?,
+    };
+    let mut cif = Cif::new(arg_types.iter().cloned(), native_return);
+    prepare_abi(&mut cif, signature.calling_convention.abi()?)?;
+
+    if let Some(trace) = trace.as_mut() {
+        trace.push(format!(
+            "abi {} ffi_abi={} native_args={}",
+            signature.calling_convention,
+            signature.calling_convention.abi()?,
+            arg_types.len()
+        ));
+        for (index, value) in values.iter().enumerate() {
+            trace.push(format!(
+                "arg {} {} {}",
+                index + 1,
+                format_param(signature.param_types[index], &signature.param_modes[index]),
+                value.trace()
+            ));
+        }
+    }
+
+    if options.dry_run {
+        return Ok("DRYRUN resolved".into());
+    }
+
+    let ffi_args: Vec<Arg> = values.iter().flat_map(ArgValue::as_args).collect();
+    let code_ptr = CodePtr::from_ptr(func_ptr);
+    let call = || {
+        let started = Instant::now();
+        let result: ReturnBuffer = unsafe { cif.call(code_ptr, &ffi_args) };
+        (result, started.elapsed())
+    };
+    let ((result, elapsed), captured) = if options.capture {
+        let (call, captured) =
+            capture::capture(call).map_err(|e| format!("Cannot capture output: {}", e))?;
+        (call, Some(captured))
+    } else {
+        (call(), None)
+    };
+    if let Some(trace) = trace {
+        trace.push(match return_type {
+            SupportedType::Void => "return void".to_string(),
+            // The first eight bytes hold the whole integer register, and
+            // cover every other supported return.
+            _ => format!("return {} raw=0x{:016x}", return_type, unsafe {
+                *(result.0.as_ptr() as *const u64)
+            }),
+        });
+    }
+
+    let mut reply = if options.discard_result {
+        options.void_reply.text().to_string()
+    } else if let Some(index) = signature.array_length_param {
+        let array = unsafe { *(result.0.as_ptr() as *const *const c_void) };
+        let len = unsafe { *(values[index].slot_ptr() as *const i32) };
+        format_array(signature.return_type, array, len, options)?
+    } else {
+        format_return(signature.return_type, &result, options)?
+    };
+    if signature.null_status && !options.discard_result {
+        let returned = unsafe { *(result.0.as_ptr() as *const *const c_void) };
+        let status = if returned.is_null() { "error" } else { "ok" };
+        reply.push_str(&format!(" status={}", status));
+    }
+    // Each value read back is appended as `<position>=<value>`, each field
+    // of a struct as `<position>.<field>=<value>`.
+    for (index, value) in values.iter().enumerate() {
+        if !signature.param_modes[index].is_output() || signature.array_length_param == Some(index)
+        {
+            continue;
+        }
+        if let (ParamMode::Struct(layout), ArgValue::Struct { ptr, .. }) =
+            (&signature.param_modes[index], value)
+        {
+            for field in &layout.fields {
+                let text = unsafe { format_value(field.ty, ptr.byte_add(field.offset), options) }?;
+                if !reply.is_empty() {
+                    reply.push(' ');
+                }
+                reply.push_str(&format!("{}.{}={}", index + 1, field.name, text));
+            }
+            continue;
+        }
+        let text = match (&signature.param_modes[index], value) {
+            (&ParamMode::Buffer { format, .. }, ArgValue::Buffer { bytes, .. }) => {
+                format_buffer(bytes, format, options)?
+            }
+            _ => unsafe { format_value(signature.param_types[index], value.slot_ptr(), options) }?,
+        };
+        if !reply.is_empty() {
+            reply.push(' ');
+        }
+        reply.push_str(&format!("{}={}", index + 1, text));
+    }
+    if options.time {
+        reply = format!("result={} time_us={}", reply, elapsed.as_micros());
+    }
+    if let Some(captured) = captured {
+        for (stream, bytes) in [("stdout", captured.stdout), ("stderr", captured.stderr)] {
+            if bytes.len() > options.max_result_bytes {
+                return Err(format!(
+                    "E_RESULT_TOO_LARGE captured {} exceeds {} bytes",
+                    stream, options.max_result_bytes
+                ));
+            }
+            let text = capture::escape_field(&options.encoding.decode(&bytes)?);
+            if !reply.is_empty() {
+                reply.push(' ');
+            }
+            reply.push_str(&format!("{}={}", stream, text));
+        }
+    }
+    if !coerced.is_empty() {
+        if !reply.is_empty() {
+            reply.push(' ');
+        }
+        reply.push_str(&format!("coerced={}", coerced.join(",")));
+    }
+    Ok(reply)
+}
+
+/// The form of `token` that a parameter of type `ty` accepts, for a client
+/// that wrote it as the other numeric type: a decimal truncated toward zero
+/// for an `int`, e.g. `3.7` as `3`, or an integer literal in any base for a
+/// `float`, e.g. `0x10` as `16`. `None` if neither applies.
+fn coerce(ty: SupportedType, token: &str) -> Option<String> {
+    match ty {
+        SupportedType::Int => token
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .map(|value| value.trunc().to_string()),
+        SupportedType::Float => parse_int::<i64>(token)
+            .ok()
+            .map(|value| (value as f32).to_string()),
+        _ => None,
+    }
+}
+
+/// Formats `len` elements at `array` as `[a,b,...]`, or `null` for a null array.
+fn format_array(
+    ty: SupportedType,
+    array: *const c_void,
+    len: i32,
+    options: &CallOptions,
+) -> Result<String, String> {
+    if array.is_null() {
+        return Ok("null".into());
+    }
+    let len =
+        usize::try_from(len).map_err(|_| format!("Returned array length {} is negative", len))?;
+    let element_size = match ty {
+        SupportedType::Int => size_of::<i32>(),
+        _ => size_of::<f32>(),
+    };
+    if len.saturating_mul(element_size) > options.max_result_bytes {
+        return Err(format!(
+            "E_RESULT_TOO_LARGE returned array exceeds {} bytes",
+            options.max_result_bytes
+        ));
+    }
+    let elements: Vec<String> = match ty {
+        SupportedType::Int => unsafe { std::slice::from_raw_parts(array as *const i32, len) }
+            .iter()
+            .map(i32::to_string)
+            .collect(),
+        _ => unsafe { std::slice::from_raw_parts(array as *const f32, len) }
+            .iter()
+            .map(f32::to_string)
+            .collect(),
+    };
+    Ok(format!("[{}]", elements.join(",")))
+}
+
+/// Formats the contents of a `buf:` parameter after the call.
+fn format_buffer(
+    bytes: &[u8],
+    format: BufferFormat,
+    options: &CallOptions,
+) -> Result<String, String> {
+    match format {
+        BufferFormat::Hex => Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect()),
+        BufferFormat::Str => {
+            let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            Ok(escape_line(&options.encoding.decode(&bytes[..len])?))
+        }
+    }
+}
+
+fn format_return(
+    ty: SupportedType,
+    result: &ReturnBuffer,
+    options: &CallOptions,
+) -> Result<String, String> {
+    let ptr = result.0.as_ptr() as *const c_void;
+    // Integer returns hold the whole register; the declared value is in its
+    // low bits, which on these little-endian hosts come first.
+    let widened = unsafe { *(ptr as *const libffi::raw::ffi_arg) };
+    match ty {
+        SupportedType::Int => {
+            let value = widened as i32;
+            let name = options
+                .return_names
+                .as_ref()
+                .and_then(|names| names.get(&value));
+            Ok(match name {
+                Some(name) => format!("{} ({})", format_int(value, options), name),
+                None => format_int(value, options),
+            })
+        }
+        SupportedType::Char => Ok(format_char(widened as i8, options)),
+        SupportedType::Void => Ok(options.void_reply.text().into()),
+        _ => unsafe { format_value(ty, ptr, options) },
+    }
+}
+
+/// Formats the value of type `ty` stored at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must point to a value of `ty`, which need not be aligned, as in a
+/// packed struct; for `str`, a null or readable string pointer.
+unsafe fn format_value(
+    ty: SupportedType,
+    ptr: *const c_void,
+    options: &CallOptions,
+) -> Result<String, String> {
+    match ty {
+        SupportedType::Int => Ok(format_int(
+            unsafe { (ptr as *const i32).read_unaligned() },
+            options,
+        )),
+        SupportedType::Char => Ok(format_char(
+            unsafe { (ptr as *const i8).read_unaligned() },
+            options,
+        )),
+        SupportedType::Float if options.float_bits => Ok(format!(
+            "0x{:08x}",
+            unsafe { (ptr as *const f32).read_unaligned() }.to_bits()
+        )),
+        SupportedType::Float => Ok(unsafe { (ptr as *const f32).read_unaligned() }.to_string()),
+        SupportedType::Void => Ok(options.void_reply.text().into()),
+        SupportedType::Str => {
+            let str_ptr = unsafe { (ptr as *const *const c_char).read_unaligned() };
+            if str_ptr.is_null() {
+                return Ok("null".into());
+            }
+            let bytes = unsafe { read_c_string(str_ptr, options.max_result_bytes) }?;
+            Ok(escape_line(&options.encoding.decode(bytes)?))
+        }
+        SupportedType::Ptr => Ok(format_pointer(unsafe {
+            (ptr as *const *const c_void).read_unaligned()
+        })),
+        SupportedType::StrLen { .. } | SupportedType::Handle => {
+            unreachable!("{} is neither returned nor read back", ty)
+        }
+        SupportedType::LongDouble => {
+            Ok(unsafe { (ptr as *const LongDouble).read_unaligned() }.to_string())
+        }
+    }
+}
+
+fn format_int(value: i32, options: &CallOptions) -> String {
+    if options.hex {
+        format!("{} (0x{:08X})", value, value as u32)
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_char(value: i8, options: &CallOptions) -> String {
+    let text = if options.char_signed {
+        value.to_string()
+    } else {
+        (value as u8).to_string()
+    };
+    if options.hex {
+        format!("{} (0x{:02X})", text, value as u8)
+    } else {
+        text
+    }
+}
+
+/// Parses a numeric `char` in the range its signedness allows, keeping the bits.
+fn parse_char(token: &str, signed: bool) -> Result<i8, IntError> {
+    if signed {
+        parse_int(token)
+    } else {
+        parse_int::<u8>(token).map(|v| v as i8)
+    }
+}
+
+/// Reads a null-terminated string without scanning past `limit` bytes, so an
+/// unterminated or enormous buffer cannot run the server through memory.
+///
+/// # Safety
+///
+/// `ptr` must be readable up to its terminator or `limit` bytes, whichever comes first.
+unsafe fn read_c_string<'a>(ptr: *const c_char, limit: usize) -> Result<&'a [u8], String> {
+    let mut len = 0;
+    while unsafe { *ptr.add(len) } != 0 {
+        len += 1;
+        if len > limit {
+            return Err(format!(
+                "E_RESULT_TOO_LARGE returned string exceeds {} bytes",
+                limit
+            ));
+        }
+    }
+    Ok(unsafe { std::slice::from_raw_parts(ptr as *const u8, len) })
+}
+
+/// Escapes characters that would break the one-line-per-response framing.
+fn escape_line(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Calls `name` in `lib`. With `+trace`, the reply is `TRACE <N>`, the `N`
+/// lines of the trace and then the usual reply, separated by newlines.
+pub fn invoke_function(
+    lib: &LoadedLibrary,
+    name: &str,
+    signature: &FunctionSignature,
+    args: &[&str],
+    options: &CallOptions,
+) -> Result<String, String> {
+    let func_ptr = lib.symbol(name)?;
+    let _guard = lib.lock_for_call(name);
+    let _permit = lib.enter_call()?;
+    let symbol = if options.trace {
+        format!(
+            "symbol {} export={} address={}",
+            name,
+            lib.export_name(name)?,
+            format_pointer(func_ptr)
+        )
+    } else {
+        String::new()
+    };
+    invoke_traced(func_ptr, symbol, signature, args, options)
+}
+
+/// Calls the code at `func_ptr` directly, without resolving a symbol, for
+/// `callat`. No library's locks or call limit apply.
+pub fn invoke_address(
+    func_ptr: *const c_void,
+    signature: &FunctionSignature,
+    args: &[&str],
+    options: &CallOptions,
+) -> Result<String, String> {
+    let symbol = format!("symbol - address={}", format_pointer(func_ptr));
+    invoke_traced(func_ptr, symbol, signature, args, options)
+}
+
+/// Makes the call, preceded by a trace starting with the `symbol` line if
+/// the options ask for one.
+fn invoke_traced(
+    func_ptr: *const c_void,
+    symbol: String,
+    signature: &FunctionSignature,
+    args: &[&str],
+    options: &CallOptions,
+) -> Result<String, String> {
+    if !options.trace {
+        return dynamic_invoke(func_ptr, signature, args, options, None);
+    }
+    let mut trace = vec![format!("signature {}", signature), symbol];
+    let reply = dynamic_invoke(func_ptr, signature, args, options, Some(&mut trace))?;
+    Ok(format!(
+        "TRACE {}\n{}\n{}",
+        trace.len(),
+        trace.join("\n"),
+        reply
+    ))
+}
+
+/// Runs `call` on a worker thread and stops waiting after `timeout`.
+///
+/// Foreign code cannot be stopped, so a call that overruns keeps running in
+/// the background; only the client is released. With `interrupt`, the worker
+/// is also sent `SIGALRM`, which ends a blocking system call it is in early
+/// if the call can be interrupted, and the function may then return.
+pub fn invoke_with_timeout(
+    call: impl FnOnce() -> Result<String, String> + Send + 'static,
+    timeout: Duration,
+    interrupt: bool,
+) -> Result<String, String> {
+    let (tx, rx) = mpsc::channel();
+    let call_thread = Arc::new(CallThread::default());
+    let worker = Arc::clone(&call_thread);
+    thread::spawn(move || {
+        worker.enter();
+        let result = call();
+        worker.leave();
+        tx.send(result).ok();
+    });
+
+    rx.recv_timeout(timeout).map_err(|e| match e {
+        mpsc::RecvTimeoutError::Timeout => {
+            if interrupt {
+                call_thread.interrupt();
+            }
+            format!(
+                "E_TIMEOUT call did not complete within {} ms",
+                timeout.as_millis()
+            )
+        }
+        mpsc::RecvTimeoutError::Disconnected => "Call worker exited without a result".into(),
+    })?
+}
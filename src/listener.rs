@@ -0,0 +1,418 @@
+//! Listening sockets and the client connections they accept, over TCP or,
+//! on Unix, a Unix domain socket.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Binds a listening socket to `addr`, setting `SO_REUSEADDR` to
+/// `reuse_address` before the bind.
+///
+/// With the option set, a restarted server can bind its port while
+/// connections of the previous run are still in `TIME_WAIT`.
+#[cfg(unix)]
+pub fn bind(addr: &str, reuse_address: bool) -> io::Result<TcpListener> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no addresses to bind");
+    for addr in addr.to_socket_addrs()? {
+        let family = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Owned from here on, so every error path closes it.
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        match configure(fd, addr, reuse_address) {
+            Ok(()) => return Ok(TcpListener::from(socket)),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Binds with the platform's defaults; `SO_REUSEADDR` is not set where it
+/// would let other sockets take over the port.
+#[cfg(not(unix))]
+pub fn bind(addr: &str, _reuse_address: bool) -> io::Result<TcpListener> {
+    TcpListener::bind(addr)
+}
+
+#[cfg(unix)]
+fn configure(fd: libc::c_int, addr: SocketAddr, reuse_address: bool) -> io::Result<()> {
+    let check = |result: libc::c_int| {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+    check(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    let enable = libc::c_int::from(reuse_address);
+    check(unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &enable as *const libc::c_int as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })?;
+
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+            size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_scope_id = v6.scope_id();
+            size_of::<libc::sockaddr_in6>()
+        }
+    };
+    check(unsafe {
+        libc::bind(
+            fd,
+            &storage as *const _ as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    })?;
+    check(unsafe { libc::listen(fd, 128) })
+}
+
+/// Where to listen, as given to `--listen`: `tcp://<host>:<port>` or
+/// `unix://<path>` (Unix only).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenSpec {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenSpec {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            Ok(ListenSpec::Tcp(addr.to_string()))
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            if !cfg!(unix) {
+                return Err("Unix sockets are not supported on this platform".into());
+            }
+            if path.is_empty() {
+                return Err(format!("Missing socket path: {}", s));
+            }
+            Ok(ListenSpec::Unix(PathBuf::from(path)))
+        } else {
+            Err(format!(
+                "Invalid listener: {} (expected tcp://<host>:<port> or unix://<path>)",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for ListenSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenSpec::Tcp(addr) => write!(f, "tcp://{}", addr),
+            ListenSpec::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// A bound listening socket of either transport.
+pub enum Listener {
+    Tcp(TcpListener),
+    /// A socket file the server created is removed again when the listener
+    /// is dropped; one inherited from a service manager is left alone.
+    #[cfg(unix)]
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+impl Listener {
+    /// Binds `spec`. A stale socket file left at a Unix path by an earlier
+    /// run is replaced; any other file there is an error.
+    pub fn bind(spec: &ListenSpec, reuse_address: bool) -> io::Result<Listener> {
+        match spec {
+            ListenSpec::Tcp(addr) => bind(addr, reuse_address).map(Listener::Tcp),
+            #[cfg(unix)]
+            ListenSpec::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                match std::fs::symlink_metadata(path) {
+                    Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            "path exists and is not a socket",
+                        ));
+                    }
+                    Err(_) => {}
+                }
+                let listener = UnixListener::bind(path)?;
+                Ok(Listener::Unix(listener, Some(path.clone())))
+            }
+            #[cfg(not(unix))]
+            ListenSpec::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+        }
+    }
+
+    /// The TCP port, for a TCP listener.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok().map(|addr| addr.port()),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+
+    /// What a client connects to in order to reach this listener, so a
+    /// shutdown can wake a thread blocked in `accept`.
+    pub fn wake_address(&self) -> io::Result<WakeAddress> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(WakeAddress::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener
+                .local_addr()?
+                .as_pathname()
+                .map(|path| WakeAddress::Unix(path.to_path_buf()))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "unnamed socket")),
+        }
+    }
+}
+
+/// The first file descriptor systemd passes (`SD_LISTEN_FDS_START`).
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: libc::c_int = 3;
+
+/// The listening sockets handed over by systemd socket activation: the
+/// `LISTEN_FDS` descriptors starting at fd 3, provided `LISTEN_PID` names
+/// this process. Empty when the variables are absent or meant for another
+/// process, so the caller binds its own listeners instead.
+///
+/// The caller should remove the variables afterwards so that processes it
+/// starts do not take the sockets for their own.
+#[cfg(target_os = "linux")]
+pub fn inherited() -> Result<Vec<Listener>, String> {
+    use std::os::fd::FromRawFd;
+
+    let pid = std::env::var("LISTEN_PID").ok();
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let Ok(count) = std::env::var("LISTEN_FDS") else {
+        return Ok(Vec::new());
+    };
+    let count: libc::c_int = count
+        .parse()
+        .map_err(|_| format!("Invalid LISTEN_FDS: {}", count))?;
+    (LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(count))
+        .map(|fd| {
+            let family = listening_family(fd)
+                .map_err(|e| format!("Inherited fd {} is not a listening socket: {}", fd, e))?;
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+                return Err(format!("fd {}: {}", fd, io::Error::last_os_error()));
+            }
+            // Owned from here on: the service manager passed it to this process alone.
+            Ok(match family {
+                libc::AF_UNIX => Listener::Unix(unsafe { UnixListener::from_raw_fd(fd) }, None),
+                _ => Listener::Tcp(unsafe { TcpListener::from_raw_fd(fd) }),
+            })
+        })
+        .collect()
+}
+
+/// Socket activation is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn inherited() -> Result<Vec<Listener>, String> {
+    Ok(Vec::new())
+}
+
+/// The address family of `fd`, if it is a listening stream socket of a
+/// family the server can serve.
+#[cfg(target_os = "linux")]
+fn listening_family(fd: libc::c_int) -> io::Result<libc::c_int> {
+    let option = |name: libc::c_int| {
+        let mut value: libc::c_int = 0;
+        let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                name,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(value)
+        }
+    };
+    let unsuitable = |what: &str| io::Error::new(io::ErrorKind::InvalidInput, what.to_string());
+    if option(libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return Err(unsuitable("not a stream socket"));
+    }
+    if option(libc::SO_ACCEPTCONN)? == 0 {
+        return Err(unsuitable("not listening"));
+    }
+    match option(libc::SO_DOMAIN)? {
+        family @ (libc::AF_INET | libc::AF_INET6 | libc::AF_UNIX) => Ok(family),
+        _ => Err(unsuitable("unsupported address family")),
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, Some(path)) = self {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// The address of a bound [`Listener`].
+pub enum WakeAddress {
+    Tcp(std::net::SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl WakeAddress {
+    /// Connects and disconnects at once, unblocking the listener's `accept`.
+    pub fn wake(&self) {
+        match self {
+            WakeAddress::Tcp(addr) => {
+                TcpStream::connect(addr).ok();
+            }
+            #[cfg(unix)]
+            WakeAddress::Unix(path) => {
+                UnixStream::connect(path).ok();
+            }
+        }
+    }
+}
+
+/// An accepted client connection of either transport.
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    /// Sets `TCP_NODELAY`; Unix sockets have no Nagle delay to turn off.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+
+    /// Whether `TCP_NODELAY` is in effect.
+    pub fn nodelay(&self) -> bool {
+        match self {
+            Stream::Tcp(stream) => stream.nodelay().unwrap_or(false),
+            #[cfg(unix)]
+            Stream::Unix(_) => false,
+        }
+    }
+
+    /// Shuts down both directions; the client sees the connection close.
+    pub fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(std::net::Shutdown::Both),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(std::net::Shutdown::Both),
+        }
+    }
+
+    /// The client's address: `<ip>:<port>` over TCP, the client socket's
+    /// path over a Unix socket, or `None` if it has none (Unix clients
+    /// usually do not bind one).
+    pub fn peer(&self) -> Option<String> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().ok().map(|addr| addr.to_string()),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream
+                .peer_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(|p| p.display().to_string())),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
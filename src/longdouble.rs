@@ -0,0 +1,454 @@
+//! C `long double`, whose format depends on the platform: the x87 80-bit
+//! extended format on x86 and x86-64 (except with MSVC), the same format as
+//! `double` with MSVC and on Apple arm64, and something the server cannot
+//! handle (such as IEEE quad precision) elsewhere. The format is chosen when
+//! the server is built.
+//!
+//! Decimal text is converted exactly, with big-integer arithmetic, so a value
+//! keeps the precision that `float` and `double` would lose.
+
+use libffi::middle::Type;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// How the host lays out a `long double`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    /// 64-bit mantissa with an explicit integer bit, 15-bit exponent, sign.
+    X87,
+    /// Identical to `double`.
+    Double,
+    Unsupported,
+}
+
+const HOST: Format = if cfg!(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(target_env = "msvc")
+)) {
+    Format::X87
+} else if cfg!(any(
+    target_env = "msvc",
+    all(target_vendor = "apple", target_arch = "aarch64")
+)) {
+    Format::Double
+} else {
+    Format::Unsupported
+};
+
+/// The libffi type of the host's `long double`, if the server can marshal it.
+pub fn ffi_type() -> Option<Type> {
+    ffi_type_for(HOST)
+}
+
+/// The libffi type of a `long double` laid out as `format`.
+fn ffi_type_for(format: Format) -> Option<Type> {
+    match format {
+        #[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
+        Format::X87 => Some(Type::longdouble()),
+        Format::Double => Some(Type::f64()),
+        _ => None,
+    }
+}
+
+/// A `long double` in the host's format, in storage large and aligned enough
+/// for any of them.
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+pub struct LongDouble([u8; 16]);
+
+impl LongDouble {
+    pub const ZERO: LongDouble = LongDouble([0; 16]);
+
+    /// Parses decimal text as Rust parses floats (`-1.5e-3`, `inf`, `nan`),
+    /// rounding to the nearest value the host's format can hold.
+    pub fn parse(text: &str) -> Option<LongDouble> {
+        match HOST {
+            Format::X87 => parse_x87(text),
+            _ => {
+                let value: f64 = text.parse().ok()?;
+                let mut bytes = [0; 16];
+                bytes[..8].copy_from_slice(&value.to_ne_bytes());
+                Some(LongDouble(bytes))
+            }
+        }
+    }
+
+    /// The bytes that make up the value, most significant first, as hex.
+    pub fn raw_hex(&self) -> String {
+        let len = match HOST {
+            Format::X87 => 10,
+            _ => 8,
+        };
+        let mut bytes = self.0[..len].to_vec();
+        if cfg!(target_endian = "little") {
+            bytes.reverse();
+        }
+        let digits: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("0x{}", digits)
+    }
+}
+
+impl fmt::Display for LongDouble {
+    /// Up to 21 significant digits for x87 values, enough to tell every one
+    /// apart; `double`-format values print as `f64` does.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match HOST {
+            Format::X87 => f.write_str(&format_x87(self)),
+            _ => {
+                let value = f64::from_ne_bytes(self.0[..8].try_into().unwrap());
+                write!(f, "{}", value)
+            }
+        }
+    }
+}
+
+const X87_BIAS: i64 = 16383;
+const X87_MAX_EXPONENT: i64 = 0x7fff;
+/// Significant digits printed for x87 values.
+const X87_DIGITS: usize = 21;
+
+fn encode_x87(negative: bool, exponent: i64, mantissa: u64) -> LongDouble {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&mantissa.to_le_bytes());
+    let top = (u16::from(negative) << 15) | exponent as u16;
+    bytes[8..10].copy_from_slice(&top.to_le_bytes());
+    LongDouble(bytes)
+}
+
+fn parse_x87(text: &str) -> Option<LongDouble> {
+    let (negative, body) = match text.as_bytes().first()? {
+        b'-' => (true, &text[1..]),
+        b'+' => (false, &text[1..]),
+        _ => (false, text),
+    };
+    match body.to_ascii_lowercase().as_str() {
+        "inf" | "infinity" => return Some(encode_x87(negative, X87_MAX_EXPONENT, 1 << 63)),
+        "nan" => return Some(encode_x87(false, X87_MAX_EXPONENT, 0xc000_0000_0000_0000)),
+        _ => {}
+    }
+
+    let (number, exponent) = match body.find(['e', 'E']) {
+        Some(at) => (&body[..at], body[at + 1..].parse::<i64>().ok()?),
+        None => (body, 0),
+    };
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    let digits = whole.bytes().chain(fraction.bytes());
+    let mut significand = BigUint::default();
+    let mut significant_digits = 0i64;
+    for digit in digits {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        if significand.is_zero() && digit == b'0' {
+            continue;
+        }
+        significand.mul_add(10, u32::from(digit - b'0'));
+        significant_digits += 1;
+    }
+    let zero = encode_x87(negative, 0, 0);
+    if significand.is_zero() {
+        return Some(zero);
+    }
+    let exponent = exponent.checked_sub(fraction.len() as i64)?;
+    // Far outside the format's range (about 3.6e-4951 to 1.2e4932), skip the
+    // arithmetic.
+    let magnitude = exponent.saturating_add(significant_digits);
+    if magnitude > 4940 {
+        return Some(encode_x87(negative, X87_MAX_EXPONENT, 1 << 63));
+    }
+    if magnitude < -4960 {
+        return Some(zero);
+    }
+
+    // The value is exactly numerator / denominator.
+    let mut numerator = significand;
+    let mut denominator = BigUint::from(1);
+    if exponent >= 0 {
+        numerator.mul_pow(10, exponent as u32);
+    } else {
+        denominator.mul_pow(10, (-exponent) as u32);
+    }
+
+    // Scale by 2^shift so the quotient has 65 bits: the mantissa and a
+    // rounding bit.
+    let mut shift = 65 - (numerator.bit_len() as i64 - denominator.bit_len() as i64);
+    let (mut quotient, mut sticky) = divide_scaled(&numerator, &denominator, shift);
+    if quotient >> 65 != 0 {
+        sticky |= quotient & 1 != 0;
+        quotient >>= 1;
+        shift -= 1;
+    }
+    // The quotient's top bit has weight 2^(64 - shift).
+    let mut biased = 64 - shift + X87_BIAS;
+    let dropped = if biased >= 1 { 1 } else { 1 - biased + 1 };
+    if biased < 1 {
+        biased = 0;
+    }
+    if dropped >= 128 {
+        return Some(zero);
+    }
+    let mut mantissa = (quotient >> dropped) as u64;
+    let round = (quotient >> (dropped - 1)) & 1 != 0;
+    sticky |= quotient & ((1u128 << (dropped - 1)) - 1) != 0;
+    if round && (sticky || mantissa & 1 != 0) {
+        mantissa = mantissa.wrapping_add(1);
+        if mantissa == 0 {
+            mantissa = 1 << 63;
+            biased += 1;
+        } else if biased == 0 && mantissa == 1 << 63 {
+            // Rounded up out of the subnormal range.
+            biased = 1;
+        }
+    }
+    if biased >= X87_MAX_EXPONENT {
+        return Some(encode_x87(negative, X87_MAX_EXPONENT, 1 << 63));
+    }
+    Some(encode_x87(negative, biased, mantissa))
+}
+
+/// `numerator * 2^shift / denominator`, which must fit in 128 bits, and
+/// whether the division left a remainder.
+fn divide_scaled(numerator: &BigUint, denominator: &BigUint, shift: i64) -> (u128, bool) {
+    let mut remainder = numerator.clone();
+    let mut divisor = denominator.clone();
+    if shift >= 0 {
+        remainder.shl(shift as usize);
+    } else {
+        divisor.shl((-shift) as usize);
+    }
+    let mut quotient = 0u128;
+    let bits = remainder.bit_len().saturating_sub(divisor.bit_len());
+    for bit in (0..=bits).rev() {
+        let mut shifted = divisor.clone();
+        shifted.shl(bit);
+        if remainder.cmp(&shifted) != Ordering::Less {
+            remainder.sub(&shifted);
+            quotient |= 1 << bit;
+        }
+    }
+    (quotient, !remainder.is_zero())
+}
+
+fn format_x87(value: &LongDouble) -> String {
+    let mantissa = u64::from_le_bytes(value.0[..8].try_into().unwrap());
+    let top = u16::from_le_bytes(value.0[8..10].try_into().unwrap());
+    let sign = if top >> 15 != 0 { "-" } else { "" };
+    let exponent = i64::from(top & 0x7fff);
+    if exponent == X87_MAX_EXPONENT {
+        return if mantissa << 1 == 0 {
+            format!("{}inf", sign)
+        } else {
+            "NaN".into()
+        };
+    }
+    if mantissa == 0 {
+        return format!("{}0", sign);
+    }
+
+    // The value is exactly mantissa * 2^power = digits * 10^decimal_exponent.
+    let power = exponent.max(1) - X87_BIAS - 63;
+    let mut exact = BigUint::from(mantissa);
+    let mut decimal_exponent = 0;
+    if power >= 0 {
+        exact.shl(power as usize);
+    } else {
+        exact.mul_pow(5, (-power) as u32);
+        decimal_exponent = power;
+    }
+    let mut digits = exact.to_decimal().into_bytes();
+
+    if digits.len() > X87_DIGITS {
+        let round_up = digits[X87_DIGITS] >= b'5';
+        decimal_exponent += (digits.len() - X87_DIGITS) as i64;
+        digits.truncate(X87_DIGITS);
+        if round_up {
+            let mut index = X87_DIGITS;
+            loop {
+                if index == 0 {
+                    digits.insert(0, b'1');
+                    digits.pop();
+                    decimal_exponent += 1;
+                    break;
+                }
+                index -= 1;
+                if digits[index] == b'9' {
+                    digits[index] = b'0';
+                } else {
+                    digits[index] += 1;
+                    break;
+                }
+            }
+        }
+    }
+    while digits.last() == Some(&b'0') {
+        digits.pop();
+        decimal_exponent += 1;
+    }
+    let digits = String::from_utf8(digits).unwrap();
+
+    // Digits before the decimal point.
+    let point = digits.len() as i64 + decimal_exponent;
+    if (1..=X87_DIGITS as i64).contains(&point) {
+        let point = point as usize;
+        if point >= digits.len() {
+            format!("{}{}{}", sign, digits, "0".repeat(point - digits.len()))
+        } else {
+            format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+        }
+    } else if (-5..=0).contains(&point) {
+        format!("{}0.{}{}", sign, "0".repeat((-point) as usize), digits)
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let rest = if rest.is_empty() {
+            String::new()
+        } else {
+            format!(".{}", rest)
+        };
+        format!("{}{}{}e{}", sign, first, rest, point - 1)
+    }
+}
+
+/// A minimal arbitrary-precision unsigned integer, little-endian base-2^32.
+#[derive(Clone, Default)]
+struct BigUint(Vec<u32>);
+
+impl From<u64> for BigUint {
+    fn from(value: u64) -> Self {
+        let mut n = BigUint(vec![value as u32, (value >> 32) as u32]);
+        n.trim();
+        n
+    }
+}
+
+impl BigUint {
+    fn trim(&mut self) {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bit_len(&self) -> usize {
+        match self.0.last() {
+            Some(top) => 32 * self.0.len() - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    /// `self = self * factor + addend`.
+    fn mul_add(&mut self, factor: u32, addend: u32) {
+        let mut carry = u64::from(addend);
+        for limb in &mut self.0 {
+            let product = u64::from(*limb) * u64::from(factor) + carry;
+            *limb = product as u32;
+            carry = product >> 32;
+        }
+        if carry != 0 {
+            self.0.push(carry as u32);
+        }
+    }
+
+    /// Multiplies by `base^exponent`, in steps that fit a limb.
+    fn mul_pow(&mut self, base: u32, mut exponent: u32) {
+        let mut step = 1u32;
+        let mut step_power = 0;
+        while let Some(next) = step.checked_mul(base) {
+            step = next;
+            step_power += 1;
+        }
+        while exponent >= step_power {
+            self.mul_add(step, 0);
+            exponent -= step_power;
+        }
+        self.mul_add(base.pow(exponent), 0);
+    }
+
+    fn shl(&mut self, bits: usize) {
+        if self.is_zero() {
+            return;
+        }
+        let (limbs, bits) = (bits / 32, bits % 32);
+        if bits != 0 {
+            let mut carry = 0;
+            for limb in &mut self.0 {
+                let shifted = (*limb << bits) | carry;
+                carry = *limb >> (32 - bits);
+                *limb = shifted;
+            }
+            if carry != 0 {
+                self.0.push(carry);
+            }
+        }
+        self.0.splice(0..0, std::iter::repeat_n(0, limbs));
+    }
+
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+
+    /// `self -= other`, which must not exceed `self`.
+    fn sub(&mut self, other: &BigUint) {
+        let mut borrow = 0i64;
+        for (index, limb) in self.0.iter_mut().enumerate() {
+            let mut difference =
+                i64::from(*limb) - i64::from(other.0.get(index).copied().unwrap_or(0)) - borrow;
+            borrow = 0;
+            if difference < 0 {
+                difference += 1 << 32;
+                borrow = 1;
+            }
+            *limb = difference as u32;
+        }
+        self.trim();
+    }
+
+    fn to_decimal(&self) -> String {
+        const CHUNK: u64 = 1_000_000_000;
+        let mut limbs = self.0.clone();
+        let mut chunks = Vec::new();
+        while !limbs.is_empty() {
+            let mut remainder = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let value = (remainder << 32) | u64::from(*limb);
+                *limb = (value / CHUNK) as u32;
+                remainder = value % CHUNK;
+            }
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+            chunks.push(remainder);
+        }
+        let mut text = chunks.pop().map_or("0".to_string(), |c| c.to_string());
+        for chunk in chunks.iter().rev() {
+            text.push_str(&format!("{:09}", chunk));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_formats_have_no_ffi_type() {
+        assert!(ffi_type_for(Format::Unsupported).is_none());
+        assert!(ffi_type_for(Format::Double).is_some());
+    }
+
+    #[test]
+    fn invalid_text_is_not_a_long_double() {
+        for text in ["", ".", "1.2.3", "1e", "0x10", "--1"] {
+            assert!(LongDouble::parse(text).is_none(), "{}", text);
+        }
+    }
+}
@@ -1,225 +1,1823 @@
-use libloading::Library;
+mod access_log;
+mod affinity;
+mod base64;
+mod capture;
+mod config;
+mod debuginfo;
+mod demangle;
+mod encoding;
+mod exports;
+mod fault;
+mod gzip;
+mod handles;
+mod integer;
+mod interactive;
+mod invoke;
+mod listener;
+mod longdouble;
+mod metrics;
+mod privileges;
+mod rate_limit;
+mod registry;
+mod schema;
+mod signals;
+mod signature;
+
+use access_log::{AccessEntry, AccessLog};
+use config::Config;
+use fault::Fault;
+use invoke::{CallOptions, VoidReply, invoke_address, invoke_function, invoke_with_timeout};
+use listener::{Listener, Stream, WakeAddress};
+use rate_limit::TokenBucket;
+use registry::{LibraryRegistry, LoadOptions, LoadedLibrary};
+use schema::{Ownership, Schema};
+use signature::{FunctionSignature, extract_signature, parse_signature};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::env::args;
-use std::ffi::CString;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
-use std::str::FromStr;
-use std::sync::Arc;
+use std::ffi::c_void;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// State shared by every client connection.
+struct Server {
+    config: Config,
+    libraries: LibraryRegistry,
+    schema: RwLock<Schema>,
+    access_log: Option<AccessLog>,
+    /// Files opened with `admin open`, for `handle` arguments.
+    handles: Arc<handles::Handles>,
+    /// Token that `auth` must be given to unlock `admin` commands.
+    admin_token: Option<String>,
+    stats: Stats,
+    /// Where each listener is bound, so a shutdown can wake its accept loop.
+    wake_addresses: Vec<WakeAddress>,
+    shutting_down: AtomicBool,
+}
+
+/// Counters reported by `admin stats` and `--metrics-port`.
+struct Stats {
+    started: Instant,
+    connections: AtomicU64,
+    /// Connections open right now.
+    active_connections: AtomicUsize,
+    requests: AtomicU64,
+    /// `call` and `callat` requests, a subset of `requests`.
+    calls: AtomicU64,
+    errors: AtomicU64,
+    /// Requests being handled right now; a shutdown waits for these.
+    in_flight: AtomicUsize,
+    /// How long requests took from being read to their reply being ready.
+    latency: metrics::Histogram,
+}
+
+/// Counts a connection as active until it is dropped.
+struct ActiveConnection<'a>(&'a Stats);
+
+impl<'a> ActiveConnection<'a> {
+    fn new(stats: &'a Stats) -> Self {
+        stats.active_connections.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection(stats)
+    }
+}
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Exit codes for failures at startup, following BSD `sysexits.h`, so a
+/// supervisor can tell a retryable failure (`BIND`) from one that needs a
+/// fix (`USAGE`, `CONFIG`).
+mod exit_code {
+    /// Invalid command line.
+    pub const USAGE: i32 = 64;
+    /// The admin token file cannot be read, or `--working-dir` cannot be entered.
+    pub const NO_INPUT: i32 = 66;
+    /// The library cannot be loaded.
+    pub const LOAD: i32 = 69;
+    /// The listening socket cannot be bound.
+    pub const BIND: i32 = 70;
+    /// The access log cannot be opened.
+    pub const CANT_CREATE: i32 = 73;
+    /// Privileges cannot be dropped.
+    pub const NO_PERMISSION: i32 = 77;
+    /// The schema or admin token is invalid.
+    pub const CONFIG: i32 = 78;
+}
+
+/// How long a shutdown waits for in-flight requests before exiting anyway.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the server knows about the connection a request arrived on.
+struct Client {
+    /// The client's address, if its transport gives it one.
+    peer: Option<String>,
+    /// Whether `TCP_NODELAY` is in effect on the connection.
+    nodelay: bool,
+    /// Set once the connection has sent the admin token with `auth`.
+    admin: Cell<bool>,
+    /// The connection's allowance under `--rate-limit`.
+    rate: Option<RefCell<TokenBucket>>,
+    /// Addresses returned by schema `allocates` functions and not yet passed
+    /// to a `frees` function, with the function that returned each.
+    allocations: RefCell<BTreeMap<usize, String>>,
+    /// Set once the client has offered gzip with `caps compress=gzip`.
+    gzip: Cell<bool>,
+    /// Signatures bound with `bind`, keyed by the function as the client
+    /// names it, e.g. `alias!function`.
+    bindings: RefCell<HashMap<String, FunctionSignature>>,
+    /// Requests received so far, which `--fault-inject` schedules count.
+    requests: Cell<u64>,
+    /// The connection's most recent calls, at most `--history-size`, oldest first.
+    history: RefCell<VecDeque<HistoryEntry>>,
+    /// Calls recorded so far, which numbers them.
+    calls_made: Cell<u64>,
+}
+
+/// A call as `history` lists it and `replay` repeats it.
+struct HistoryEntry {
+    number: u64,
+    /// The request as the client sent it, from `call` or `callat` on.
+    tokens: Vec<String>,
+    /// The request with the schema's `redact` arguments hidden.
+    shown: String,
+    /// The result, or its last line for a `+trace`.
+    reply: Result<String, String>,
+}
 
-#[derive(Debug)]
-enum SupportedType {
-    Int,
-    Float,
-    Char,
-    Void,
+/// Replies shorter than this are sent as they are even to a client that
+/// accepts compression; encoding them would save little or nothing.
+const COMPRESS_MIN_BYTES: usize = 256;
+
+/// A protocol command: its name, its usage as reported by `help`, and what
+/// runs it, given the request's tokens from the name on.
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    run: fn(&Server, &Client, &[&str]) -> Result<String, String>,
+}
+
+/// The protocol's commands, which requests are dispatched through and `help`
+/// and `caps` report.
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "call",
+        usage: "call [modifiers] <function> [sig:<signature>] [args...]",
+        run: call_command,
+    },
+    Command {
+        name: "callat",
+        usage: "callat [modifiers] <address> sig:<signature> [args...]",
+        run: call_command,
+    },
+    Command {
+        name: "batch",
+        usage: "batch <call>; <call>...",
+        run: batch_command,
+    },
+    Command {
+        name: "bench",
+        usage: "bench [modifiers] <function> [sig:<signature>] [args...] times:<N>",
+        run: bench_command,
+    },
+    Command {
+        name: "bind",
+        usage: "bind <function> sig:<signature>",
+        run: bind_command,
+    },
+    Command {
+        name: "check",
+        usage: "check <function> [sig:<signature>] [args...]",
+        run: check_command,
+    },
+    Command {
+        name: "describe",
+        usage: "describe <function>",
+        run: describe_command,
+    },
+    Command {
+        name: "upload",
+        usage: "upload [bind:now|bind:lazy] [scope:global|scope:local] <alias> <base64>",
+        run: |server, _, tokens| upload_command(server, tokens),
+    },
+    Command {
+        name: "addrs",
+        usage: "addrs <function>...",
+        run: |server, _, tokens| addrs_command(server, tokens),
+    },
+    Command {
+        name: "caps",
+        usage: "caps [compress=<algorithm>,...]",
+        run: caps_command,
+    },
+    Command {
+        name: "types",
+        usage: "types",
+        run: |_, _, _| Ok(types_report()),
+    },
+    Command {
+        name: "auth",
+        usage: "auth <token>",
+        run: auth_command,
+    },
+    Command {
+        name: "admin",
+        usage: "admin stats | admin config | admin reload | admin unload <alias> | admin open <path> [read|write|append|readwrite] | admin close <handle> | admin shutdown",
+        run: admin_command,
+    },
+    Command {
+        name: "shutdown",
+        usage: "shutdown",
+        run: shutdown_command,
+    },
+    Command {
+        name: "history",
+        usage: "history",
+        run: |_, client, _| Ok(history_report(client)),
+    },
+    Command {
+        name: "replay",
+        usage: "replay <n>",
+        run: replay_command,
+    },
+    Command {
+        name: "help",
+        usage: "help",
+        run: |_, _, _| Ok(help_report()),
+    },
+];
+
+fn execute_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let name = tokens.first().copied().unwrap_or_default();
+    match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => (command.run)(server, client, tokens),
+        None => Err(format!(
+            "E_UNKNOWN_CMD unknown command '{}'; try 'help'",
+            name
+        )),
+    }
 }
 
-impl FromStr for SupportedType {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "int" => Ok(SupportedType::Int),
-            "float" => Ok(SupportedType::Float),
-            "char" => Ok(SupportedType::Char),
-            "void" => Ok(SupportedType::Void),
-            _ => Err(format!("Unsupported type: {}", s)),
+/// `help`: the usage of every command, separated by tabs.
+fn help_report() -> String {
+    COMMANDS
+        .iter()
+        .map(|command| command.usage)
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/// `caps [compress=<algorithm>,...]`: reports the server's capabilities. A
+/// client that can decompress lists the algorithms it accepts; the server
+/// picks one it supports (only `gzip`) and reports it as `compression=`.
+/// `compress=none` turns compression off again.
+fn caps_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    match &tokens[1..] {
+        [] => {}
+        [offer] => {
+            let Some(algorithms) = offer.strip_prefix("compress=") else {
+                return Err("Usage: caps [compress=<algorithm>,...]".into());
+            };
+            client
+                .gzip
+                .set(algorithms.split(',').any(|algorithm| algorithm == "gzip"));
         }
+        _ => return Err("Usage: caps [compress=<algorithm>,...]".into()),
     }
+    Ok(capabilities(&server.config, client))
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-struct FunctionSignature {
-    calling_convention: String, // e.g., "cdecl" or "stdcall"
-    param_types: Vec<SupportedType>,
-    return_type: SupportedType,
+/// `types`: every type this server supports, as `<name> aliases=<name>,...
+/// width=<bytes> pointer=<bool>` entries separated by `; `, in the order of
+/// [`signature::TYPE_NAMES`]. Types the host cannot represent are left out,
+/// as they are from `caps`.
+fn types_report() -> String {
+    let mut entries = Vec::new();
+    for (index, &(name, ty)) in signature::TYPE_NAMES.iter().enumerate() {
+        // Aliases are reported with the first name listed for their type.
+        if signature::TYPE_NAMES[..index].iter().any(|&(_, t)| t == ty) {
+            continue;
+        }
+        let Ok(width) = ty.width() else {
+            continue;
+        };
+        let aliases: Vec<&str> = ty.aliases().collect();
+        entries.push(format!(
+            "{} aliases={} width={} pointer={}",
+            name,
+            if aliases.is_empty() {
+                "-".to_string()
+            } else {
+                aliases.join(",")
+            },
+            width,
+            ty.is_pointer()
+        ));
+    }
+    format!("types {}", entries.join("; "))
 }
 
-fn parse_signature(signature: &str) -> Result<FunctionSignature, String> {
-    let parts: Vec<&str> = signature.split("->").collect();
-    if parts.len() != 2 {
-        return Err("Signature must contain '->'".into());
+/// Describes what this server supports as `key=value` fields on one line.
+fn capabilities(config: &Config, client: &Client) -> String {
+    let commands: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+    let types: Vec<&str> = signature::TYPE_NAMES
+        .iter()
+        .filter(|(_, ty)| ty.ffi_type().is_ok())
+        .map(|(name, _)| *name)
+        .collect();
+    let mut features = vec!["request-ids"];
+    if config.allow_upload {
+        features.push("upload");
     }
-    let params_with_conv = parts[0]; // e.g., "int,int(stdcall)"
-    let ret_type_str = parts[1]; // e.g., "int"
+    if config.allow_raw_pointers {
+        features.push("raw-pointers");
+    }
+    if client.nodelay {
+        features.push("nodelay");
+    }
+    if config.no_call {
+        features.push("no-call");
+    }
+    format!(
+        "caps version={} protocol={} commands={} types={} encodings={} max_args={} framing=line features={} compression={}",
+        env!("CARGO_PKG_VERSION"),
+        config.protocol,
+        commands.join(","),
+        types.join(","),
+        encoding::ENCODING_NAMES.join(","),
+        config.max_args,
+        features.join(","),
+        if client.gzip.get() { "gzip" } else { "none" }
+    )
+}
 
-    let mut calling_convention = "cdecl".to_string();
-    let params_part = if let Some(start) = params_with_conv.find('(') {
-        if let Some(end) = params_with_conv.find(')') {
-            calling_convention = params_with_conv[start + 1..end].to_string();
-            &params_with_conv[..start]
+/// `bind <function> sig:<signature>`: makes calls to `function` on this
+/// connection use `signature` when they give no `sig:` of their own, ahead
+/// of the schema and debug info. Binding again replaces the signature; all
+/// bindings end with the connection.
+fn bind_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let usage = "Usage: bind <function> sig:<signature>";
+    let Some(&function_name) = tokens.get(1) else {
+        return Err(usage.into());
+    };
+    let (sig, used) = extract_signature(&tokens[2..], server.config.max_signature_length)?;
+    let (Some(sig), true) = (sig, used == tokens.len() - 2) else {
+        return Err(usage.into());
+    };
+    let signature = parse_signature(&sig, server.config.default_convention)?;
+    let (lib, name) = server.libraries.resolve(function_name)?;
+    lib.symbol(name)?;
+    client
+        .bindings
+        .borrow_mut()
+        .insert(function_name.to_string(), signature);
+    Ok(acknowledge(server, ""))
+}
+
+/// `check <function> [sig:<signature>] [args...]`: validates a call without
+/// making it. The signature (the client's, a binding or the schema's) must
+/// parse, including its return type, the function must resolve, and any
+/// arguments given must meet the schema's constraints.
+fn check_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let Some(&function_name) = tokens.get(1) else {
+        return Err("Usage: check <function> [sig:<signature>] [args...]".into());
+    };
+    let (sig, used) = extract_signature(&tokens[2..], server.config.max_signature_length)?;
+    let args = &tokens[2 + used..];
+    let signature = sig
+        .map(|sig| parse_signature(&sig, server.config.default_convention))
+        .transpose()?;
+    let (lib, name) = server.libraries.resolve(function_name)?;
+    let signature = match signature {
+        Some(signature) => signature,
+        None => declared_signature(server, client, &lib, function_name, name)?,
+    };
+    lib.symbol(name)?;
+    server
+        .schema
+        .read()
+        .unwrap()
+        .check_constraints(function_name, &signature, args)?;
+    Ok(acknowledge(server, ""))
+}
+
+/// `describe <function>`: how the server resolves a function, as one line of
+/// `key=value` fields: the export it binds to and how that name was matched
+/// (`exact`, `case-insensitive` or `demangled`), the file of the library it
+/// is in, its address, and the signature a call without `sig:` would use
+/// together with its source (`-` for none).
+fn describe_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let [_, function_name] = tokens else {
+        return Err("Usage: describe <function>".into());
+    };
+    let (lib, name) = server.libraries.resolve(function_name)?;
+    let export = lib.export_name(name)?;
+    let address = lib.symbol(name)?;
+    let (signature, source) =
+        match find_declared_signature(server, client, &lib, function_name, name) {
+            Ok(Some((signature, source))) => (signature.to_string(), source),
+            // Unreadable debug info means there is no signature to report.
+            Ok(None) | Err(_) => ("-".to_string(), "-"),
+        };
+    Ok(format!(
+        "describe function={} export={} match={} library={} address={} signature={} source={}",
+        function_name,
+        export,
+        if name.starts_with("cpp:") {
+            "demangled"
+        } else if export == name {
+            "exact"
         } else {
-            return Err("Malformed signature: missing closing parenthesis".into());
+            "case-insensitive"
+        },
+        lib.path().display(),
+        invoke::format_pointer(address),
+        signature,
+        source
+    ))
+}
+
+/// `auth <token>`: unlocks `admin` commands for the rest of the connection.
+fn auth_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let Some(expected) = &server.admin_token else {
+        return Err("E_AUTH admin commands are disabled; start with --admin-token-file".into());
+    };
+    let [_, token] = tokens else {
+        return Err("Usage: auth <token>".into());
+    };
+    if !constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+        return Err("E_AUTH invalid token".into());
+    }
+    client.admin.set(true);
+    Ok(acknowledge(server, ""))
+}
+
+/// Compares secrets without stopping at the first differing byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// `admin <command>`: operational commands, available after `auth`.
+fn admin_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    if !client.admin.get() {
+        return Err("E_AUTH admin commands require auth".into());
+    }
+    match tokens[1..] {
+        ["stats"] => {
+            let stats = &server.stats;
+            Ok(format!(
+                "stats uptime_s={} connections={} requests={} errors={} in_flight={} libs={}",
+                stats.started.elapsed().as_secs(),
+                stats.connections.load(Ordering::Relaxed),
+                stats.requests.load(Ordering::Relaxed),
+                stats.errors.load(Ordering::Relaxed),
+                stats.in_flight.load(Ordering::Relaxed),
+                server.libraries.count()
+            ))
+        }
+        ["reload"] => {
+            let path = server
+                .config
+                .schema_path
+                .as_deref()
+                .ok_or("No schema to reload; start with --schema")?;
+            let schema = Schema::load(path, server.config.default_convention)?;
+            *server.schema.write().unwrap() = schema;
+            Ok(acknowledge(server, ""))
+        }
+        ["config"] => {
+            let mut fields = server.config.effective();
+            let mut libraries = vec![server.config.dll_path.clone()];
+            libraries.extend(server.libraries.aliases());
+            fields.push(("libraries", libraries.join(",")));
+            // The token itself never leaves the server.
+            fields.push(("admin_token", "<redacted>".into()));
+            let fields: Vec<String> = fields
+                .into_iter()
+                .map(|(key, value)| format!("{}={}", key, quote_config_value(&value)))
+                .collect();
+            Ok(format!("config {}", fields.join(" ")))
+        }
+        ["unload", alias] => {
+            server.libraries.unload(alias)?;
+            Ok(acknowledge(server, ""))
+        }
+        ["open", path] => server.handles.open(path, "read"),
+        ["open", path, mode] => server.handles.open(path, mode),
+        ["close", token] => {
+            server.handles.close(token)?;
+            Ok(acknowledge(server, ""))
+        }
+        ["shutdown"] => {
+            request_shutdown(server);
+            Ok(acknowledge(server, "shutting down"))
         }
+        _ => {
+            Err("Usage: admin stats | admin config | admin reload | admin unload <alias> | admin open <path> [read|write|append|readwrite] | admin close <handle> | admin shutdown".into())
+        }
+    }
+}
+
+/// A configuration value as it appears in `admin config`: quoted, with
+/// escapes, if it is empty or would otherwise split the line into fields.
+fn quote_config_value(value: &str) -> String {
+    if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c == '"') {
+        format!("{:?}", value)
     } else {
-        params_with_conv
+        value.to_string()
+    }
+}
+
+/// `shutdown`: the same orderly shutdown as `admin shutdown` or `SIGTERM`,
+/// for an authenticated connection.
+fn shutdown_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    if !client.admin.get() {
+        return Err("E_AUTH shutdown requires auth".into());
+    }
+    if tokens.len() != 1 {
+        return Err("Usage: shutdown".into());
+    }
+    request_shutdown(server);
+    Ok(acknowledge(server, "shutting down"))
+}
+
+/// Stops accepting connections. The accept loop then waits for in-flight
+/// requests, including the one asking for the shutdown, and exits.
+fn request_shutdown(server: &Server) {
+    if !server.shutting_down.swap(true, Ordering::SeqCst) {
+        // An accept loop only notices the flag once it accepts something.
+        for address in &server.wake_addresses {
+            address.wake();
+        }
+    }
+}
+
+/// `upload [bind:now|bind:lazy] [scope:global|scope:local] <alias> <base64>`:
+/// loads a library body sent by the client. `bind:` and `scope:` override
+/// `--bind-now` and `--global-symbols` for this library.
+fn upload_command(server: &Server, tokens: &[&str]) -> Result<String, String> {
+    if !server.config.allow_upload {
+        return Err("Uploads are disabled; start the server with --allow-upload".into());
+    }
+    let usage = || {
+        "Usage: upload [bind:now|bind:lazy] [scope:global|scope:local] <alias> <base64>".to_string()
     };
+    let [_, modifiers @ .., alias, body] = tokens else {
+        return Err(usage());
+    };
+    let mut flags = server.config.open_flags();
+    for modifier in modifiers {
+        match *modifier {
+            "bind:now" => flags.bind_now = true,
+            "bind:lazy" => flags.bind_now = false,
+            "scope:global" => flags.global = true,
+            "scope:local" => flags.global = false,
+            _ => return Err(usage()),
+        }
+    }
+    let body = base64::decode(body)?;
+    server.libraries.upload(alias, &body, flags)?;
+    Ok(acknowledge(server, ""))
+}
 
-    let param_types: Result<Vec<SupportedType>, String> = params_part
-        .split(',')
-        .filter(|s| !s.trim().is_empty())
-        .map(|s| s.trim().parse())
+/// `addrs <name>...`: resolves several symbols at once, reporting `name=<address>`
+/// for each, or `name=null` when it cannot be resolved.
+fn addrs_command(server: &Server, tokens: &[&str]) -> Result<String, String> {
+    if tokens.len() < 2 {
+        return Err("Usage: addrs <function>...".into());
+    }
+    let fields: Vec<String> = tokens[1..]
+        .iter()
+        .map(|&name| {
+            let address = server
+                .libraries
+                .resolve(name)
+                .and_then(|(lib, symbol)| lib.symbol(symbol));
+            match address {
+                Ok(address) => format!("{}={}", name, invoke::format_pointer(address)),
+                Err(_) => format!("{}=null", name),
+            }
+        })
         .collect();
-    let param_types = param_types?;
-    let return_type = ret_type_str.trim().parse()?;
+    Ok(fields.join(" "))
+}
 
-    Ok(FunctionSignature {
-        calling_convention,
-        param_types,
-        return_type,
-    })
+/// `call [modifiers] <function> [sig:<signature>] [args...]`
+///
+/// Modifiers are placed before the function name, either `key:value` or `+flag`:
+/// `enc:<encoding>` overrides the string encoding for this call,
+/// `timeout:<ms>` overrides `--call-timeout` (up to `--max-call-timeout`),
+/// `+discard` makes the call without reading its return value, `+bits`
+/// exchanges `float` values as hex IEEE-754 bit patterns, `+hex` adds the
+/// hex form to `int` and `char` results, `+trace` precedes the result with
+/// a trace of the call, for authenticated clients or with `--allow-trace`,
+/// `+time` reports how long the foreign call itself took, and `+capture`
+/// what it wrote to stdout and stderr.
+///
+/// An argument of exactly `@peer` is replaced by the client's address, and
+/// `@@peer` passes the literal text `@peer`.
+///
+/// `callat [modifiers] <address> sig:<signature> [args...]` takes the same
+/// form but calls the code at a `0x`-prefixed address, such as one reported
+/// by `addrs`, without resolving a symbol. It needs `--allow-raw-pointers`.
+fn call_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let peer = peer_text(client);
+    let by_address = tokens[0] == "callat";
+    if by_address && !server.config.allow_raw_pointers {
+        return Err(
+            "Calls by address are disabled; start the server with --allow-raw-pointers".into(),
+        );
+    }
+    let call = prepare_call(server, client, &tokens[1..], &peer, by_address)?;
+    // A trace exposes addresses, so it is not for every client.
+    if call.options.trace && !server.config.allow_trace && !client.admin.get() {
+        return Err("E_AUTH +trace requires auth or --allow-trace".into());
+    }
+    let reply = call.run();
+    record_history(server, client, tokens, &call, &reply);
+    let reply = reply?;
+    if let Some(ownership) = call.ownership {
+        track_ownership(client, &call, ownership, &reply);
+    }
+    Ok(reply)
+}
+
+/// Adds a call that was made to the connection's history, dropping the
+/// oldest entry once `--history-size` are kept.
+fn record_history(
+    server: &Server,
+    client: &Client,
+    tokens: &[&str],
+    call: &PreparedCall,
+    reply: &Result<String, String>,
+) {
+    let size = server.config.history_size;
+    if size == 0 {
+        return;
+    }
+    // The arguments are the last tokens of the request.
+    let args_start = tokens.len() - call.args.len();
+    let shown: Vec<&str> = tokens
+        .iter()
+        .enumerate()
+        .map(|(index, &token)| match index.checked_sub(args_start) {
+            Some(position) if call.redacted.contains(&position) => "<redacted>",
+            _ => token,
+        })
+        .collect();
+    let number = client.calls_made.get() + 1;
+    client.calls_made.set(number);
+    let mut history = client.history.borrow_mut();
+    if history.len() == size {
+        history.pop_front();
+    }
+    history.push_back(HistoryEntry {
+        number,
+        tokens: tokens.iter().map(|token| token.to_string()).collect(),
+        shown: shown.join(" "),
+        reply: reply
+            .as_ref()
+            .map(|reply| reply.lines().last().unwrap_or_default().to_string())
+            .map_err(Clone::clone),
+    });
 }
 
-fn dynamic_invoke(func_ptr: *const std::ffi::c_void, args: &[&str]) -> Result<String, String> {
-    use libffi::middle::{Arg, Cif, CodePtr, Type};
+/// `history`: the connection's recent calls, oldest first, as
+/// `<n>: <request> -> OK <result>` or `-> ERR <error>` entries separated by `; `.
+fn history_report(client: &Client) -> String {
+    let entries: Vec<String> = client
+        .history
+        .borrow()
+        .iter()
+        .map(|entry| {
+            let reply = match &entry.reply {
+                Ok(result) => with_ok_status(result.clone()),
+                Err(e) => format!("ERR {}", e),
+            };
+            format!("{}: {} -> {}", entry.number, entry.shown, reply)
+        })
+        .collect();
+    if entries.is_empty() {
+        return "history".into();
+    }
+    format!("history {}", entries.join("; "))
+}
 
-    let arg_types = vec![Type::i32(); args.len()];
-    let cif = Cif::new(arg_types, Type::i32());
+/// `replay <n>`: makes call `n` of the connection's history again, which
+/// records it anew.
+fn replay_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let [_, number] = tokens else {
+        return Err("Usage: replay <n>".into());
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid history number: {}", number))?;
+    let request = client
+        .history
+        .borrow()
+        .iter()
+        .find(|entry| entry.number == number)
+        .map(|entry| entry.tokens.clone())
+        .ok_or_else(|| format!("E_HISTORY no call {} in this connection's history", number))?;
+    let request: Vec<&str> = request.iter().map(String::as_str).collect();
+    call_command(server, client, &request)
+}
 
-    let parsed_args: Result<Vec<i32>, _> = args.iter().map(|s| s.parse::<i32>()).collect();
-    let parsed_args = parsed_args.map_err(|_| "Argument parsing error".to_string())?;
+/// Records what a successful call to a schema-declared allocator handed out,
+/// and forgets what a declared free function released, so the connection's
+/// unfreed allocations can be reported when it closes.
+fn track_ownership(client: &Client, call: &PreparedCall, ownership: Ownership, reply: &str) {
+    let mut allocations = client.allocations.borrow_mut();
+    if let Some(index) = ownership.frees
+        && let Some(address) = call
+            .signature
+            .argument_position(index)
+            .and_then(|position| call.args.get(position))
+            .and_then(|a| invoke::parse_pointer(a))
+    {
+        allocations.remove(&(address as usize));
+    }
+    if ownership.allocates {
+        // The pointer is the reply's first field: on the last line of a
+        // trace, and after `result=` with `+time`.
+        let value = reply.lines().last().unwrap_or_default();
+        let value = value.strip_prefix("result=").unwrap_or(value);
+        let address = value
+            .split_whitespace()
+            .next()
+            .and_then(invoke::parse_pointer)
+            .filter(|address| !address.is_null());
+        if let Some(address) = address {
+            allocations.insert(address as usize, call.function.to_string());
+        }
+    }
+}
 
-    let ffi_args: Vec<Arg> = parsed_args.iter().map(|a| Arg::new(a)).collect();
-    let code_ptr = CodePtr::from_ptr(func_ptr);
-    let result: i32 = unsafe { cif.call(code_ptr, &ffi_args) };
+/// A parsed and resolved call, ready to be made (possibly repeatedly).
+struct PreparedCall<'a> {
+    target: CallTarget<'a>,
+    /// The function or address as the client named it.
+    function: &'a str,
+    signature: FunctionSignature,
+    args: Vec<&'a str>,
+    options: CallOptions,
+    timeout: Option<Duration>,
+    /// Send the call's thread `SIGALRM` when it times out.
+    interrupt_on_timeout: bool,
+    /// What the schema says the function allocates or frees.
+    ownership: Option<Ownership>,
+    /// The positions in `args` that `history` hides.
+    redacted: Vec<usize>,
+}
 
-    Ok(result.to_string())
+/// The code a call goes to.
+enum CallTarget<'a> {
+    /// A function exported by `lib`, resolved under `name`.
+    Symbol {
+        lib: Arc<LoadedLibrary>,
+        name: &'a str,
+    },
+    /// A raw code address given to `callat`.
+    Address(*const c_void),
 }
 
-fn invoke_function(
-    lib: &Library,
-    name: &str,
-    metadata: Option<&str>,
-    args: &[&str],
-) -> Result<String, String> {
-    let func_name = CString::new(name).map_err(|_| "Invalid function name")?;
-    unsafe {
-        let symbol = lib
-            .get::<*const ()>(func_name.as_bytes_with_nul())
-            .map_err(|e| e.to_string())?;
-        let func_ptr = *symbol as *const std::ffi::c_void;
-
-        if let Some(return_str) = metadata {
-            let signature = parse_signature(return_str)?;
-            println!("Using metadata: {:?}", signature);
-            dynamic_invoke(func_ptr, args)
+impl PreparedCall<'_> {
+    fn run(&self) -> Result<String, String> {
+        let Some(timeout) = self.timeout else {
+            return match &self.target {
+                CallTarget::Symbol { lib, name } => {
+                    invoke_function(lib, name, &self.signature, &self.args, &self.options)
+                }
+                CallTarget::Address(address) => {
+                    invoke_address(*address, &self.signature, &self.args, &self.options)
+                }
+            };
+        };
+        // The worker thread needs its own copies of everything.
+        let signature = self.signature.clone();
+        let args: Vec<String> = self.args.iter().map(|a| a.to_string()).collect();
+        let options = self.options.clone();
+        match &self.target {
+            CallTarget::Symbol { lib, name } => {
+                let lib = Arc::clone(lib);
+                let name = name.to_string();
+                invoke_with_timeout(
+                    move || {
+                        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                        invoke_function(&lib, &name, &signature, &args, &options)
+                    },
+                    timeout,
+                    self.interrupt_on_timeout,
+                )
+            }
+            CallTarget::Address(address) => {
+                // Raw pointers are not `Send`; the address is only a number here.
+                let address = *address as usize;
+                invoke_with_timeout(
+                    move || {
+                        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                        invoke_address(address as *const c_void, &signature, &args, &options)
+                    },
+                    timeout,
+                    self.interrupt_on_timeout,
+                )
+            }
+        }
+    }
+}
+
+/// The client's address as substituted for `@peer`.
+fn peer_text(client: &Client) -> String {
+    client.peer.clone().unwrap_or_else(|| "-".into())
+}
+
+/// Parses the tokens of a `call` request after the command word; with
+/// `by_address`, those of a `callat` request, which name an address instead
+/// of a function.
+fn prepare_call<'a>(
+    server: &Server,
+    client: &Client,
+    tokens: &[&'a str],
+    peer: &'a str,
+    by_address: bool,
+) -> Result<PreparedCall<'a>, String> {
+    let config = &server.config;
+    let mut options = CallOptions {
+        encoding: config.string_encoding,
+        max_result_bytes: config.max_result_bytes,
+        discard_result: false,
+        float_bits: false,
+        hex: false,
+        dry_run: config.no_call,
+        // The status token protocol 2 starts the reply with already says `OK`.
+        void_reply: match config.void_reply {
+            VoidReply::Ok if config.protocol >= 2 => VoidReply::Empty,
+            mode => mode,
+        },
+        trace: false,
+        time: false,
+        capture: false,
+        char_signed: config.char_signed,
+        lenient_args: config.lenient_args,
+        return_names: None,
+        handles: server.handles.clone(),
+        enums: server.schema.read().unwrap().enums(),
+    };
+    let mut timeout = config.call_timeout;
+
+    let mut tokens = tokens;
+    while let Some(&modifier) = tokens.first() {
+        if let Some(flag) = modifier.strip_prefix('+') {
+            match flag {
+                "discard" => options.discard_result = true,
+                "bits" => options.float_bits = true,
+                "hex" => options.hex = true,
+                "trace" => options.trace = true,
+                "time" => options.time = true,
+                "capture" => options.capture = true,
+                _ => return Err(format!("Unknown call modifier: {}", modifier)),
+            }
+        } else if let Some((key, value)) = modifier.split_once(':') {
+            match key {
+                "enc" => options.encoding = value.parse()?,
+                "timeout" => timeout = Some(parse_call_timeout(value, config)?),
+                // A C++ function name, possibly after an alias.
+                "cpp" => break,
+                _ if key.ends_with("!cpp") => break,
+                _ => return Err(format!("Unknown call modifier: {}", modifier)),
+            }
         } else {
-            Err("No signature string provided".into())
+            break;
         }
+        tokens = &tokens[1..];
+    }
+
+    if tokens.is_empty() {
+        return Err("Missing function name".into());
     }
+
+    let function_name = tokens[0];
+
+    let (metadata, sig_tokens) = extract_signature(&tokens[1..], config.max_signature_length)?;
+    if metadata.as_ref().is_some_and(|sig| !sig.contains("->")) {
+        return Err("Malformed signature; no '->' found".into());
+    }
+    let args_start = 1 + sig_tokens;
+
+    let args = &tokens[args_start..];
+    if args.len() > config.max_args {
+        return Err(format!(
+            "E_TOO_MANY_ARGS {} arguments exceeds the limit of {}",
+            args.len(),
+            config.max_args
+        ));
+    }
+
+    let target = if by_address {
+        let address = invoke::parse_pointer(function_name)
+            .filter(|address| !address.is_null())
+            .ok_or_else(|| format!("Invalid address: {}", function_name))?;
+        CallTarget::Address(address)
+    } else {
+        let (lib, name) = server.libraries.resolve(function_name)?;
+        CallTarget::Symbol { lib, name }
+    };
+    // A client-supplied signature wins; otherwise fall back to a binding,
+    // the schema, then the library's debug info.
+    let signature = match (metadata, &target) {
+        (Some(sig), _) => parse_signature(&sig, config.default_convention)?,
+        (None, CallTarget::Symbol { lib, name }) => {
+            declared_signature(server, client, lib, function_name, name)?
+        }
+        (None, CallTarget::Address(_)) => return Err("callat requires sig:<signature>".into()),
+    };
+
+    let args: Vec<&str> = args
+        .iter()
+        .map(|&arg| match arg {
+            "@peer" => peer,
+            "@@peer" => "@peer",
+            _ => arg,
+        })
+        .collect();
+
+    let mut ownership = None;
+    let mut redacted = Vec::new();
+    if let CallTarget::Symbol { .. } = target {
+        let schema = server.schema.read().unwrap();
+        schema.check_constraints(function_name, &signature, &args)?;
+        options.return_names = schema.return_names(function_name);
+        ownership = schema.ownership(function_name);
+        redacted = schema
+            .redacted(function_name)
+            .into_iter()
+            .filter_map(|index| signature.argument_position(index))
+            .collect();
+    }
+
+    Ok(PreparedCall {
+        target,
+        function: function_name,
+        signature,
+        args,
+        options,
+        timeout,
+        interrupt_on_timeout: config.interrupt_on_timeout,
+        ownership,
+        redacted,
+    })
 }
 
-fn handle_client_command(stream: &mut TcpStream, lib: &Library, line: &str) -> () {
-    let tokens: Vec<&str> = line.trim().split_whitespace().collect();
-    if tokens.get(0) != Some(&"call") {
-        stream
-            .write_all(b"ERR Command must start with 'call'")
-            .expect("Could not write to stream");
+/// `bench <call> times:<N>`: makes the call `N` times and reports its latency
+/// over all iterations in nanoseconds, as `key=value` fields. `<call>` takes
+/// the same form as a `call` request without the leading `call`; it is parsed
+/// and resolved once, so only the calls themselves are timed.
+fn bench_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let usage = "Usage: bench [modifiers] <function> [sig:<signature>] [args...] times:<N>";
+    let Some((times, call)) = tokens[1..].split_last() else {
+        return Err(usage.into());
+    };
+    let times: u64 = times
+        .strip_prefix("times:")
+        .ok_or(usage)?
+        .parse()
+        .map_err(|_| format!("Invalid iteration count: {}", times))?;
+    let max = server.config.max_bench_iterations;
+    if times == 0 || times > max {
+        return Err(format!("Iterations must be between 1 and {}", max));
+    }
+    let peer = peer_text(client);
+    let call = prepare_call(server, client, call, &peer, false)?;
+    if call.options.trace {
+        return Err("+trace cannot be used with bench".into());
+    }
 
-        return;
+    let (mut min, mut max, mut total) = (Duration::MAX, Duration::ZERO, Duration::ZERO);
+    for _ in 0..times {
+        let started = Instant::now();
+        call.run()?;
+        let elapsed = started.elapsed();
+        min = min.min(elapsed);
+        max = max.max(elapsed);
+        total += elapsed;
     }
-    if tokens.len() < 2 {
-        stream
-            .write_all(b"ERR Missing function name")
-            .expect("Could not write to stream");
-        return;
+    Ok(format!(
+        "bench n={} min_ns={} avg_ns={} max_ns={} total_ns={}",
+        times,
+        min.as_nanos(),
+        total.as_nanos() / times as u128,
+        max.as_nanos(),
+        total.as_nanos()
+    ))
+}
+
+/// The signature of a function called without `sig:`: the connection's
+/// binding for `function`, else the schema's declaration of it, else the
+/// prototype of `name` in `lib`'s debug info when `--debug-info` is on.
+fn declared_signature(
+    server: &Server,
+    client: &Client,
+    lib: &LoadedLibrary,
+    function: &str,
+    name: &str,
+) -> Result<FunctionSignature, String> {
+    match find_declared_signature(server, client, lib, function, name) {
+        Ok(Some((signature, _))) => Ok(signature),
+        Ok(None) => Err("No signature string provided".into()),
+        Err(e) => Err(format!("No signature string provided ({})", e)),
     }
+}
 
-    let function_name = tokens[1];
+/// Like `declared_signature`, also naming where the signature came from
+/// (`bind`, `schema` or `debug-info`); `None` if none declares the function.
+fn find_declared_signature(
+    server: &Server,
+    client: &Client,
+    lib: &LoadedLibrary,
+    function: &str,
+    name: &str,
+) -> Result<Option<(FunctionSignature, &'static str)>, String> {
+    if let Some(signature) = client.bindings.borrow().get(function) {
+        return Ok(Some((signature.clone(), "bind")));
+    }
+    if let Some(signature) = server.schema.read().unwrap().signature(function) {
+        return Ok(Some((signature.clone(), "schema")));
+    }
+    Ok(lib
+        .debug_signature(name, server.config.default_convention)?
+        .map(|signature| (signature, "debug-info")))
+}
 
-    let mut metadata: Option<String> = None;
-    let mut args_start = 2;
+/// `batch <call>; <call>...`: makes several calls in order in one request.
+///
+/// Each `;`-separated call takes the same form as `call` (the leading `call`
+/// may be left out). An argument of exactly `$N` is replaced by the result of
+/// the batch's `N`th call (1-based), which must come earlier, and `$$N` passes
+/// the literal text `$N`. The reply is the results joined by `; `, with `;`
+/// inside a result escaped as `\;`. The batch stops at the first failing
+/// call, answering `E_BATCH call <N>: <error>`.
+fn batch_command(server: &Server, client: &Client, tokens: &[&str]) -> Result<String, String> {
+    let text = tokens[1..].join(" ");
+    let calls = split_batch(&text);
+    let calls: Vec<Vec<&str>> = calls
+        .iter()
+        .map(|call| call.split_whitespace().collect::<Vec<_>>())
+        .filter(|call| !call.is_empty())
+        .collect();
+    if calls.is_empty() {
+        return Err("Usage: batch <call>; <call>...".into());
+    }
+    let mut results: Vec<String> = Vec::with_capacity(calls.len());
+    for (index, call) in calls.iter().enumerate() {
+        let batch_error = |e: String| format!("E_BATCH call {}: {}", index + 1, e);
+        let mut call_tokens = vec!["call"];
+        let call = match call.split_first() {
+            Some((&"call", rest)) => rest,
+            _ => &call[..],
+        };
+        // A trace spans several lines, which a batch reply cannot hold.
+        if call.contains(&"+trace") {
+            return Err(batch_error("+trace cannot be used in a batch".into()));
+        }
+        for &token in call {
+            call_tokens.push(substitute_result(token, &results).map_err(batch_error)?);
+        }
+        let result = call_command(server, client, &call_tokens).map_err(batch_error)?;
+        results.push(result);
+    }
+    Ok(results
+        .iter()
+        .map(|result| result.replace(';', "\\;"))
+        .collect::<Vec<_>>()
+        .join("; "))
+}
 
-    if let Some(&sig_tok) = tokens.get(2) {
-        if sig_tok.starts_with("sig:") {
-            let mut sig = String::new();
-            let mut end_idx = 2;
-            for (i, &tok) in tokens.iter().enumerate().skip(2) {
-                let piece = if i == 2 {
-                    tok.trim_start_matches("sig:")
-                } else {
-                    tok
-                };
-                if !sig.is_empty() {
-                    sig.push(' ');
+/// Splits a batch at each `;` that is not written as `\;`, which stands for a
+/// literal `;` inside an argument.
+fn split_batch(text: &str) -> Vec<String> {
+    let mut calls = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&';') => {
+                chars.next();
+                calls.last_mut().unwrap().push(';');
+            }
+            ';' => calls.push(String::new()),
+            _ => calls.last_mut().unwrap().push(c),
+        }
+    }
+    calls
+}
+
+/// Resolves a `$N` reference to the result of an earlier call in a batch.
+fn substitute_result<'a>(token: &'a str, results: &'a [String]) -> Result<&'a str, String> {
+    if let Some(literal) = token.strip_prefix('$').filter(|t| t.starts_with('$')) {
+        return Ok(literal);
+    }
+    let Some(index) = token
+        .strip_prefix('$')
+        .and_then(|n| n.parse::<usize>().ok())
+    else {
+        return Ok(token);
+    };
+    match index.checked_sub(1).and_then(|i| results.get(i)) {
+        Some(result) => Ok(result),
+        None => Err(format!("{} does not refer to an earlier call", token)),
+    }
+}
+
+fn parse_call_timeout(value: &str, config: &Config) -> Result<Duration, String> {
+    let millis: u64 = value
+        .parse()
+        .map_err(|_| format!("Invalid timeout: {}", value))?;
+    let timeout = Duration::from_millis(millis);
+    if timeout.is_zero() || timeout > config.max_call_timeout {
+        return Err(format!(
+            "Timeout must be between 1 and {} ms",
+            config.max_call_timeout.as_millis()
+        ));
+    }
+    Ok(timeout)
+}
+
+/// The function named by a `call` request, skipping its modifiers.
+fn called_function<'a>(tokens: &[&'a str]) -> Option<&'a str> {
+    match tokens.split_first() {
+        Some((&"call", rest)) => rest
+            .iter()
+            .copied()
+            .find(|tok| !tok.starts_with('+') && !tok.contains(':')),
+        _ => None,
+    }
+}
+
+/// Splits an optional `id:<n>` correlation prefix off the request tokens.
+fn split_request_id<'a>(tokens: &'a [&'a str]) -> Result<(Option<u64>, &'a [&'a str]), String> {
+    match tokens.first() {
+        Some(tok) if tok.starts_with("id:") => {
+            let id = tok["id:".len()..]
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid request id: {}", tok))?;
+            Ok((Some(id), &tokens[1..]))
+        }
+        _ => Ok((None, tokens)),
+    }
+}
+
+/// Executes one request and writes its reply in full. An error means the
+/// reply could not be delivered and the connection should be dropped.
+fn handle_client_command(
+    stream: &mut Stream,
+    server: &Server,
+    client: &Client,
+    line: &str,
+) -> std::io::Result<()> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let interactive = server.config.interactive;
+    // Blank lines (e.g. a stray Enter in telnet) are ignored without a reply,
+    // though an interactive client is prompted again.
+    if tokens.is_empty() {
+        if interactive {
+            write_lines(stream, server, "")?;
+        }
+        return Ok(());
+    }
+    if interactive && tokens[0] == "?" {
+        let help = interactive::help(&tokens[1..]);
+        return write_lines(stream, server, &help);
+    }
+
+    let started = Instant::now();
+    server.stats.requests.fetch_add(1, Ordering::Relaxed);
+    server.stats.in_flight.fetch_add(1, Ordering::SeqCst);
+    client.requests.set(client.requests.get() + 1);
+    let fault = server
+        .config
+        .fault_inject
+        .as_ref()
+        .and_then(|plan| plan.fault_for(client.requests.get()));
+    if let Some(fault) = fault {
+        capture::log(format_args!(
+            "Client {}: injecting {} into request {}",
+            peer_text(client),
+            fault,
+            client.requests.get()
+        ));
+    }
+    let (id, command, reply) = match split_request_id(&tokens) {
+        Ok((id, command)) if !within_rate_limit(client) => (
+            id,
+            command,
+            Err("E_RATE_LIMITED Too many requests; slow down".into()),
+        ),
+        Ok((id, command)) if let Some(Fault::Error(code)) = fault => {
+            (id, command, Err(format!("{} injected fault", code)))
+        }
+        Ok((id, command)) => (id, command, execute_command(server, client, command)),
+        Err(err) => (None, &tokens[..], Err(err)),
+    };
+    server.stats.latency.observe(started.elapsed());
+    if matches!(command.first(), Some(&"call" | &"callat")) {
+        server.stats.calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if let Some(log) = &server.access_log {
+        log.record(&AccessEntry {
+            peer: client.peer.as_deref(),
+            command: command.first().copied().unwrap_or("-"),
+            function: called_function(command),
+            result: &reply,
+            latency: started.elapsed(),
+        });
+    }
+
+    let mut response = match id {
+        Some(id) => format!("id:{} ", id),
+        None => String::new(),
+    };
+    let reply = render_reply(server, reply);
+    // The outcome of a negotiation must be readable whatever it is.
+    if command.first() == Some(&"caps") {
+        response.push_str(&reply);
+    } else {
+        response.push_str(&compress_reply(client, reply));
+    }
+
+    if fault == Some(&Fault::Truncate) {
+        let half = &response.as_bytes()[..response.len() / 2];
+        let written = stream.write_all(half).and_then(|()| stream.shutdown());
+        server.stats.in_flight.fetch_sub(1, Ordering::SeqCst);
+        return written;
+    }
+
+    let written = write_lines(stream, server, &response);
+    server.stats.in_flight.fetch_sub(1, Ordering::SeqCst);
+    written
+}
+
+/// The `--metrics-port` exposition of the server's counters.
+fn metrics_text(server: &Server) -> String {
+    let stats = &server.stats;
+    let mut out = String::new();
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    metrics::write_metric(
+        &mut out,
+        "dllbridge32_uptime_seconds",
+        "gauge",
+        "Seconds since the server started.",
+        stats.started.elapsed().as_secs(),
+    );
+    metrics::write_metric(
+        &mut out,
+        "dllbridge32_connections_total",
+        "counter",
+        "Connections accepted.",
+        load(&stats.connections),
+    );
+    metrics::write_metric(
+        &mut out,
+        "dllbridge32_active_connections",
+        "gauge",
+        "Connections open right now.",
+        stats.active_connections.load(Ordering::Relaxed),
+    );
+    metrics::write_metric(
+        &mut out,
+        "dllbridge32_requests_total",
+        "counter",
+        "Requests handled, of any command.",
+        load(&stats.requests),
+    );
+    metrics::write_metric(
+        &mut out,
+        "dllbridge32_calls_total",
+        "counter",
+        "call and callat requests handled.",
+        load(&stats.calls),
+    );
+    metrics::write_metric(
+        &mut out,
+        "dllbridge32_errors_total",
+        "counter",
+        "Requests answered with ERR.",
+        load(&stats.errors),
+    );
+    metrics::write_metric(
+        &mut out,
+        "dllbridge32_requests_in_flight",
+        "gauge",
+        "Requests being handled right now.",
+        stats.in_flight.load(Ordering::Relaxed),
+    );
+    metrics::write_metric(
+        &mut out,
+        "dllbridge32_libraries",
+        "gauge",
+        "Libraries loaded, including uploads.",
+        server.libraries.count(),
+    );
+    stats.latency.write(
+        &mut out,
+        "dllbridge32_request_duration_seconds",
+        "Time from reading a request to its reply being ready.",
+    );
+    out
+}
+
+/// The text of a reply: the result, or `ERR <message>`, both labelled for
+/// reading in `--interactive` mode. Counts errors in the stats.
+fn render_reply(server: &Server, reply: Result<String, String>) -> String {
+    if reply.is_err() {
+        server.stats.errors.fetch_add(1, Ordering::Relaxed);
+    }
+    match reply {
+        reply if server.config.interactive => interactive::format_reply(reply),
+        Ok(res) if server.config.protocol >= 2 => with_ok_status(res),
+        Ok(res) => res,
+        Err(err) => format!("ERR {}", err),
+    }
+}
+
+/// A long reply to a client that negotiated compression, as one line:
+/// `gzip:<base64 of the gzipped reply>`. Any newlines of a multi-line reply
+/// are inside the compressed text.
+fn compress_reply(client: &Client, reply: String) -> String {
+    if !client.gzip.get() || reply.len() < COMPRESS_MIN_BYTES {
+        return reply;
+    }
+    format!("gzip:{}", base64::encode(&gzip::compress(reply.as_bytes())))
+}
+
+/// A successful result as protocol 2 sends it: `OK <result>`, or just `OK`
+/// when there is no result.
+fn with_ok_status(result: String) -> String {
+    if result.is_empty() {
+        "OK".into()
+    } else {
+        format!("OK {}", result)
+    }
+}
+
+/// The result of a command that succeeded with nothing to report, with an
+/// optional `note` on what follows (`shutting down`). Protocol 2 starts
+/// every successful reply with `OK` already; protocol 1 spells it out.
+fn acknowledge(server: &Server, note: &str) -> String {
+    if server.config.protocol >= 2 {
+        note.to_string()
+    } else if note.is_empty() {
+        "OK".into()
+    } else {
+        format!("OK {}", note)
+    }
+}
+
+/// Writes `text`, wrapping each of its lines in `--response-prefix` and
+/// `--response-suffix` and ending it with the delimiter; a multi-line
+/// reply (`+trace`) becomes several lines. In `--interactive` mode the prompt
+/// follows, and an empty `text` is just the prompt. Everything goes out in
+/// one `write_all`, which retries short writes until the reply is complete.
+fn write_lines(stream: &mut Stream, server: &Server, text: &str) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(text.len() + 1);
+    if !text.is_empty() || !server.config.interactive {
+        for line in text.split('\n') {
+            bytes.extend_from_slice(&server.config.response_prefix);
+            bytes.extend_from_slice(line.as_bytes());
+            bytes.extend_from_slice(&server.config.response_suffix);
+            bytes.extend_from_slice(&server.config.delimiter);
+        }
+    }
+    if server.config.interactive {
+        bytes.extend_from_slice(interactive::PROMPT.as_bytes());
+    }
+    stream.write_all(&bytes)
+}
+
+/// Takes a request from the client's `--rate-limit` allowance, if it has one.
+fn within_rate_limit(client: &Client) -> bool {
+    client
+        .rate
+        .as_ref()
+        .is_none_or(|bucket| bucket.borrow_mut().take())
+}
+
+fn handle_client(mut stream: Stream, server: Arc<Server>) {
+    let _active = ActiveConnection::new(&server.stats);
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    // Set before anything is written so even the first reply is not held back.
+    if server.config.nodelay && stream.set_nodelay(true).is_err() {
+        capture::log(format_args!("Failed to set TCP_NODELAY"));
+    }
+    let client = Client {
+        peer: stream.peer(),
+        nodelay: stream.nodelay(),
+        admin: Cell::new(false),
+        rate: server
+            .config
+            .rate_limit
+            .map(|limit| RefCell::new(TokenBucket::new(limit))),
+        allocations: RefCell::new(BTreeMap::new()),
+        gzip: Cell::new(false),
+        bindings: RefCell::new(HashMap::new()),
+        requests: Cell::new(0),
+        history: RefCell::new(VecDeque::new()),
+        calls_made: Cell::new(0),
+    };
+    // With --keepalive, an idle read times out and a `PING` line is sent instead.
+    if let Some(interval) = server.config.keepalive
+        && stream.set_read_timeout(Some(interval)).is_err()
+    {
+        return;
+    }
+    if server.config.interactive
+        && write_lines(&mut stream, &server, &interactive::greeting(&server.config)).is_err()
+    {
+        return;
+    }
+    let delimiter = &server.config.delimiter;
+    let mut request = Vec::new();
+    loop {
+        match read_request(&mut reader, delimiter, &mut request) {
+            Ok(0) => break,
+            Ok(_) => {
+                let line = request.strip_suffix(&delimiter[..]).unwrap_or(&request);
+                match std::str::from_utf8(line) {
+                    Ok(line) => {
+                        if let Err(e) = handle_client_command(&mut stream, &server, &client, line) {
+                            capture::log(format_args!(
+                                "Client {}: write failed: {}",
+                                peer_text(&client),
+                                e
+                            ));
+                            break;
+                        }
+                    }
+                    // The request is answered and dropped; the connection stays usable.
+                    Err(e) => {
+                        capture::log(format_args!(
+                            "Client {}: request is not valid UTF-8 (at byte {})",
+                            peer_text(&client),
+                            e.valid_up_to()
+                        ));
+                        let reply = render_reply(
+                            &server,
+                            Err("E_ENCODING request is not valid UTF-8".into()),
+                        );
+                        if let Err(e) = write_lines(&mut stream, &server, &reply) {
+                            capture::log(format_args!(
+                                "Client {}: write failed: {}",
+                                peer_text(&client),
+                                e
+                            ));
+                            break;
+                        }
+                    }
                 }
-                sig.push_str(piece);
-                if sig.contains("->") {
-                    end_idx = i;
+                request.clear();
+            }
+            // Any partial request stays in `request` and is completed by the next read.
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                let config = &server.config;
+                let ping = [
+                    &config.response_prefix[..],
+                    b"PING",
+                    &config.response_suffix,
+                    delimiter,
+                ]
+                .concat();
+                if let Err(e) = stream.write_all(&ping) {
+                    capture::log(format_args!(
+                        "Client {}: keepalive failed: {}",
+                        peer_text(&client),
+                        e
+                    ));
                     break;
                 }
             }
-            if !sig.contains("->") {
-                stream
-                    .write_all(b"ERR Malformed signature; no '->' found")
-                    .expect("Could not write to stream");
-                return;
+            Err(e) => {
+                let cause = match e.kind() {
+                    ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => {
+                        "connection reset by peer".to_string()
+                    }
+                    _ => format!("read failed: {}", e),
+                };
+                capture::log(format_args!("Client {}: {}", peer_text(&client), cause));
+                break;
             }
-            metadata = Some(sig);
-            args_start = end_idx + 1;
         }
     }
+    report_unfreed(&client);
+}
 
-    let args = &tokens[args_start..];
-
-    match invoke_function(lib, function_name, metadata.as_deref(), args) {
-        Ok(res) => stream
-            .write_all(format!("{res}").as_bytes())
-            .expect("Could not write to stream"),
-        Err(err) => stream
-            .write_all(format!("ERR {}", err).as_bytes())
-            .expect("Could not write to stream"),
-    };
+/// Logs the allocations a closing connection never freed, e.g.
+/// `WARN 2 unfreed allocations ...`.
+fn report_unfreed(client: &Client) {
+    let allocations = client.allocations.borrow();
+    if allocations.is_empty() {
+        return;
+    }
+    let list: Vec<String> = allocations
+        .iter()
+        .map(|(&address, function)| {
+            format!(
+                "{} from {}",
+                invoke::format_pointer(address as *const c_void),
+                function
+            )
+        })
+        .collect();
+    capture::log(format_args!(
+        "WARN {} unfreed allocation{} by client {}: {}",
+        allocations.len(),
+        if allocations.len() == 1 { "" } else { "s" },
+        peer_text(client),
+        list.join(", ")
+    ));
 }
 
-fn handle_client(mut stream: TcpStream, lib: Arc<Library>) {
-    let mut reader = BufReader::new(stream.try_clone().unwrap());
-    let mut line = String::new();
-    while let Ok(n) = reader.read_line(&mut line) {
-        if n == 0 {
-            break;
+/// Reads up to and including the next `delimiter`, appending to `buf`. Like
+/// `read_until`, but the delimiter may be several bytes long.
+fn read_request(
+    reader: &mut impl BufRead,
+    delimiter: &[u8],
+    buf: &mut Vec<u8>,
+) -> std::io::Result<usize> {
+    let last = *delimiter.last().expect("delimiter is not empty");
+    let start = buf.len();
+    loop {
+        if reader.read_until(last, buf)? == 0 || buf.ends_with(delimiter) {
+            return Ok(buf.len() - start);
         }
-        handle_client_command(&mut stream, &lib, &line);
-        line.clear();
     }
 }
 
 fn main() {
-    let args: Vec<String> = args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <path_to_dll> [port]", args[0]);
-        std::process::exit(1);
-    }
-    let dll_path = &args[1];
-    let port = if args.len() >= 3 { &args[2] } else { "5000" };
-
-    let lib = unsafe {
-        Library::new(dll_path).unwrap_or_else(|e| {
-            eprintln!("Failed to load DLL {}: {}", dll_path, e);
-            std::process::exit(1);
+    capture::keep_server_output();
+    let mut args = args();
+    let program = args.next().unwrap_or_else(|| "dllbridge32".to_string());
+    let config = Config::from_args(args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        eprintln!("Usage: {} {}", program, config::USAGE);
+        std::process::exit(exit_code::USAGE);
+    });
+    let dll_path = &config.dll_path;
+    if config.fault_inject.is_some() {
+        eprintln!(
+            "WARN --fault-inject is on: replies will be broken on purpose; never use it in production"
+        );
+    }
+
+    let inherited = listener::inherited().unwrap_or_else(|e| {
+        eprintln!("Failed to use inherited sockets: {}", e);
+        std::process::exit(exit_code::BIND);
+    });
+
+    // Before anything is opened, so relative paths given to the other options
+    // are resolved against it too.
+    if let Some(dir) = &config.working_dir
+        && let Err(e) = std::env::set_current_dir(dir)
+    {
+        eprintln!("Failed to change to --working-dir {}: {}", dir.display(), e);
+        std::process::exit(exit_code::NO_INPUT);
+    }
+
+    // Still single-threaded here, so changing the environment is sound.
+    for key in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        unsafe { std::env::remove_var(key) };
+    }
+    for (key, value) in &config.env {
+        unsafe { std::env::set_var(key, value) };
+    }
+
+    // After `--env`, which may set `LD_LIBRARY_PATH`.
+    let library_path = registry::find_library(dll_path);
+    let lib = registry::open_library(&library_path, config.open_flags()).unwrap_or_else(|e| {
+        eprintln!("Failed to load DLL {}: {}", dll_path, e);
+        std::process::exit(exit_code::LOAD);
+    });
+    if !config.machine {
+        if library_path == Path::new(dll_path) {
+            println!("Loaded DLL: {}", dll_path);
+        } else {
+            println!("Loaded DLL: {} ({})", dll_path, library_path.display());
+        }
+    }
+
+    // Sockets handed over by the service manager replace the configured ones.
+    let listeners: Vec<Listener> = if !inherited.is_empty() {
+        if !config.machine {
+            println!(
+                "DLL server listening on {} inherited socket(s)",
+                inherited.len()
+            );
+        }
+        inherited
+    } else {
+        config
+            .listen
+            .iter()
+            .map(|spec| {
+                let listener = Listener::bind(spec, config.reuse_address).unwrap_or_else(|e| {
+                    eprintln!("Failed to bind to {}: {}", spec, e);
+                    std::process::exit(exit_code::BIND);
+                });
+                if !config.machine {
+                    println!("DLL server listening on {}", spec);
+                }
+                listener
+            })
+            .collect()
+    };
+
+    let metrics_listener = config.metrics_port.map(|port| {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|e| {
+            eprintln!("Failed to bind metrics port {}: {}", port, e);
+            std::process::exit(exit_code::BIND);
+        });
+        if !config.machine {
+            println!("Metrics at http://127.0.0.1:{}/metrics", port);
+        }
+        listener
+    });
+
+    // Opened before dropping privileges, which may take away write access to it.
+    let access_log = config.access_log.as_ref().map(|path| {
+        AccessLog::open(path, config.access_log_max_bytes).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(exit_code::CANT_CREATE);
         })
+    });
+
+    if config.privileges.is_requested() {
+        if let Err(e) = config.privileges.apply() {
+            eprintln!("Failed to drop privileges: {}", e);
+            std::process::exit(exit_code::NO_PERMISSION);
+        }
+        if !config.machine {
+            println!("Dropped privileges: {:?}", config.privileges);
+        }
+    }
+
+    // Checked as the user uploads will be written by.
+    if let Some(Err(e)) = config.temp_dir.as_deref().map(registry::check_temp_dir) {
+        eprintln!("Invalid --temp-dir: {}", e);
+        std::process::exit(exit_code::CANT_CREATE);
+    }
+
+    // Handler threads inherit the affinity of the thread that spawns them.
+    if let Some(cpus) = &config.pin_cpu {
+        match cpus.pin_current_thread() {
+            Ok(()) if !config.machine => println!("Pinned to CPUs {}", cpus),
+            Ok(()) => {}
+            Err(e) => eprintln!("Failed to pin to CPUs {}: {}", cpus, e),
+        }
+    }
+
+    let mut schema = match &config.schema_path {
+        Some(path) => Schema::load(path, config.default_convention).unwrap_or_else(|e| {
+            eprintln!("Failed to load schema {}", e);
+            std::process::exit(exit_code::CONFIG);
+        }),
+        None => Schema::default(),
     };
-    println!("Loaded DLL: {}", dll_path);
 
-    let lib = Arc::new(lib);
+    let admin_token = config.admin_token_file.as_ref().map(|path| {
+        let token = std::fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read admin token {}: {}", path, e);
+            std::process::exit(exit_code::NO_INPUT);
+        });
+        let token = token.lines().next().unwrap_or_default().trim().to_string();
+        if token.is_empty() {
+            eprintln!("Admin token file {} is empty", path);
+            std::process::exit(exit_code::CONFIG);
+        }
+        token
+    });
 
-    let listener_addr = format!("127.0.0.1:{}", port);
-    let listener = TcpListener::bind(&listener_addr).unwrap_or_else(|e| {
-        eprintln!("Failed to bind to {}: {}", listener_addr, e);
-        std::process::exit(1);
+    let libraries = LibraryRegistry::new(
+        lib,
+        library_path,
+        LoadOptions {
+            case_insensitive_symbols: config.case_insensitive_symbols,
+            debug_info: config.debug_info,
+            max_concurrent_calls: config.max_concurrent_calls_per_lib,
+            temp_dir: config.temp_dir.clone(),
+        },
+        schema.take_serialization(),
+    );
+    let server = Arc::new(Server {
+        config,
+        libraries,
+        schema: RwLock::new(schema),
+        access_log,
+        handles: Arc::default(),
+        admin_token,
+        stats: Stats {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            active_connections: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            calls: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            latency: metrics::Histogram::new(),
+        },
+        wake_addresses: listeners
+            .iter()
+            .map(|listener| {
+                listener
+                    .wake_address()
+                    .expect("Listener has no local address")
+            })
+            .collect(),
+        shutting_down: AtomicBool::new(false),
     });
-    println!("DLL server listening on {}", listener_addr);
 
-    for stream in listener.incoming() {
+    if let Some(listener) = metrics_listener {
+        let server = Arc::clone(&server);
+        metrics::serve(listener, move || metrics_text(&server));
+    }
+
+    let on_sigterm = Arc::clone(&server);
+    if let Err(e) = signals::on_terminate(move || request_shutdown(&on_sigterm)) {
+        eprintln!("Failed to handle SIGTERM: {}", e);
+    }
+    if server.config.interrupt_on_timeout
+        && let Err(e) = signals::install_interrupt_handler()
+    {
+        eprintln!("Failed to handle SIGALRM: {}", e);
+    }
+
+    if server.config.machine {
+        // The first TCP listener's port, or 0 if there is none.
+        let port = listeners.iter().find_map(Listener::port).unwrap_or(0);
+        println!(
+            "EVENT ready port={} libs={}",
+            port,
+            server.libraries.count()
+        );
+    }
+
+    let accept_loops: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            let server = Arc::clone(&server);
+            thread::spawn(move || accept_loop(listener, &server))
+        })
+        .collect();
+    for accept_loop in accept_loops {
+        accept_loop.join().ok();
+    }
+
+    // Idle connections are not waited for; only requests already being handled.
+    let drain_started = Instant::now();
+    while server.stats.in_flight.load(Ordering::SeqCst) > 0
+        && drain_started.elapsed() < SHUTDOWN_DRAIN_TIMEOUT
+    {
+        thread::sleep(Duration::from_millis(10));
+    }
+    // Idle connections still hold the server, so nothing would drop these.
+    server.libraries.unload_all();
+    if !server.config.machine {
+        capture::server_stdout("Shut down\n").ok();
+    }
+}
+
+/// Serves every connection on a thread of its own until a shutdown is
+/// requested. With `--once`, serves the first connection on this thread
+/// instead and then shuts the server down.
+fn accept_loop(listener: Listener, server: &Arc<Server>) {
+    loop {
+        let stream = listener.accept();
+        if server.shutting_down.load(Ordering::SeqCst) {
+            break;
+        }
         match stream {
+            Ok(stream) if server.config.once => {
+                // Every listener is closed first, so later clients are
+                // refused rather than queued.
+                request_shutdown(server);
+                drop(listener);
+                server.stats.connections.fetch_add(1, Ordering::Relaxed);
+                // Every reply is written before the client's disconnect
+                // ends this and the process exits.
+                handle_client(stream, Arc::clone(server));
+                break;
+            }
             Ok(stream) => {
-                let lib_clone = Arc::clone(&lib);
+                server.stats.connections.fetch_add(1, Ordering::Relaxed);
+                let server = Arc::clone(server);
                 thread::spawn(move || {
-                    handle_client(stream, lib_clone);
+                    handle_client(stream, server);
                 });
             }
-            Err(e) => eprintln!("Connection failed: {}", e),
+            Err(e) => capture::log(format_args!("Connection failed: {}", e)),
         }
     }
 }
@@ -0,0 +1,87 @@
+/// Where to confine the server once its listener is bound.
+#[derive(Debug, Default)]
+pub struct PrivilegeDrop {
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub chroot: Option<String>,
+}
+
+impl PrivilegeDrop {
+    pub fn is_requested(&self) -> bool {
+        self.user.is_some() || self.group.is_some() || self.chroot.is_some()
+    }
+
+    /// Chroots and switches to the configured group and user, in that order.
+    /// Any failure is returned so the caller can refuse to serve.
+    #[cfg(unix)]
+    pub fn apply(&self) -> Result<(), String> {
+        use std::ffi::CString;
+
+        let uid = self.user.as_deref().map(lookup_user).transpose()?;
+        let gid = self.group.as_deref().map(lookup_group).transpose()?;
+
+        if let Some(dir) = &self.chroot {
+            let path = CString::new(dir.as_str()).map_err(|_| "Invalid chroot path")?;
+            if unsafe { libc::chroot(path.as_ptr()) } != 0 {
+                return Err(format!("chroot({}) failed: {}", dir, last_error()));
+            }
+            std::env::set_current_dir("/").map_err(|e| format!("chdir(/) failed: {}", e))?;
+        }
+        if let Some(gid) = gid {
+            if unsafe { libc::setgroups(0, std::ptr::null()) } != 0 {
+                return Err(format!("setgroups failed: {}", last_error()));
+            }
+            if unsafe { libc::setgid(gid) } != 0 {
+                return Err(format!("setgid({}) failed: {}", gid, last_error()));
+            }
+        }
+        if let Some(uid) = uid {
+            if unsafe { libc::setuid(uid) } != 0 {
+                return Err(format!("setuid({}) failed: {}", uid, last_error()));
+            }
+            // Being able to get root back means the drop did not stick.
+            if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+                return Err("Privileges could be regained after setuid".into());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self) -> Result<(), String> {
+        Err("--user, --group and --chroot are only supported on Unix".into())
+    }
+}
+
+#[cfg(unix)]
+fn last_error() -> std::io::Error {
+    std::io::Error::last_os_error()
+}
+
+/// Resolves a numeric uid or a user name.
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<libc::uid_t, String> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let name = std::ffi::CString::new(user).map_err(|_| "Invalid user name")?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("Unknown user: {}", user));
+    }
+    Ok(unsafe { (*entry).pw_uid })
+}
+
+/// Resolves a numeric gid or a group name.
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<libc::gid_t, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(group).map_err(|_| "Invalid group name")?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("Unknown group: {}", group));
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
@@ -0,0 +1,44 @@
+//! Per-connection request throttling with a token bucket.
+
+use std::time::Instant;
+
+/// How fast one connection may send requests, as given to `--rate-limit`
+/// and `--rate-limit-burst`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Requests per second the bucket refills by.
+    pub per_second: f64,
+    /// Requests that may arrive back to back; the bucket's capacity.
+    pub burst: f64,
+}
+
+/// A connection's bucket. It starts full and refills while the client is
+/// idle, so a burst after a quiet period is never throttled.
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket {
+            limit,
+            tokens: limit.burst,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Takes a token for one request; `false` if the bucket is empty.
+    pub fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * self.limit.per_second;
+        self.tokens = (self.tokens + earned).min(self.limit.burst);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
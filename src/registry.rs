@@ -0,0 +1,701 @@
+use crate::capture;
+use crate::debuginfo::{self, Prototype};
+use crate::demangle;
+use crate::exports;
+use crate::signature::{CallingConvention, FunctionSignature, ParamMode};
+use libloading::Library;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, c_void};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock};
+
+/// How every library in a registry is handled.
+#[derive(Debug, Default, Clone)]
+pub struct LoadOptions {
+    /// When an exact lookup fails, fall back to the one export whose name
+    /// matches ignoring ASCII case.
+    pub case_insensitive_symbols: bool,
+    /// Take signatures the client and schema omit from the library's DWARF
+    /// debug info.
+    pub debug_info: bool,
+    /// Most calls that may be running in one library at a time.
+    pub max_concurrent_calls: Option<usize>,
+    /// Where uploads are written before loading; the system temp dir if unset.
+    pub temp_dir: Option<PathBuf>,
+}
+
+/// Which calls into a library are not reentrant and must not overlap.
+#[derive(Debug, Default)]
+pub struct Serialization {
+    /// Every call into the library takes one shared lock.
+    pub whole_library: bool,
+    /// Calls to each of these functions take a lock of their own.
+    pub functions: HashSet<String>,
+}
+
+/// A library opened by the server, optionally backed by a temp file it owns.
+pub struct LoadedLibrary {
+    // Declared before `_temp_file` so the library is closed before the file is removed.
+    lib: Library,
+    path: PathBuf,
+    options: LoadOptions,
+    /// Addresses of symbols resolved so far, keyed by name.
+    symbols: Mutex<HashMap<String, usize>>,
+    /// The file's export table, read the first time it is needed.
+    exports: OnceLock<Result<Vec<String>, String>>,
+    /// Prototypes from the file's debug info, read the first time one is needed.
+    prototypes: OnceLock<Result<HashMap<String, Prototype>, String>>,
+    library_lock: Option<Mutex<()>>,
+    function_locks: HashMap<String, Mutex<()>>,
+    /// Calls currently running, counted against `max_concurrent_calls`.
+    active_calls: AtomicUsize,
+    _temp_file: Option<TempFile>,
+}
+
+impl LoadedLibrary {
+    fn new(
+        lib: Library,
+        path: PathBuf,
+        options: LoadOptions,
+        serialization: Serialization,
+        temp_file: Option<TempFile>,
+    ) -> Self {
+        LoadedLibrary {
+            lib,
+            path,
+            options,
+            symbols: Mutex::new(HashMap::new()),
+            exports: OnceLock::new(),
+            prototypes: OnceLock::new(),
+            library_lock: serialization.whole_library.then(|| Mutex::new(())),
+            function_locks: serialization
+                .functions
+                .into_iter()
+                .map(|name| (name, Mutex::new(())))
+                .collect(),
+            active_calls: AtomicUsize::new(0),
+            _temp_file: temp_file,
+        }
+    }
+
+    /// The file the library was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Takes the lock a call to `name` must hold, if the library or the
+    /// function is declared non-reentrant. Reentrant calls get `None` and run
+    /// concurrently.
+    pub fn lock_for_call(&self, name: &str) -> Option<MutexGuard<'_, ()>> {
+        self.library_lock
+            .as_ref()
+            .or_else(|| self.function_locks.get(name))
+            // A call that panicked cannot have left Rust state behind the lock.
+            .map(|lock| lock.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Counts a call against the library's concurrency limit until the
+    /// permit is dropped, or fails with `E_LIB_BUSY` if the limit is reached.
+    /// Calls over the limit are refused rather than queued, so a saturated
+    /// library cannot tie up every connection's thread.
+    pub fn enter_call(&self) -> Result<CallPermit<'_>, String> {
+        let Some(max) = self.options.max_concurrent_calls else {
+            return Ok(CallPermit(None));
+        };
+        self.active_calls
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < max).then_some(active + 1)
+            })
+            .map_err(|_| {
+                format!(
+                    "E_LIB_BUSY {} calls are already running in the library",
+                    max
+                )
+            })?;
+        Ok(CallPermit(Some(&self.active_calls)))
+    }
+
+    /// Resolves an exported symbol, caching successful lookups.
+    pub fn symbol(&self, name: &str) -> Result<*const c_void, String> {
+        if let Some(&address) = self.symbols.lock().unwrap().get(name) {
+            return Ok(address as *const c_void);
+        }
+        let address = self.lookup(&self.export_name(name)?)?;
+        self.symbols
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), address as usize);
+        Ok(address)
+    }
+
+    /// The export a lookup of `name` resolves to: `name` itself or, with
+    /// case-insensitive symbols, the one export matching it ignoring case.
+    /// A `cpp:<prototype>` name resolves to the C++ function it describes.
+    pub fn export_name(&self, name: &str) -> Result<String, String> {
+        if let Some(prototype) = name.strip_prefix("cpp:") {
+            return self.demangled_match(prototype);
+        }
+        match self.lookup(name) {
+            Ok(_) => Ok(name.to_string()),
+            Err(e) if self.options.case_insensitive_symbols => {
+                self.case_insensitive_match(name).ok_or(e)?
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<*const c_void, String> {
+        // Kept distinct from `E_NUL`, which is about string arguments.
+        let c_name = CString::new(name).map_err(|_| "E_BAD_NAME function name contains NUL")?;
+        unsafe {
+            let symbol = self
+                .lib
+                .get::<*const ()>(c_name.as_bytes_with_nul())
+                .map_err(|e| e.to_string())?;
+            Ok(*symbol as *const c_void)
+        }
+    }
+
+    /// The names this library's file exports.
+    pub fn exports(&self) -> Result<&[String], String> {
+        self.exports
+            .get_or_init(|| exports::exported_symbols(&self.path))
+            .as_deref()
+            .map_err(Clone::clone)
+    }
+
+    /// The signature of `name` according to the library's debug info, using
+    /// `convention` since DWARF does not record it. `None` if debug info is
+    /// not enabled or does not describe the function with supported types.
+    pub fn debug_signature(
+        &self,
+        name: &str,
+        convention: CallingConvention,
+    ) -> Result<Option<FunctionSignature>, String> {
+        if !self.options.debug_info {
+            return Ok(None);
+        }
+        let prototypes = self
+            .prototypes
+            .get_or_init(|| debuginfo::prototypes(&self.path))
+            .as_ref()
+            .map_err(Clone::clone)?;
+        Ok(prototypes.get(name).map(|prototype| FunctionSignature {
+            calling_convention: convention,
+            param_types: prototype.param_types.clone(),
+            param_modes: vec![ParamMode::In; prototype.param_types.len()],
+            return_type: prototype.return_type,
+            array_length_param: None,
+            null_status: false,
+        }))
+    }
+
+    /// The export that demangles to `prototype`, compared ignoring
+    /// whitespace, e.g. `ns::add(int,int)`; for a name without parameter
+    /// types, the one function of that name. An error if there is none, or
+    /// if the name alone matches several overloads.
+    fn demangled_match(&self, prototype: &str) -> Result<String, String> {
+        let wanted: String = prototype.split_whitespace().collect();
+        let by_name = !wanted.contains('(');
+        let mut matches: Vec<(String, &String)> = self
+            .exports()?
+            .iter()
+            .filter_map(|export| {
+                let demangled = demangle::demangle(export)?;
+                let compact: String = demangled.split_whitespace().collect();
+                let matched = if by_name {
+                    compact.split('(').next() == Some(&wanted)
+                } else {
+                    compact == wanted
+                };
+                matched.then_some((demangled, export))
+            })
+            .collect();
+        matches.sort();
+        matches.dedup();
+        match &matches[..] {
+            [] => Err(format!("No C++ function matches {}", prototype)),
+            [(_, export)] => Ok(export.to_string()),
+            _ => Err(format!(
+                "C++ function {} is overloaded: {}; give its parameter types",
+                prototype,
+                matches
+                    .iter()
+                    .map(|(demangled, _)| demangled.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// The unique export equal to `name` ignoring ASCII case: `None` if there
+    /// is none, an error if there are several.
+    fn case_insensitive_match(&self, name: &str) -> Option<Result<String, String>> {
+        let exports = match self.exports() {
+            Ok(exports) => exports,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut matches: Vec<&String> = exports
+            .iter()
+            .filter(|export| export.eq_ignore_ascii_case(name))
+            .collect();
+        matches.sort();
+        matches.dedup();
+        match matches[..] {
+            [] => None,
+            [export] => Some(Ok(export.clone())),
+            _ => Some(Err(format!(
+                "Symbol {} is ambiguous ignoring case: {}",
+                name,
+                matches
+                    .iter()
+                    .map(|m| m.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+}
+
+/// A call counted by [`LoadedLibrary::enter_call`].
+pub struct CallPermit<'a>(Option<&'a AtomicUsize>);
+
+impl Drop for CallPermit<'_> {
+    fn drop(&mut self) {
+        if let Some(active) = self.0 {
+            active.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A materialized upload, deleted again once its library is dropped.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            capture::log(format_args!("Failed to remove {}: {}", self.0.display(), e));
+        }
+    }
+}
+
+/// How the loader binds a library's symbols; only Unix has a choice.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenFlags {
+    /// Resolve every symbol the library uses when loading it (`RTLD_NOW`)
+    /// rather than each function on its first call (`RTLD_LAZY`).
+    pub bind_now: bool,
+    /// Make the library's symbols available to libraries loaded after it
+    /// (`RTLD_GLOBAL`) rather than only through its own handle (`RTLD_LOCAL`).
+    pub global: bool,
+}
+
+/// Where to open the library named `name` from. On Linux, a name without a
+/// `/` is looked up in the directories of `LD_LIBRARY_PATH`, then the
+/// standard library directories. `libfoo` or `libfoo.so` picks the highest
+/// `libfoo.so[.N...]` version in the first directory that has one, while a
+/// versioned name like `libfoo.so.3` is found exactly. Any other name, or one
+/// nothing is found for, is left to the loader as it is.
+#[cfg(target_os = "linux")]
+pub fn find_library(name: &str) -> PathBuf {
+    if name.contains('/') {
+        return PathBuf::from(name);
+    }
+    let dirs = library_dirs();
+    if name.contains(".so.") {
+        return dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| PathBuf::from(name));
+    }
+    let prefix = format!("{}.so", name.strip_suffix(".so").unwrap_or(name));
+    for dir in &dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let newest = entries
+            .flatten()
+            .filter_map(|entry| {
+                let version = soname_version(entry.file_name().to_str()?, &prefix)?;
+                Some((version, entry.path()))
+            })
+            .filter(|(_, path)| path.is_file())
+            .max();
+        if let Some((_, path)) = newest {
+            return path;
+        }
+    }
+    PathBuf::from(name)
+}
+
+/// Names are passed to the loader unchanged.
+#[cfg(not(target_os = "linux"))]
+pub fn find_library(name: &str) -> PathBuf {
+    PathBuf::from(name)
+}
+
+/// The directories [`find_library`] searches, in order, and dependencies are
+/// looked for in: those of `LD_LIBRARY_PATH`, then the standard ones for the
+/// server's word size, so a 32-bit server does not pick up 64-bit libraries
+/// or the other way round.
+#[cfg(unix)]
+fn library_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::var_os("LD_LIBRARY_PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let triplet = match std::env::consts::ARCH {
+        "x86" => "i386",
+        arch => arch,
+    };
+    let (lib, usr_lib) = if cfg!(target_pointer_width = "64") {
+        ("/lib64", "/usr/lib64")
+    } else {
+        ("/lib32", "/usr/lib32")
+    };
+    dirs.extend(
+        [
+            format!("/lib/{}-linux-gnu", triplet),
+            format!("/usr/lib/{}-linux-gnu", triplet),
+            lib.to_string(),
+            usr_lib.to_string(),
+            "/lib".to_string(),
+            "/usr/lib".to_string(),
+            "/usr/local/lib".to_string(),
+        ]
+        .map(PathBuf::from),
+    );
+    dirs
+}
+
+/// The version of `file` if it is `<prefix>` (no version, which sorts
+/// first) or `<prefix>.N[.N...]`.
+#[cfg(target_os = "linux")]
+fn soname_version(file: &str, prefix: &str) -> Option<Vec<u64>> {
+    let rest = file.strip_prefix(prefix)?;
+    if rest.is_empty() {
+        return Some(Vec::new());
+    }
+    rest.strip_prefix('.')?
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Opens the library at `path` with `flags`. When the loader refuses, the
+/// error names the dependencies that cannot be loaded either, since the
+/// loader's own message often does not (Windows only says a module was not
+/// found).
+pub fn open_library(path: &Path, flags: OpenFlags) -> Result<Library, String> {
+    let error = match load(path, flags) {
+        Ok(lib) => return Ok(lib),
+        Err(e) => e.to_string(),
+    };
+    // The file may not be a library at all, in which case the loader's message says so.
+    let Ok(imports) = exports::imports(path) else {
+        return Err(error);
+    };
+    let missing: Vec<&str> = imports
+        .libraries
+        .iter()
+        .map(String::as_str)
+        .filter(|name| !dependency_found(name, path, &imports.search_path))
+        .collect();
+    if missing.is_empty() {
+        return Err(error);
+    }
+    Err(format!(
+        "{} (dependencies not found: {})",
+        error,
+        missing.join(", ")
+    ))
+}
+
+/// Whether the loader can find `name`, a dependency of the library at
+/// `library`, judged from the file system so that nothing is loaded and no
+/// library code runs: a name with a `/` is a path, and any other is looked
+/// for in the library's own search path (`$ORIGIN` standing for its
+/// directory), `LD_LIBRARY_PATH`, the standard directories and those of
+/// `/etc/ld.so.conf`. A dependency that is found may still fail to load, for
+/// instance for being built for another architecture.
+#[cfg(unix)]
+fn dependency_found(name: &str, library: &Path, search_path: &[String]) -> bool {
+    if name.contains('/') {
+        return Path::new(name).is_file();
+    }
+    let origin = library
+        .parent()
+        .map_or_else(|| ".".to_string(), |dir| dir.display().to_string());
+    search_path
+        .iter()
+        .map(|dir| {
+            PathBuf::from(
+                dir.replace("${ORIGIN}", &origin)
+                    .replace("$ORIGIN", &origin),
+            )
+        })
+        .chain(library_dirs())
+        .chain(configured_library_dirs())
+        .any(|dir| dir.join(name).is_file())
+}
+
+/// Maps `name` as a data file, which looks it up as a dependency would be
+/// without running its `DllMain` or resolving its own imports.
+#[cfg(not(unix))]
+fn dependency_found(name: &str, _library: &Path, _search_path: &[String]) -> bool {
+    use libloading::os::windows;
+    unsafe { windows::Library::load_with_flags(name, windows::LOAD_LIBRARY_AS_DATAFILE) }.is_ok()
+}
+
+/// The directories listed in `/etc/ld.so.conf` and the files it includes,
+/// which the loader finds libraries in through its cache.
+#[cfg(unix)]
+fn configured_library_dirs() -> Vec<PathBuf> {
+    fn read(file: &Path, dirs: &mut Vec<PathBuf>, depth: usize) {
+        let Ok(text) = std::fs::read_to_string(file) else {
+            return;
+        };
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some(pattern) = line.strip_prefix("include") else {
+                if line.starts_with('/') {
+                    dirs.push(PathBuf::from(line));
+                }
+                continue;
+            };
+            if depth == 0 {
+                continue;
+            }
+            // Only the `<dir>/*.conf` form of glob is in common use.
+            let pattern = Path::new(pattern.trim());
+            let included: Vec<PathBuf> = match pattern.file_name().and_then(|name| name.to_str()) {
+                Some("*.conf") => {
+                    let dir = pattern.parent().unwrap_or(Path::new("/etc"));
+                    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+                        .into_iter()
+                        .flatten()
+                        .flatten()
+                        .map(|entry| entry.path())
+                        .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+                        .collect();
+                    files.sort();
+                    files
+                }
+                _ => vec![pattern.to_path_buf()],
+            };
+            for file in included {
+                read(&file, dirs, depth - 1);
+            }
+        }
+    }
+    let mut dirs = Vec::new();
+    read(Path::new("/etc/ld.so.conf"), &mut dirs, 4);
+    dirs
+}
+
+/// `dlopen`s `path` with the `RTLD_*` flags `flags` stands for.
+#[cfg(unix)]
+fn load(path: &Path, flags: OpenFlags) -> Result<Library, libloading::Error> {
+    use libloading::os::unix;
+    let binding = if flags.bind_now {
+        unix::RTLD_NOW
+    } else {
+        unix::RTLD_LAZY
+    };
+    let scope = if flags.global {
+        unix::RTLD_GLOBAL
+    } else {
+        unix::RTLD_LOCAL
+    };
+    unsafe { unix::Library::open(Some(path), binding | scope) }.map(Library::from)
+}
+
+/// Windows binds a DLL's imports when it is loaded, and a DLL only ever
+/// resolves against the DLLs it imports, so `flags` changes nothing.
+#[cfg(not(unix))]
+fn load(path: &Path, _flags: OpenFlags) -> Result<Library, libloading::Error> {
+    unsafe { Library::new(path) }
+}
+
+/// Checks at startup that uploads can be materialized in `dir`: it must be
+/// a directory the server can create files in, on a filesystem that allows
+/// mapping them executable. A `noexec` mount passes every other check and
+/// only fails once the first upload is loaded.
+pub fn check_temp_dir(dir: &Path) -> Result<(), String> {
+    let metadata = std::fs::metadata(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    if !metadata.is_dir() {
+        return Err(format!("{}: not a directory", dir.display()));
+    }
+    let probe = dir.join(format!("dllbridge32-{}-probe", std::process::id()));
+    create_private(&probe).map_err(|e| format!("{} is not writable: {}", dir.display(), e))?;
+    let _ = std::fs::remove_file(&probe);
+    check_executable(dir)
+}
+
+/// Creates `path` for writing, accessible only to the server's user. An
+/// existing file or symlink there is an error rather than being reused.
+fn create_private(path: &Path) -> std::io::Result<File> {
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+#[cfg(unix)]
+fn check_executable(dir: &Path) -> Result<(), String> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|_| format!("{}: path contains a NUL byte", dir.display()))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } < 0 {
+        return Err(format!(
+            "{}: {}",
+            dir.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    if stat.f_flag & libc::ST_NOEXEC != 0 {
+        return Err(format!(
+            "{} is on a noexec filesystem; uploaded libraries could not be loaded from it",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_executable(_dir: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// The startup library plus any libraries added at runtime under an alias.
+pub struct LibraryRegistry {
+    default: Arc<LoadedLibrary>,
+    aliases: RwLock<HashMap<String, Arc<LoadedLibrary>>>,
+    options: LoadOptions,
+}
+
+static UPLOAD_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+impl LibraryRegistry {
+    /// Wraps the startup library, opened from `path`.
+    pub fn new(
+        default: Library,
+        path: PathBuf,
+        options: LoadOptions,
+        serialization: Serialization,
+    ) -> Self {
+        LibraryRegistry {
+            default: Arc::new(LoadedLibrary::new(
+                default,
+                path,
+                options.clone(),
+                serialization,
+                None,
+            )),
+            aliases: RwLock::new(HashMap::new()),
+            options,
+        }
+    }
+
+    /// Number of loaded libraries, including the startup library.
+    pub fn count(&self) -> usize {
+        1 + self.aliases.read().unwrap().len()
+    }
+
+    /// Splits an `alias!function` reference and returns the library it targets.
+    /// Names without an alias resolve against the startup library.
+    pub fn resolve<'a>(&self, function: &'a str) -> Result<(Arc<LoadedLibrary>, &'a str), String> {
+        match function.split_once('!') {
+            Some((alias, name)) => {
+                let aliases = self.aliases.read().unwrap();
+                let lib = aliases
+                    .get(alias)
+                    .ok_or_else(|| format!("Unknown library alias: {}", alias))?;
+                Ok((Arc::clone(lib), name))
+            }
+            None => Ok((Arc::clone(&self.default), function)),
+        }
+    }
+
+    /// Writes `body` to a temp file and loads it under `alias`, replacing
+    /// (and cleaning up) any library previously registered with that alias.
+    pub fn upload(&self, alias: &str, body: &[u8], flags: OpenFlags) -> Result<(), String> {
+        validate_alias(alias)?;
+
+        let dir = self
+            .options
+            .temp_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        let path = dir.join(format!(
+            "dllbridge32-{}-{}-{}.{}",
+            std::process::id(),
+            UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed),
+            alias,
+            std::env::consts::DLL_EXTENSION
+        ));
+        let mut file = create_private(&path)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        let temp_file = TempFile(path);
+        file.write_all(body)
+            .map_err(|e| format!("Failed to write {}: {}", temp_file.0.display(), e))?;
+        drop(file);
+
+        let lib = open_library(&temp_file.0, flags)?;
+        let loaded = Arc::new(LoadedLibrary::new(
+            lib,
+            temp_file.0.clone(),
+            self.options.clone(),
+            Serialization::default(),
+            Some(temp_file),
+        ));
+        self.aliases
+            .write()
+            .unwrap()
+            .insert(alias.to_string(), loaded);
+        Ok(())
+    }
+
+    /// The aliases of the uploaded libraries, in order.
+    pub fn aliases(&self) -> Vec<String> {
+        let mut aliases: Vec<String> = self.aliases.read().unwrap().keys().cloned().collect();
+        aliases.sort();
+        aliases
+    }
+
+    /// Drops the library registered under `alias`. Calls already running keep
+    /// it loaded until they finish.
+    pub fn unload(&self, alias: &str) -> Result<(), String> {
+        self.aliases
+            .write()
+            .unwrap()
+            .remove(alias)
+            .map(drop)
+            .ok_or_else(|| format!("Unknown library alias: {}", alias))
+    }
+
+    /// Drops every aliased library, removing the temp files of uploads.
+    pub fn unload_all(&self) {
+        self.aliases.write().unwrap().clear();
+    }
+}
+
+fn validate_alias(alias: &str) -> Result<(), String> {
+    if alias.is_empty()
+        || !alias
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(format!("Invalid library alias: {}", alias));
+    }
+    Ok(())
+}
@@ -0,0 +1,468 @@
+use crate::integer::parse_int;
+use crate::registry::Serialization;
+use crate::signature::{
+    CallingConvention, FunctionSignature, ParamMode, SupportedType, parse_signature,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Trusted function declarations loaded with `--schema`.
+///
+/// The file holds one `<function> = <signature>` declaration per line, using
+/// the same signature syntax as `sig:` (including the calling convention),
+/// optionally followed by `; <attribute>=<value>` pairs. A line
+/// `@library <attribute>=<value>[; ...]` sets attributes of the startup
+/// library as a whole, and `@enum <Name> = <value>=<NAME>, ...` names integer
+/// values clients can pass to any `int` parameter as `arg:<Name>:<NAME>`.
+/// `@flags` declares bits the same way, which `arg:<Name>:<NAME>|<NAME>...`
+/// combines.
+/// Blank lines and lines starting with `#` are ignored.
+///
+/// `reentrant` (default `true`) applies to functions and the library;
+/// `reentrant=false` serializes calls to the function, or to the whole
+/// library. `enum=<value>=<NAME>, ...` names the values an `int` function
+/// returns, e.g. `enum=0=OK, 1=NOT_FOUND`. `allocates=true` marks a `ptr`
+/// return as memory the caller must release, and `frees=<N>` a function that
+/// releases the `ptr` passed as parameter `N`. `arg<N>=<min>..<max>` limits
+/// the `int` parameter `N` to a range, and `arg<N>=<value>|<value>...` an
+/// `int` or `str` parameter to a set of values. `redact=<N>|<N>...` hides
+/// those parameters' arguments in `history`.
+#[derive(Default)]
+pub struct Schema {
+    functions: HashMap<String, FunctionSignature>,
+    return_names: HashMap<String, Arc<ReturnNames>>,
+    ownership: HashMap<String, Ownership>,
+    /// The constraints on each function's parameters, by zero-based index.
+    constraints: HashMap<String, Vec<(usize, Constraint)>>,
+    /// The parameters whose arguments `history` hides, by zero-based index.
+    redacted: HashMap<String, Vec<usize>>,
+    enums: Arc<Enums>,
+    serialization: Serialization,
+}
+
+/// Whether a function hands out or takes back memory, so each connection's
+/// outstanding allocations can be tracked.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Ownership {
+    /// The returned `ptr` must be released by a `frees` function.
+    pub allocates: bool,
+    /// The zero-based index of the `ptr` parameter the function releases.
+    pub frees: Option<usize>,
+}
+
+/// Symbolic names of a function's integer return values.
+pub type ReturnNames = HashMap<i32, String>;
+
+/// The `@enum` and `@flags` declarations, by name, which
+/// `arg:<Name>:<NAME>` arguments resolve against.
+#[derive(Debug, Default)]
+pub struct Enums(HashMap<String, EnumType>);
+
+#[derive(Debug)]
+struct EnumType {
+    members: Vec<(String, i32)>,
+    /// Declared with `@flags`, so several members may be OR-ed together.
+    flags: bool,
+}
+
+impl Enums {
+    /// The value of an `arg:<Name>:<NAME>` token, or of
+    /// `arg:<Name>:<NAME>|<NAME>...` for flags, or `None` if `token` is not
+    /// one and is left to the usual integer parsing.
+    pub fn resolve(&self, token: &str) -> Option<Result<i32, String>> {
+        let symbol = token.strip_prefix("arg:")?;
+        Some(self.resolve_symbol(token, symbol))
+    }
+
+    fn resolve_symbol(&self, token: &str, symbol: &str) -> Result<i32, String> {
+        let (name, members) = symbol
+            .split_once(':')
+            .ok_or_else(|| format!("E_ENUM expected arg:<enum>:<member>, not '{}'", token))?;
+        let ty = self
+            .0
+            .get(name)
+            .ok_or_else(|| format!("E_ENUM unknown enum '{}'", name))?;
+        if !ty.flags && members.contains('|') {
+            return Err(format!(
+                "E_ENUM {} is not declared with @flags; give one member",
+                name
+            ));
+        }
+        members.split('|').try_fold(0, |value, member| {
+            ty.members
+                .iter()
+                .find(|(known, _)| known == member)
+                .map(|&(_, bits)| value | bits)
+                .ok_or_else(|| {
+                    let names: Vec<&str> =
+                        ty.members.iter().map(|(name, _)| name.as_str()).collect();
+                    format!(
+                        "E_ENUM {} has no member '{}' (expected {})",
+                        name,
+                        member,
+                        names.join("|")
+                    )
+                })
+        })
+    }
+}
+
+/// The values a parameter is declared to accept; others are refused with
+/// `E_CONSTRAINT` before the function is called.
+#[derive(Debug, Clone, PartialEq)]
+enum Constraint {
+    /// An `int` from `min` to `max`, inclusive.
+    Range { min: i32, max: i32 },
+    /// One of the listed `int` values.
+    Ints(Vec<i32>),
+    /// One of the listed `str` values, compared exactly.
+    Strings(Vec<String>),
+}
+
+/// The attributes following a declaration.
+struct Attributes {
+    reentrant: bool,
+    return_names: Option<ReturnNames>,
+    ownership: Ownership,
+    /// `arg<N>` attributes as zero-based index and text, read once the
+    /// parameter's type is known.
+    constraints: Vec<(usize, String)>,
+    /// `redact` parameters as zero-based indices.
+    redacted: Vec<usize>,
+}
+
+impl Schema {
+    pub fn load(path: &str, default_convention: CallingConvention) -> Result<Schema, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read schema {}: {}", path, e))?;
+        Schema::parse(&text, default_convention).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str, default_convention: CallingConvention) -> Result<Schema, String> {
+        let mut schema = Schema::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line_error = |e: String| format!("line {}: {}", index + 1, e);
+            let enum_declaration = [("@enum", false), ("@flags", true)]
+                .into_iter()
+                .find_map(|(keyword, flags)| Some((keyword, line.strip_prefix(keyword)?, flags)));
+            if let Some((keyword, declaration, flags)) = enum_declaration {
+                let (name, members) = declaration.split_once('=').ok_or_else(|| {
+                    line_error(format!("expected '{} <name> = <members>'", keyword))
+                })?;
+                let name = name.trim();
+                if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ':') {
+                    return Err(line_error(format!("Invalid enum name: {}", name)));
+                }
+                let members = parse_enum_members(members, flags).map_err(line_error)?;
+                let enums = Arc::get_mut(&mut schema.enums).expect("not shared while parsing");
+                if enums
+                    .0
+                    .insert(name.to_string(), EnumType { members, flags })
+                    .is_some()
+                {
+                    return Err(line_error(format!("Enum {} is declared twice", name)));
+                }
+                continue;
+            }
+            if let Some(attributes) = line.strip_prefix("@library") {
+                let attributes = parse_attributes(attributes.split(';')).map_err(line_error)?;
+                if attributes.return_names.is_some()
+                    || attributes.ownership != Ownership::default()
+                    || !attributes.constraints.is_empty()
+                    || !attributes.redacted.is_empty()
+                {
+                    return Err(line_error(
+                        "enum, allocates, frees, arg<N> and redact only apply to functions".into(),
+                    ));
+                }
+                schema.serialization.whole_library = !attributes.reentrant;
+                continue;
+            }
+            let mut parts = line.split(';');
+            let declaration = parts.next().unwrap_or_default();
+            let (name, signature) = declaration
+                .split_once('=')
+                .ok_or_else(|| line_error("expected '<function> = <signature>'".into()))?;
+            let name = name.trim().to_string();
+            let signature =
+                parse_signature(signature.trim(), default_convention).map_err(line_error)?;
+            let attributes = parse_attributes(parts).map_err(line_error)?;
+            if !attributes.reentrant {
+                schema.serialization.functions.insert(name.clone());
+            }
+            if let Some(names) = attributes.return_names {
+                if signature.return_type != SupportedType::Int
+                    || signature.array_length_param.is_some()
+                {
+                    return Err(line_error("enum requires an int return type".into()));
+                }
+                schema.return_names.insert(name.clone(), Arc::new(names));
+            }
+            let ownership = attributes.ownership;
+            if ownership.allocates
+                && (signature.return_type != SupportedType::Ptr
+                    || signature.array_length_param.is_some())
+            {
+                return Err(line_error("allocates requires a ptr return type".into()));
+            }
+            if let Some(index) = ownership.frees {
+                let param = signature
+                    .param_types
+                    .get(index)
+                    .zip(signature.param_modes.get(index));
+                if param != Some((&SupportedType::Ptr, &ParamMode::In)) {
+                    return Err(line_error(format!(
+                        "frees={} must name a ptr parameter",
+                        index + 1
+                    )));
+                }
+            }
+            if ownership != Ownership::default() {
+                schema.ownership.insert(name.clone(), ownership);
+            }
+            let constraints = attributes
+                .constraints
+                .iter()
+                .map(|(index, text)| Ok((*index, parse_constraint(&signature, *index, text)?)))
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(line_error)?;
+            if !constraints.is_empty() {
+                schema.constraints.insert(name.clone(), constraints);
+            }
+            if let Some(index) = attributes
+                .redacted
+                .iter()
+                .find(|&&index| signature.argument_position(index).is_none())
+            {
+                return Err(line_error(format!(
+                    "redact={} must name a parameter the client passes",
+                    index + 1
+                )));
+            }
+            if !attributes.redacted.is_empty() {
+                schema.redacted.insert(name.clone(), attributes.redacted);
+            }
+            schema.functions.insert(name, signature);
+        }
+        Ok(schema)
+    }
+
+    /// The declared signature of `function`, if the schema has one.
+    pub fn signature(&self, function: &str) -> Option<&FunctionSignature> {
+        self.functions.get(function)
+    }
+
+    /// The names the schema gives `function`'s return values, if any.
+    pub fn return_names(&self, function: &str) -> Option<Arc<ReturnNames>> {
+        self.return_names.get(function).cloned()
+    }
+
+    /// The zero-based indices of the parameters of `function` whose arguments
+    /// `history` hides.
+    pub fn redacted(&self, function: &str) -> Vec<usize> {
+        self.redacted.get(function).cloned().unwrap_or_default()
+    }
+
+    /// The declared enums, for resolving the arguments of one call.
+    pub fn enums(&self) -> Arc<Enums> {
+        self.enums.clone()
+    }
+
+    /// Whether `function` allocates or frees memory, per the schema.
+    pub fn ownership(&self, function: &str) -> Option<Ownership> {
+        self.ownership.get(function).copied()
+    }
+
+    /// Checks `args`, the client's arguments to a call of `function` made with
+    /// `signature`, against the constraints the schema declares. Arguments
+    /// that are missing or not constrained are left to the call itself.
+    pub fn check_constraints(
+        &self,
+        function: &str,
+        signature: &FunctionSignature,
+        args: &[&str],
+    ) -> Result<(), String> {
+        let Some(constraints) = self.constraints.get(function) else {
+            return Ok(());
+        };
+        for (index, constraint) in constraints {
+            let Some(&token) = signature
+                .argument_position(*index)
+                .and_then(|position| args.get(position))
+            else {
+                continue;
+            };
+            let position = index + 1;
+            let int = || match self.enums.resolve(token) {
+                Some(value) => value,
+                None => parse_int::<i32>(token).map_err(|e| e.describe(token, SupportedType::Int)),
+            };
+            match constraint {
+                Constraint::Range { min, max } => {
+                    if !(*min..=*max).contains(&int()?) {
+                        return Err(format!(
+                            "E_CONSTRAINT arg[{}] out of range [{},{}]",
+                            position, min, max
+                        ));
+                    }
+                }
+                Constraint::Ints(values) => {
+                    if !values.contains(&int()?) {
+                        return Err(not_one_of(position, values));
+                    }
+                }
+                Constraint::Strings(values) => {
+                    if !values.iter().any(|value| value == token) {
+                        return Err(not_one_of(position, values));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Which calls into the startup library the schema declares non-reentrant.
+    pub fn take_serialization(&mut self) -> Serialization {
+        std::mem::take(&mut self.serialization)
+    }
+}
+
+/// Reads `key=value` attributes.
+fn parse_attributes<'a>(attributes: impl Iterator<Item = &'a str>) -> Result<Attributes, String> {
+    let mut parsed = Attributes {
+        reentrant: true,
+        return_names: None,
+        ownership: Ownership::default(),
+        constraints: Vec::new(),
+        redacted: Vec::new(),
+    };
+    for attribute in attributes.map(str::trim).filter(|a| !a.is_empty()) {
+        match attribute.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("reentrant", "true")) => parsed.reentrant = true,
+            Some(("reentrant", "false")) => parsed.reentrant = false,
+            Some(("enum", names)) => parsed.return_names = Some(parse_return_names(names)?),
+            Some(("allocates", "true")) => parsed.ownership.allocates = true,
+            Some(("allocates", "false")) => parsed.ownership.allocates = false,
+            Some(("frees", position)) => parsed.ownership.frees = Some(parse_position(position)?),
+            Some(("redact", positions)) => {
+                for position in positions.split('|') {
+                    parsed.redacted.push(parse_position(position.trim())?);
+                }
+            }
+            Some((key, text)) if key.starts_with("arg") => {
+                let index = parse_position(&key[3..])?;
+                parsed.constraints.push((index, text.to_string()));
+            }
+            _ => return Err(format!("Unknown attribute: {}", attribute)),
+        }
+    }
+    Ok(parsed)
+}
+
+/// The error for an argument at `position` outside the allowed `values`.
+fn not_one_of(position: usize, values: &[impl ToString]) -> String {
+    let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+    format!(
+        "E_CONSTRAINT arg[{}] is not one of {}",
+        position,
+        values.join("|")
+    )
+}
+
+/// Parses a 1-based parameter position into a zero-based index.
+fn parse_position(position: &str) -> Result<usize, String> {
+    position
+        .parse::<usize>()
+        .ok()
+        .and_then(|position| position.checked_sub(1))
+        .ok_or_else(|| format!("Invalid parameter position: {}", position))
+}
+
+/// Parses the `arg<N>` attribute `text` for parameter `index` of `signature`:
+/// `<min>..<max>` for an `int`, or `<value>|<value>...` for an `int` or `str`.
+fn parse_constraint(
+    signature: &FunctionSignature,
+    index: usize,
+    text: &str,
+) -> Result<Constraint, String> {
+    let position = index + 1;
+    let ty = signature
+        .param_types
+        .get(index)
+        .filter(|_| signature.argument_position(index).is_some())
+        .copied();
+    let invalid = || format!("Invalid constraint for arg{}: {}", position, text);
+    let int = |token: &str| parse_int::<i32>(token.trim()).map_err(|_| invalid());
+    match ty {
+        Some(SupportedType::Int) => match text.split_once("..") {
+            Some((min, max)) => {
+                let (min, max) = (int(min)?, int(max)?);
+                if min > max {
+                    return Err(invalid());
+                }
+                Ok(Constraint::Range { min, max })
+            }
+            None => Ok(Constraint::Ints(
+                text.split('|').map(int).collect::<Result<_, _>>()?,
+            )),
+        },
+        Some(SupportedType::Str | SupportedType::StrLen { .. }) => {
+            let values: Vec<String> = text.split('|').map(|v| v.trim().to_string()).collect();
+            if values.iter().any(String::is_empty) {
+                return Err(invalid());
+            }
+            Ok(Constraint::Strings(values))
+        }
+        _ => Err(format!(
+            "arg{} must name an int or str parameter the client passes",
+            position
+        )),
+    }
+}
+
+/// Parses the members of an `@enum` or, with `flags`, `@flags`:
+/// `<value>=<NAME>, ...`. Several names may share a value. Flags are bit
+/// patterns, so they may also be given as unsigned, e.g. `0x80000000`.
+fn parse_enum_members(text: &str, flags: bool) -> Result<Vec<(String, i32)>, String> {
+    let mut members: Vec<(String, i32)> = Vec::new();
+    for entry in text.split(',') {
+        let invalid = || format!("Invalid enum entry: {}", entry.trim());
+        let (value, name) = entry.split_once('=').ok_or_else(invalid)?;
+        let value = match parse_int::<i32>(value.trim()) {
+            Err(_) if flags => parse_int::<u32>(value.trim()).map(|bits| bits as i32),
+            value => value,
+        }
+        .map_err(|_| invalid())?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '|') {
+            return Err(invalid());
+        }
+        if members.iter().any(|(other, _)| other == name) {
+            return Err(format!("Duplicate enum member: {}", name));
+        }
+        members.push((name.to_string(), value));
+    }
+    Ok(members)
+}
+
+/// Parses `<value>=<NAME>, ...`.
+fn parse_return_names(text: &str) -> Result<ReturnNames, String> {
+    let mut names = ReturnNames::new();
+    for entry in text.split(',') {
+        let invalid = || format!("Invalid enum entry: {}", entry.trim());
+        let (value, name) = entry.split_once('=').ok_or_else(invalid)?;
+        let value: i32 = value.trim().parse().map_err(|_| invalid())?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        if names.insert(value, name.to_string()).is_some() {
+            return Err(format!("Duplicate enum value: {}", value));
+        }
+    }
+    Ok(names)
+}
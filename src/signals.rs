@@ -0,0 +1,127 @@
+//! Turns `SIGTERM` into the same orderly shutdown as the `shutdown` command,
+//! and, with `--interrupt-on-timeout`, uses `SIGALRM` to interrupt the
+//! blocking system call a timed-out call is stuck in.
+//!
+//! The `SIGTERM` handler only writes a byte to a pipe; a thread waiting on
+//! the other end runs the shutdown, where allocating and locking are allowed.
+
+use std::io;
+
+#[cfg(unix)]
+static WAKE_FD: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(-1);
+
+#[cfg(unix)]
+extern "C" fn on_sigterm(_: libc::c_int) {
+    let fd = WAKE_FD.load(std::sync::atomic::Ordering::Relaxed);
+    // `write` is async-signal-safe; a full pipe means a shutdown is already pending.
+    unsafe { libc::write(fd, b"x".as_ptr() as *const libc::c_void, 1) };
+}
+
+/// Runs `shutdown` on a thread of its own once the process receives
+/// `SIGTERM`. Later signals are ignored while the shutdown runs.
+#[cfg(unix)]
+pub fn on_terminate(shutdown: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read_fd, write_fd] = fds;
+    for fd in fds {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    // Never block in the handler, even if signals arrive faster than they are read.
+    unsafe { libc::fcntl(write_fd, libc::F_SETFL, libc::O_NONBLOCK) };
+    WAKE_FD.store(write_fd, std::sync::atomic::Ordering::Relaxed);
+
+    std::thread::spawn(move || {
+        let mut byte = 0u8;
+        loop {
+            let read = unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if read == 1 {
+                break;
+            }
+            if read < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            // The pipe is gone, so no signal can arrive any more.
+            return;
+        }
+        shutdown();
+    });
+
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = on_sigterm as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    if unsafe { libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// There is no `SIGTERM` to handle; `shutdown` is never run.
+#[cfg(not(unix))]
+pub fn on_terminate(_shutdown: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+extern "C" fn on_sigalrm(_: libc::c_int) {}
+
+/// Makes `SIGALRM` do nothing but interrupt the system call the thread that
+/// receives it is blocked in, which then fails with `EINTR` instead of being
+/// restarted.
+#[cfg(unix)]
+pub fn install_interrupt_handler() -> io::Result<()> {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = on_sigalrm as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // No `SA_RESTART`, or the interrupted call would simply be made again.
+    action.sa_flags = 0;
+    if unsafe { libc::sigaction(libc::SIGALRM, &action, std::ptr::null_mut()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// There are no signals to interrupt a call with.
+#[cfg(not(unix))]
+pub fn install_interrupt_handler() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--interrupt-on-timeout is only supported on Unix",
+    ))
+}
+
+/// The thread running a call, while it runs, so a timeout can interrupt it.
+#[derive(Default)]
+pub struct CallThread {
+    #[cfg(unix)]
+    thread: std::sync::Mutex<Option<libc::pthread_t>>,
+}
+
+impl CallThread {
+    /// Marks the current thread as running the call.
+    pub fn enter(&self) {
+        #[cfg(unix)]
+        {
+            *self.thread.lock().unwrap() = Some(unsafe { libc::pthread_self() });
+        }
+    }
+
+    /// Marks the call as returned; it can no longer be interrupted.
+    pub fn leave(&self) {
+        #[cfg(unix)]
+        {
+            *self.thread.lock().unwrap() = None;
+        }
+    }
+
+    /// Sends `SIGALRM` to the thread if it is still running the call. The
+    /// lock keeps the thread from exiting, and its id from being reused,
+    /// while the signal is sent.
+    pub fn interrupt(&self) {
+        #[cfg(unix)]
+        if let Some(thread) = *self.thread.lock().unwrap() {
+            unsafe { libc::pthread_kill(thread, libc::SIGALRM) };
+        }
+    }
+}
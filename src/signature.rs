@@ -0,0 +1,834 @@
+use crate::longdouble;
+use libffi::middle::Type;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SupportedType {
+    Int,
+    Float,
+    Char,
+    Void,
+    /// A null-terminated `char*`, converted using the call's string encoding.
+    Str,
+    /// A counted string: a `char*` to the encoded bytes followed by a
+    /// `size_t` byte count the server inserts. One argument on the wire, two
+    /// in the native call. No NUL is appended unless `nul` (`str_len:nul`),
+    /// for APIs that take a length but also read up to a terminator; the
+    /// count never includes it.
+    StrLen {
+        nul: bool,
+    },
+    /// A C `long double` in the host's format, where the server supports it
+    /// (see [`longdouble`](crate::longdouble)).
+    LongDouble,
+    /// An opaque address, exchanged with clients as a `0x`-prefixed hex token.
+    Ptr,
+    /// A file opened with `admin open`, given by its `fd:<n>` token and
+    /// passed as the host's handle type (see [`handles`](crate::handles)).
+    Handle,
+}
+
+/// Every accepted type name, so parsing and capability reports share one list.
+pub const TYPE_NAMES: &[(&str, SupportedType)] = &[
+    ("int", SupportedType::Int),
+    ("float", SupportedType::Float),
+    ("char", SupportedType::Char),
+    ("void", SupportedType::Void),
+    ("str", SupportedType::Str),
+    ("string", SupportedType::Str),
+    ("char*", SupportedType::Str),
+    ("cstr", SupportedType::Str),
+    ("str_len", SupportedType::StrLen { nul: false }),
+    ("str_len:nul", SupportedType::StrLen { nul: true }),
+    ("longdouble", SupportedType::LongDouble),
+    ("ptr", SupportedType::Ptr),
+    ("pointer", SupportedType::Ptr),
+    ("void*", SupportedType::Ptr),
+    ("handle", SupportedType::Handle),
+    ("fd", SupportedType::Handle),
+];
+
+impl FromStr for SupportedType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase();
+        TYPE_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, ty)| ty)
+            .ok_or_else(|| format!("Unsupported type: {}", s))
+    }
+}
+
+impl SupportedType {
+    /// The libffi type a value of this type is passed and returned as; for
+    /// `str_len`, that of its pointer, which the server follows with a
+    /// `size_t` length. Every signature is checked against this when it is
+    /// parsed, so a type the host cannot represent is refused with
+    /// `E_UNSUPPORTED_TYPE` instead of producing an invalid call interface.
+    pub fn ffi_type(self) -> Result<Type, String> {
+        Ok(match self {
+            SupportedType::Int => Type::i32(),
+            SupportedType::Float => Type::f32(),
+            SupportedType::Char => Type::i8(),
+            SupportedType::Void => Type::void(),
+            SupportedType::Str | SupportedType::StrLen { .. } | SupportedType::Ptr => {
+                Type::pointer()
+            }
+            SupportedType::Handle if cfg!(unix) => Type::c_int(),
+            SupportedType::Handle => Type::pointer(),
+            SupportedType::LongDouble => {
+                longdouble::ffi_type().ok_or_else(|| self.unsupported())?
+            }
+        })
+    }
+
+    /// The error for a type the host cannot represent.
+    fn unsupported(self) -> String {
+        format!(
+            "E_UNSUPPORTED_TYPE type '{}' not supported on this platform",
+            self
+        )
+    }
+
+    /// The names accepted for the type besides its canonical one.
+    pub fn aliases(self) -> impl Iterator<Item = &'static str> {
+        TYPE_NAMES
+            .iter()
+            .filter(move |&&(_, ty)| ty == self)
+            .skip(1)
+            .map(|&(name, _)| name)
+    }
+
+    /// The bytes a value occupies in the native call on this host: none for
+    /// `void`, and for `str_len` its pointer plus the `size_t` that follows.
+    pub fn width(self) -> Result<usize, String> {
+        let size = |ty: Type| unsafe { (*ty.as_raw_ptr()).size };
+        Ok(match self {
+            SupportedType::Void => 0,
+            SupportedType::StrLen { .. } => size(Type::pointer()) + size(Type::usize()),
+            ty => size(ty.ffi_type()?),
+        })
+    }
+
+    /// Whether a value is passed as an address rather than by value.
+    pub fn is_pointer(self) -> bool {
+        match self {
+            SupportedType::Str | SupportedType::StrLen { .. } | SupportedType::Ptr => true,
+            SupportedType::Handle => !cfg!(unix),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for SupportedType {
+    /// The canonical name, the first listed for the type in `TYPE_NAMES`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = TYPE_NAMES
+            .iter()
+            .find(|&&(_, ty)| ty == *self)
+            .map_or("?", |&(name, _)| name);
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallingConvention {
+    Cdecl,
+    Stdcall,
+    Fastcall,
+    Thiscall,
+    /// The Microsoft x64 convention, also usable on other x86-64 hosts (`ms_abi`).
+    Win64,
+}
+
+impl FromStr for CallingConvention {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let convention = match s.trim().to_lowercase().as_str() {
+            "cdecl" | "c" => CallingConvention::Cdecl,
+            "stdcall" | "winapi" => CallingConvention::Stdcall,
+            "fastcall" => CallingConvention::Fastcall,
+            "thiscall" => CallingConvention::Thiscall,
+            "win64" | "ms_abi" => CallingConvention::Win64,
+            _ => return Err(format!("Unknown calling convention: {}", s)),
+        };
+        convention.abi()?;
+        Ok(convention)
+    }
+}
+
+impl fmt::Display for CallingConvention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CallingConvention::Cdecl => "cdecl",
+            CallingConvention::Stdcall => "stdcall",
+            CallingConvention::Fastcall => "fastcall",
+            CallingConvention::Thiscall => "thiscall",
+            CallingConvention::Win64 => "win64",
+        })
+    }
+}
+
+impl CallingConvention {
+    /// The libffi ABI implementing this convention on the host, or an error if
+    /// the host has no such convention.
+    #[cfg(target_arch = "x86")]
+    pub fn abi(self) -> Result<libffi::low::ffi_abi, String> {
+        use libffi::raw;
+        Ok(match self {
+            CallingConvention::Cdecl => raw::ffi_abi_FFI_DEFAULT_ABI,
+            CallingConvention::Stdcall => raw::ffi_abi_FFI_STDCALL,
+            CallingConvention::Fastcall => raw::ffi_abi_FFI_FASTCALL,
+            CallingConvention::Thiscall => raw::ffi_abi_FFI_THISCALL,
+            CallingConvention::Win64 => return Err("Win64 is not supported on this host".into()),
+        })
+    }
+
+    /// The libffi ABI implementing this convention on the host, or an error if
+    /// the host has no such convention.
+    ///
+    /// Compilers accept and ignore the 32-bit convention keywords on x86-64,
+    /// so they all map to the platform's default ABI.
+    #[cfg(target_arch = "x86_64")]
+    pub fn abi(self) -> Result<libffi::low::ffi_abi, String> {
+        Ok(match self {
+            CallingConvention::Win64 => libffi::raw::ffi_abi_FFI_WIN64,
+            _ => libffi::raw::ffi_abi_FFI_DEFAULT_ABI,
+        })
+    }
+
+    /// The libffi ABI implementing this convention on the host, or an error if
+    /// the host has no such convention.
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    pub fn abi(self) -> Result<libffi::low::ffi_abi, String> {
+        match self {
+            CallingConvention::Cdecl => Ok(libffi::raw::ffi_abi_FFI_DEFAULT_ABI),
+            other => Err(format!("{:?} is not supported on this host", other)),
+        }
+    }
+}
+
+/// How a parameter's value travels between client and function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamMode {
+    /// Passed by value from the client's argument (the default).
+    In,
+    /// Passed as a pointer to server-owned storage of the parameter type,
+    /// which the function fills in; the client sends no argument and the
+    /// value is read back into the reply.
+    Out,
+    /// Like `Out`, but the storage starts out holding the client's argument.
+    InOut,
+    /// `buf:<size>[:hex|:str]`: a zeroed buffer of `size` bytes the server
+    /// allocates and passes as a `ptr`, for functions that write into memory
+    /// the caller owns. The client sends no argument and the contents are
+    /// read back into the reply in `format`.
+    Buffer { size: usize, format: BufferFormat },
+    /// `out struct{<name>:<type>,...}`: a zeroed struct of the given layout
+    /// that the server allocates and passes as a `ptr`, for functions that
+    /// fill in a results struct the caller owns. The client sends no argument
+    /// and each field is read back into the reply by name.
+    Struct(StructLayout),
+}
+
+/// The fields of an `out struct{...}` parameter, laid out as the C compiler
+/// lays out the same struct for the host's default ABI, or under
+/// `#pragma pack(N)` for an `out struct pack(N){...}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructLayout {
+    pub fields: Vec<StructField>,
+    /// The size of the whole struct, including trailing padding.
+    pub size: usize,
+    /// The most bytes any field is aligned to; `None` for natural alignment.
+    pub pack: Option<usize>,
+}
+
+/// The `pack(N)` values a struct may be declared with, as MSVC, GCC and
+/// Clang accept them.
+pub const STRUCT_PACKINGS: [usize; 5] = [1, 2, 4, 8, 16];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructField {
+    pub name: String,
+    pub ty: SupportedType,
+    /// The byte offset of the field from the start of the struct.
+    pub offset: usize,
+}
+
+impl fmt::Display for StructLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| format!("{}:{}", field.name, field.ty))
+            .collect();
+        match self.pack {
+            Some(pack) => write!(f, "struct pack({}){{{}}}", pack, fields.join(",")),
+            None => write!(f, "struct{{{}}}", fields.join(",")),
+        }
+    }
+}
+
+/// How the contents of a `buf:` parameter are reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferFormat {
+    /// Every byte, as lowercase hex digits (the default).
+    Hex,
+    /// The bytes up to the first NUL, decoded like a `str` result.
+    Str,
+}
+
+impl ParamMode {
+    /// Whether the server reads the parameter back after the call.
+    pub fn is_output(&self) -> bool {
+        *self != ParamMode::In
+    }
+
+    /// Whether the client sends an argument for the parameter.
+    pub fn takes_argument(&self) -> bool {
+        matches!(self, ParamMode::In | ParamMode::InOut)
+    }
+}
+
+/// Formats a parameter in the syntax `parse_signature` accepts.
+pub fn format_param(ty: SupportedType, mode: &ParamMode) -> String {
+    match mode {
+        ParamMode::In => ty.to_string(),
+        ParamMode::Out => format!("out {}", ty),
+        ParamMode::InOut => format!("inout {}", ty),
+        ParamMode::Buffer {
+            size,
+            format: BufferFormat::Hex,
+        } => format!("buf:{}", size),
+        ParamMode::Buffer {
+            size,
+            format: BufferFormat::Str,
+        } => format!("buf:{}:str", size),
+        ParamMode::Struct(layout) => format!("out {}", layout),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionSignature {
+    pub calling_convention: CallingConvention,
+    pub param_types: Vec<SupportedType>,
+    /// The mode of each parameter, parallel to `param_types`.
+    pub param_modes: Vec<ParamMode>,
+    pub return_type: SupportedType,
+    /// For `<type>[out:N]` returns: the zero-based index of the `ptr` parameter
+    /// through which the function reports how many elements the returned
+    /// array holds. `return_type` is then the element type.
+    pub array_length_param: Option<usize>,
+    /// For `ptr[status]` returns: follow the address with `status=ok`, or
+    /// `status=error` when it is null.
+    pub null_status: bool,
+}
+
+impl FunctionSignature {
+    /// Where the argument for parameter `index` is among the client's
+    /// arguments, which skip the parameters the server supplies; `None` for
+    /// those.
+    pub fn argument_position(&self, index: usize) -> Option<usize> {
+        let supplied_by_client =
+            |i: usize| self.param_modes[i].takes_argument() && self.array_length_param != Some(i);
+        supplied_by_client(index).then(|| (0..index).filter(|&i| supplied_by_client(i)).count())
+    }
+}
+
+impl fmt::Display for FunctionSignature {
+    /// Formats the signature in the syntax `parse_signature` accepts, always
+    /// naming the calling convention.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<String> = self
+            .param_types
+            .iter()
+            .zip(&self.param_modes)
+            .map(|(&ty, mode)| format_param(ty, mode))
+            .collect();
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(",")
+        };
+        write!(
+            f,
+            "{}({})->{}",
+            params, self.calling_convention, self.return_type
+        )?;
+        if let Some(index) = self.array_length_param {
+            write!(f, "[out:{}]", index + 1)?;
+        }
+        if self.null_status {
+            f.write_str("[status]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reassembles a `sig:<signature>` at the start of `tokens`, which splitting
+/// the request on whitespace may have cut into several tokens. Returns the
+/// signature (without `sig:`), if `tokens` starts with one, and how many
+/// tokens it spans.
+///
+/// The signature ends with the token that completes its return type: the
+/// first one containing `->`, extended while nothing follows the `->` yet
+/// (`int-> int`), an `[` is still unclosed (`int[out: 2]`) or the next token
+/// starts the array annotation (`int [out:2]`). So however the signature is
+/// spaced, the arguments start right after it. Without any `->`, all tokens
+/// are taken, so the signature is rejected rather than leaving arguments that
+/// were meant to be part of it.
+///
+/// A signature that would grow past `max_length` bytes is refused with
+/// `E_SIG_TOO_LONG` as soon as it does, before it is parsed.
+pub fn extract_signature(
+    tokens: &[&str],
+    max_length: usize,
+) -> Result<(Option<String>, usize), String> {
+    let Some(first) = tokens.first().and_then(|t| t.strip_prefix("sig:")) else {
+        return Ok((None, 0));
+    };
+    let too_long = || format!("E_SIG_TOO_LONG signature exceeds {} bytes", max_length);
+    if first.len() > max_length {
+        return Err(too_long());
+    }
+    let mut signature = first.to_string();
+    let mut used = 1;
+    while used < tokens.len()
+        && (!return_type_complete(&signature)
+            || (tokens[used].starts_with("[out") && !signature.contains('[')))
+    {
+        if signature.len() + 1 + tokens[used].len() > max_length {
+            return Err(too_long());
+        }
+        if !signature.is_empty() {
+            signature.push(' ');
+        }
+        signature.push_str(tokens[used]);
+        used += 1;
+    }
+    Ok((Some(signature), used))
+}
+
+/// Whether `signature` has a `->` followed by a return type that is not cut off.
+fn return_type_complete(signature: &str) -> bool {
+    match signature.split_once("->") {
+        Some((_, ret)) => {
+            !ret.trim().is_empty() && ret.matches('[').count() <= ret.matches(']').count()
+        }
+        None => false,
+    }
+}
+
+/// Parses `<params>[(<convention>)]-><return>`, using `default_convention`
+/// when the signature does not name one.
+pub fn parse_signature(
+    signature: &str,
+    default_convention: CallingConvention,
+) -> Result<FunctionSignature, String> {
+    let parts: Vec<&str> = signature.split("->").collect();
+    if parts.len() != 2 {
+        return Err("Signature must contain '->'".into());
+    }
+    let params_with_conv = parts[0]; // e.g., "int,int(stdcall)"
+    let ret_type_str = parts[1]; // e.g., "int"
+
+    let mut calling_convention = default_convention;
+    // A struct's `pack(N)` is not the convention, which follows every parameter.
+    let structs_end = params_with_conv.rfind('}').map_or(0, |end| end + 1);
+    let params_part = if let Some(start) = params_with_conv[structs_end..].find('(') {
+        let start = structs_end + start;
+        if let Some(end) = params_with_conv[start..].find(')') {
+            calling_convention = params_with_conv[start + 1..start + end].parse()?;
+            &params_with_conv[..start]
+        } else {
+            return Err("Malformed signature: missing closing parenthesis".into());
+        }
+    } else {
+        params_with_conv
+    };
+
+    let params: Vec<(ParamMode, SupportedType)> = split_params(params_part)
+        .into_iter()
+        .filter(|s| !s.trim().is_empty())
+        .map(parse_param)
+        .collect::<Result<_, String>>()?;
+    let (mut param_modes, mut param_types): (Vec<_>, Vec<_>) = params.into_iter().unzip();
+    // `void` as the whole parameter list means "no parameters", as in C.
+    if param_types == [SupportedType::Void] && param_modes == [ParamMode::In] {
+        param_types.clear();
+        param_modes.clear();
+    }
+    if param_types.contains(&SupportedType::Void) {
+        return Err("void cannot be used as a parameter type".into());
+    }
+    let (return_type, annotation) = parse_return(ret_type_str.trim(), &param_types, &param_modes)?;
+    let array_length_param = match annotation {
+        ReturnAnnotation::ArrayLength(index) => Some(index),
+        _ => None,
+    };
+    for ty in param_types.iter().chain([&return_type]) {
+        ty.ffi_type()?;
+    }
+
+    Ok(FunctionSignature {
+        calling_convention,
+        param_types,
+        param_modes,
+        return_type,
+        array_length_param,
+        null_status: annotation == ReturnAnnotation::NullStatus,
+    })
+}
+
+/// Splits a parameter list at the commas that are not inside a struct's
+/// `{...}`.
+fn split_params(text: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                params.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&text[start..]);
+    params
+}
+
+/// Parses one parameter, a type optionally preceded by its mode: `in`,
+/// `out` or `inout`; a `buf:` buffer; or an `out struct{...}`.
+fn parse_param(text: &str) -> Result<(ParamMode, SupportedType), String> {
+    let text = text.trim();
+    if text == "buf" || text.starts_with("buf:") {
+        return parse_buffer(text).map(|mode| (mode, SupportedType::Ptr));
+    }
+    let (mode, ty) = match text.split_once(char::is_whitespace) {
+        Some(("in", ty)) => (ParamMode::In, ty),
+        Some(("out", ty)) => (ParamMode::Out, ty),
+        Some(("inout", ty)) => (ParamMode::InOut, ty),
+        _ => (ParamMode::In, text),
+    };
+    if ty.trim().starts_with("struct") {
+        if mode != ParamMode::Out {
+            return Err(format!(
+                "Struct parameters must be out parameters: {}",
+                text
+            ));
+        }
+        return parse_struct(ty.trim())
+            .map(|layout| (ParamMode::Struct(layout), SupportedType::Ptr));
+    }
+    let ty = ty.trim();
+    // Only a counted string has a choice of terminator.
+    if let Some((name, _)) = ty.split_once(':')
+        && name.parse() == Ok(SupportedType::Str)
+    {
+        return Err(format!(
+            "{} is always NUL-terminated; pass a counted string as str_len or str_len:nul",
+            ty
+        ));
+    }
+    let ty = ty.parse()?;
+    if matches!(ty, SupportedType::StrLen { .. } | SupportedType::Handle) && mode != ParamMode::In {
+        return Err(format!("{} cannot be an out or inout parameter", ty));
+    }
+    Ok((mode, ty))
+}
+
+/// Parses `buf:<size>[:hex|:str]`. The size must be given and nonzero.
+fn parse_buffer(text: &str) -> Result<ParamMode, String> {
+    let invalid = || {
+        format!(
+            "Invalid buffer parameter: {} (expected buf:<size>[:hex|:str])",
+            text
+        )
+    };
+    let mut parts = text.split(':').skip(1);
+    let size = parts
+        .next()
+        .and_then(|size| size.trim().parse::<usize>().ok())
+        .filter(|&size| size > 0)
+        .ok_or_else(invalid)?;
+    let format = match parts.next().map(str::trim) {
+        None | Some("hex") => BufferFormat::Hex,
+        Some("str") => BufferFormat::Str,
+        Some(_) => return Err(invalid()),
+    };
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(ParamMode::Buffer { size, format })
+}
+
+/// Parses `struct[ pack(<N>)]{<name>:<type>,...}`, which needs at least one
+/// field. A natural layout comes from libffi, so padding matches the C
+/// compiler's; a packed one caps each field's alignment at `N` bytes.
+fn parse_struct(text: &str) -> Result<StructLayout, String> {
+    let invalid = || {
+        format!(
+            "Invalid struct parameter: {} (expected out struct[ pack(<N>)]{{<name>:<type>,...}})",
+            text
+        )
+    };
+    let rest = text
+        .strip_prefix("struct")
+        .map(str::trim_start)
+        .ok_or_else(invalid)?;
+    let (pack, rest) = match rest.strip_prefix("pack(") {
+        Some(rest) => {
+            let (pack, rest) = rest.split_once(')').ok_or_else(invalid)?;
+            let pack = pack
+                .trim()
+                .parse()
+                .ok()
+                .filter(|pack| STRUCT_PACKINGS.contains(pack))
+                .ok_or_else(|| {
+                    format!(
+                        "Unsupported struct packing: pack({}) (expected 1, 2, 4, 8 or 16)",
+                        pack.trim()
+                    )
+                })?;
+            (Some(pack), rest.trim_start())
+        }
+        None => (None, rest),
+    };
+    let body = rest
+        .strip_prefix('{')
+        .and_then(|body| body.strip_suffix('}'))
+        .ok_or_else(invalid)?;
+    let mut fields: Vec<(String, SupportedType)> = Vec::new();
+    for field in body.split(',') {
+        let (name, ty) = field.split_once(':').ok_or_else(invalid)?;
+        let name = name.trim();
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(invalid());
+        }
+        if fields.iter().any(|(other, _)| other == name) {
+            return Err(format!("Struct field {} is declared twice", name));
+        }
+        let ty: SupportedType = ty.trim().parse()?;
+        if matches!(
+            ty,
+            SupportedType::Void | SupportedType::StrLen { .. } | SupportedType::Handle
+        ) {
+            return Err(format!("Struct field {} cannot be {}", name, ty));
+        }
+        fields.push((name.to_string(), ty));
+    }
+
+    let types = fields
+        .iter()
+        .map(|(_, ty)| ty.ffi_type())
+        .collect::<Result<Vec<_>, _>>()?;
+    let (offsets, size) = match pack {
+        Some(pack) => packed_layout(&types, pack),
+        None => {
+            let layout = Type::structure(types);
+            let mut offsets = vec![0; fields.len()];
+            let status = unsafe {
+                libffi::raw::ffi_get_struct_offsets(
+                    libffi::raw::ffi_abi_FFI_DEFAULT_ABI,
+                    layout.as_raw_ptr(),
+                    offsets.as_mut_ptr(),
+                )
+            };
+            if status != libffi::raw::ffi_status_FFI_OK {
+                return Err(format!("Cannot lay out {}", text));
+            }
+            (offsets, unsafe { (*layout.as_raw_ptr()).size })
+        }
+    };
+    Ok(StructLayout {
+        fields: fields
+            .into_iter()
+            .zip(offsets)
+            .map(|((name, ty), offset)| StructField { name, ty, offset })
+            .collect(),
+        size,
+        pack,
+    })
+}
+
+/// The field offsets and size of a struct of `types` under
+/// `#pragma pack(pack)`: each field is aligned to the smaller of its natural
+/// alignment and `pack`, and the struct to the largest of those.
+fn packed_layout(types: &[Type], pack: usize) -> (Vec<usize>, usize) {
+    let mut end = 0usize;
+    let mut struct_alignment = 1;
+    let offsets = types
+        .iter()
+        .map(|ty| {
+            let raw = unsafe { &*ty.as_raw_ptr() };
+            let alignment = usize::from(raw.alignment).min(pack);
+            struct_alignment = struct_alignment.max(alignment);
+            let offset = end.next_multiple_of(alignment);
+            end = offset + raw.size;
+            offset
+        })
+        .collect();
+    (offsets, end.next_multiple_of(struct_alignment))
+}
+
+/// What follows a return type in brackets.
+#[derive(Debug, PartialEq)]
+enum ReturnAnnotation {
+    None,
+    /// `[out:N]`: the zero-based index of the array length parameter.
+    ArrayLength(usize),
+    /// `[status]` on a `ptr`.
+    NullStatus,
+}
+
+/// Parses a return type: a plain type, `<type>[out:N]` for a pointer to an
+/// array whose length is written through parameter `N` (1-based), which must
+/// be an `out int` or a `ptr`, or `ptr[status]` for a pointer that is null on
+/// failure.
+///
+/// This is the single place that decides whether a return value can be read
+/// safely; anything it cannot vouch for is refused with `E_BAD_RET` before a
+/// call is ever made.
+fn parse_return(
+    text: &str,
+    param_types: &[SupportedType],
+    param_modes: &[ParamMode],
+) -> Result<(SupportedType, ReturnAnnotation), String> {
+    let bad_return = |reason: String| format!("E_BAD_RET {}", reason);
+    let parse_type = |name: &str| {
+        name.trim()
+            .parse::<SupportedType>()
+            .map_err(|_| bad_return(format!("Unsupported return type: {}", name.trim())))
+    };
+    let Some((element, annotation)) = text.split_once('[') else {
+        return match parse_type(text)? {
+            ty @ (SupportedType::StrLen { .. } | SupportedType::Handle) => {
+                Err(bad_return(format!("Unsupported return type: {}", ty)))
+            }
+            ty => Ok((ty, ReturnAnnotation::None)),
+        };
+    };
+    let element = parse_type(element)?;
+    if annotation.trim() == "status]" {
+        return match element {
+            SupportedType::Ptr => Ok((element, ReturnAnnotation::NullStatus)),
+            _ => Err(bad_return(format!(
+                "[status] requires a ptr return, not {}",
+                element
+            ))),
+        };
+    }
+    if !matches!(element, SupportedType::Int | SupportedType::Float) {
+        return Err(bad_return(format!(
+            "Arrays of {:?} are not supported",
+            element
+        )));
+    }
+    let position = annotation
+        .strip_suffix(']')
+        .and_then(|a| a.trim().strip_prefix("out:"))
+        .and_then(|n| n.trim().parse::<usize>().ok())
+        .ok_or_else(|| bad_return(format!("Malformed array return: {}", text)))?;
+    let index = position.checked_sub(1);
+    let param = index.and_then(|i| Some((*param_types.get(i)?, param_modes.get(i)?)));
+    match (index, param) {
+        (
+            Some(index),
+            Some((SupportedType::Ptr, ParamMode::In) | (SupportedType::Int, ParamMode::Out)),
+        ) => Ok((element, ReturnAnnotation::ArrayLength(index))),
+        _ => Err(bad_return(format!(
+            "Array length parameter {} must be an out int or ptr parameter",
+            position
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_signature_without_sig_prefix() {
+        assert_eq!(extract_signature(&[], 4096), Ok((None, 0)));
+        assert_eq!(extract_signature(&["int->int", "1"], 4096), Ok((None, 0)));
+    }
+
+    #[test]
+    fn extract_signature_in_one_token() {
+        assert_eq!(
+            extract_signature(&["sig:int->int", "1"], 4096),
+            Ok((Some("int->int".to_string()), 1))
+        );
+    }
+
+    #[test]
+    fn extract_signature_split_around_the_arrow() {
+        assert_eq!(
+            extract_signature(&["sig:int,", "int->int", "1"], 4096),
+            Ok((Some("int, int->int".to_string()), 2))
+        );
+        assert_eq!(
+            extract_signature(&["sig:int->", "int", "1"], 4096),
+            Ok((Some("int-> int".to_string()), 2))
+        );
+        assert_eq!(
+            extract_signature(&["sig:", "void->int"], 4096),
+            Ok((Some("void->int".to_string()), 2))
+        );
+    }
+
+    #[test]
+    fn extract_signature_waits_for_a_closing_bracket() {
+        assert_eq!(
+            extract_signature(&["sig:ptr->int[out:", "2]", "buf"], 4096),
+            Ok((Some("ptr->int[out: 2]".to_string()), 2))
+        );
+    }
+
+    #[test]
+    fn extract_signature_without_arrow_takes_everything() {
+        assert_eq!(
+            extract_signature(&["sig:int", "1", "2"], 4096),
+            Ok((Some("int 1 2".to_string()), 3))
+        );
+        assert!(parse_signature("int 1 2", CallingConvention::Cdecl).is_err());
+    }
+
+    #[test]
+    fn extract_signature_refuses_a_long_signature() {
+        let refused = Err("E_SIG_TOO_LONG signature exceeds 9 bytes".to_string());
+        assert_eq!(extract_signature(&["sig:int,int->int"], 9), refused);
+        assert_eq!(extract_signature(&["sig:int,", "int->int"], 9), refused);
+        assert_eq!(
+            extract_signature(&["sig:int->", "int"], 9),
+            Ok((Some("int-> int".to_string()), 2))
+        );
+    }
+
+    #[test]
+    fn unsupported_type_error() {
+        assert_eq!(
+            SupportedType::LongDouble.unsupported(),
+            "E_UNSUPPORTED_TYPE type 'longdouble' not supported on this platform"
+        );
+    }
+
+    #[test]
+    fn longdouble_is_refused_only_where_unsupported() {
+        for signature in ["longdouble,int->int", "void->longdouble"] {
+            let parsed = parse_signature(signature, CallingConvention::Cdecl);
+            match longdouble::ffi_type() {
+                Some(_) => assert!(parsed.is_ok(), "{}", signature),
+                None => assert_eq!(parsed.unwrap_err(), SupportedType::LongDouble.unsupported()),
+            }
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// C++ functions for the demangling lookup tests; built with the system C++ compiler.
+#include <cstring>
+
+int twice(int value) { return 2 * value; }
+
+int add(int a, int b) { return a + b; }
+
+float add(float a, float b) { return a + b; }
+
+int same(const char *a, const char *b) { return std::strcmp(a, b) == 0; }
+
+namespace geometry {
+struct Point {
+    int x;
+    int y;
+};
+
+int area(int width, int height) { return width * height; }
+
+int manhattan(const Point *a, const Point *b) {
+    int dx = a->x > b->x ? a->x - b->x : b->x - a->x;
+    int dy = a->y > b->y ? a->y - b->y : b->y - a->y;
+    return dx + dy;
+}
+}
@@ -15,3 +15,240 @@ EXPORT int AddNumbers(int a, int b) {
 EXPORT int ComputeSumStdCall(int a, int b) {
     return a + b;
 }
+
+#include <string.h>
+
+EXPORT const char *echo_string(const char *s) {
+    return s;
+}
+
+EXPORT int string_length(const char *s) {
+    return (int)strlen(s);
+}
+
+#include <unistd.h>
+
+EXPORT int sleep_ms(int ms) {
+    usleep((useconds_t)ms * 1000);
+    return ms;
+}
+
+#include <stdlib.h>
+
+typedef struct {
+    int value;
+} Counter;
+
+EXPORT Counter *counter_create(int initial) {
+    Counter *c = malloc(sizeof(Counter));
+    c->value = initial;
+    return c;
+}
+
+// Fails, returning NULL, for a negative initial value.
+EXPORT Counter *counter_try_create(int initial) {
+    return initial < 0 ? NULL : counter_create(initial);
+}
+
+EXPORT int counter_add(Counter *c, int delta) {
+    c->value += delta;
+    return c->value;
+}
+
+EXPORT void counter_destroy(Counter *c) {
+    free(c);
+}
+
+EXPORT const char *repeat_char(char c, int n) {
+    static char buf[65536];
+    if (n < 0 || n >= (int)sizeof(buf)) {
+        return NULL;
+    }
+    memset(buf, c, (size_t)n);
+    buf[n] = '\0';
+    return buf;
+}
+
+#if defined(__x86_64__)
+EXPORT __attribute__((ms_abi)) int AddNumbersWin64(int a, int b) {
+    return a + b;
+}
+#endif
+
+EXPORT int *make_range(int n, int *out_len) {
+    int *values = malloc(sizeof(int) * (n > 0 ? n : 1));
+    for (int i = 0; i < n; i++) {
+        values[i] = i * i;
+    }
+    *out_len = n;
+    return values;
+}
+
+EXPORT const char *record_caller(const char *caller) {
+    static char last[128];
+    strncpy(last, caller, sizeof(last) - 1);
+    return last;
+}
+
+EXPORT int wait_ms(int ms) {
+    usleep((useconds_t)ms * 1000);
+    return ms;
+}
+
+EXPORT int float_bits(float f) {
+    int bits;
+    memcpy(&bits, &f, sizeof(bits));
+    return bits;
+}
+
+EXPORT float float_identity(float f) {
+    return f;
+}
+
+EXPORT int ambiguous_name(void) {
+    return 1;
+}
+
+EXPORT int AMBIGUOUS_NAME(void) {
+    return 2;
+}
+
+EXPORT void divmod(int a, int b, int *quotient, int *remainder) {
+    *quotient = a / b;
+    *remainder = a % b;
+}
+
+EXPORT void increment(int *value) {
+    *value += 1;
+}
+
+EXPORT void greeting(const char **out) {
+    *out = "hello";
+}
+
+EXPORT void skip_word(const char **s) {
+    const char *space = strchr(*s, ' ');
+    *s = space ? space + 1 : *s + strlen(*s);
+}
+
+static const char *mode_at_load = "unset";
+
+__attribute__((constructor)) static void read_mode_at_load(void) {
+    const char *mode = getenv("DLLBRIDGE_TEST_MODE");
+    if (mode) {
+        mode_at_load = mode;
+    }
+}
+
+EXPORT const char *load_mode(void) {
+    return mode_at_load;
+}
+
+#include <stdio.h>
+
+EXPORT int create_file(const char *path) {
+    FILE *f = fopen(path, "w");
+    if (!f) {
+        return -1;
+    }
+    fclose(f);
+    return 0;
+}
+
+EXPORT int read_relative_config(void) {
+    int value = -1;
+    FILE *f = fopen("dllbridge_test.cfg", "r");
+    if (!f) {
+        return -1;
+    }
+    if (fscanf(f, "%d", &value) != 1) {
+        value = -1;
+    }
+    fclose(f);
+    return value;
+}
+
+EXPORT int greet(const char *name) {
+    int written = printf("Hello, %s!\n", name);
+    fprintf(stderr, "greeted %s\n", name);
+    return written;
+}
+
+EXPORT int count_byte(const char *data, size_t len, char c) {
+    int count = 0;
+    for (size_t i = 0; i < len; i++) {
+        count += data[i] == c;
+    }
+    return count;
+}
+
+EXPORT int byte_at(const char *s, int index) {
+    return (unsigned char)s[index];
+}
+
+// The length the function was given and one byte, read as it sees them.
+EXPORT int counted_byte_at(const char *data, size_t len, int index) {
+    return (int)len * 1000 + (unsigned char)data[index];
+}
+
+EXPORT long double scale_ld(long double x, int n) {
+    return x * n;
+}
+
+EXPORT long double third_ld(void) {
+    return 1.0L / 3;
+}
+
+EXPORT void get_name(char *out) {
+    strcpy(out, "dllbridge");
+}
+
+EXPORT long long wide_value(void) {
+    return 0x100000002LL;
+}
+
+EXPORT char char_identity(char c) {
+    return c;
+}
+
+typedef struct {
+    int sum;
+    char positive;
+    float mean;
+    const char *label;
+    int max;
+} Stats;
+
+EXPORT void compute_stats(int a, int b, Stats *out) {
+    out->sum = a + b;
+    out->positive = out->sum > 0;
+    out->mean = (a + b) / 2.0f;
+    out->label = a > b ? "first" : "second";
+    out->max = a > b ? a : b;
+}
+
+#pragma pack(push, 1)
+typedef struct {
+    char tag;
+    int value;
+    float ratio;
+    char flag;
+    int last;
+} Packed;
+#pragma pack(pop)
+
+EXPORT void fill_packed(int value, Packed *out) {
+    out->tag = 'P';
+    out->value = value;
+    out->ratio = 0.25f;
+    out->flag = 1;
+    out->last = -value;
+}
+
+EXPORT int read_first_byte(int fd) {
+    unsigned char byte;
+    if (read(fd, &byte, 1) != 1) {
+        return -1;
+    }
+    return byte;
+}
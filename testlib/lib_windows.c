@@ -1,3 +1,6 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
 #include <windows.h>
 
 // Exported function with cdecl (default) convention
@@ -14,7 +17,231 @@ __declspec(dllexport) int __stdcall ComputeSumStdCall(int a, int b) {
   return a + b;
 }
 
-// Standard DLL entry point.
+// Returns its argument unchanged, for round-tripping string marshaling.
+__declspec(dllexport) const char *echo_string(const char *s) { return s; }
+
+// Returns the byte length of a null-terminated string.
+__declspec(dllexport) int string_length(const char *s) { return (int)strlen(s); }
+
+// Sleeps for the given number of milliseconds and returns it.
+__declspec(dllexport) int sleep_ms(int ms) {
+  Sleep((DWORD)ms);
+  return ms;
+}
+
+// Opaque handle used to exercise pointer round trips.
+typedef struct {
+  int value;
+} Counter;
+
+__declspec(dllexport) Counter *counter_create(int initial) {
+  Counter *c = malloc(sizeof(Counter));
+  c->value = initial;
+  return c;
+}
+
+// Fails, returning NULL, for a negative initial value.
+__declspec(dllexport) Counter *counter_try_create(int initial) {
+  return initial < 0 ? NULL : counter_create(initial);
+}
+
+__declspec(dllexport) int counter_add(Counter *c, int delta) {
+  c->value += delta;
+  return c->value;
+}
+
+__declspec(dllexport) void counter_destroy(Counter *c) { free(c); }
+
+// Returns a static string of `n` copies of `c` (NULL if it would not fit).
+__declspec(dllexport) const char *repeat_char(char c, int n) {
+  static char buf[65536];
+  if (n < 0 || n >= (int)sizeof(buf)) {
+    return NULL;
+  }
+  memset(buf, c, (size_t)n);
+  buf[n] = '\0';
+  return buf;
+}
+
+// Same as AddNumbers; on x64 every function uses the Win64 convention.
+__declspec(dllexport) int AddNumbersWin64(int a, int b) { return a + b; }
+
+// Returns a malloc'd array of the first `n` squares; its length goes to `out_len`.
+__declspec(dllexport) int *make_range(int n, int *out_len) {
+  int *values = malloc(sizeof(int) * (n > 0 ? n : 1));
+  for (int i = 0; i < n; i++) {
+    values[i] = i * i;
+  }
+  *out_len = n;
+  return values;
+}
+
+// Stands in for an audit hook: keeps a copy of the caller and returns it.
+__declspec(dllexport) const char *record_caller(const char *caller) {
+  static char last[128];
+  strncpy(last, caller, sizeof(last) - 1);
+  return last;
+}
+
+// Same as sleep_ms, so one can be declared non-reentrant and the other not.
+__declspec(dllexport) int wait_ms(int ms) {
+  Sleep((DWORD)ms);
+  return ms;
+}
+
+// Returns the raw IEEE-754 bits of `f`, to check float arguments bit-for-bit.
+__declspec(dllexport) int float_bits(float f) {
+  int bits;
+  memcpy(&bits, &f, sizeof(bits));
+  return bits;
+}
+
+// Returns its argument unchanged, for round-tripping float returns.
+__declspec(dllexport) float float_identity(float f) { return f; }
+
+// Two exports differing only in case, for case-insensitive lookup.
+__declspec(dllexport) int ambiguous_name(void) { return 1; }
+__declspec(dllexport) int AMBIGUOUS_NAME(void) { return 2; }
+
+// Writes both results of an integer division through out-parameters.
+__declspec(dllexport) void divmod(int a, int b, int *quotient, int *remainder) {
+  *quotient = a / b;
+  *remainder = a % b;
+}
+
+// Adds one to an in/out integer.
+__declspec(dllexport) void increment(int *value) { *value += 1; }
+
+// Returns a static string through an out-parameter.
+__declspec(dllexport) void greeting(const char **out) { *out = "hello"; }
+
+// Advances an in/out string pointer past its first word.
+__declspec(dllexport) void skip_word(const char **s) {
+  const char *space = strchr(*s, ' ');
+  *s = space ? space + 1 : *s + strlen(*s);
+}
+
+// Creates an empty file at `path`, a side effect tests can observe.
+__declspec(dllexport) int create_file(const char *path) {
+  FILE *f = fopen(path, "w");
+  if (!f) {
+    return -1;
+  }
+  fclose(f);
+  return 0;
+}
+
+// Reads the number in a config file found relative to the working directory,
+// or returns -1.
+__declspec(dllexport) int read_relative_config(void) {
+  int value = -1;
+  FILE *f = fopen("dllbridge_test.cfg", "r");
+  if (!f) {
+    return -1;
+  }
+  if (fscanf(f, "%d", &value) != 1) {
+    value = -1;
+  }
+  fclose(f);
+  return value;
+}
+
+// Prints a greeting to stdout and a note to stderr.
+__declspec(dllexport) int greet(const char *name) {
+  int written = printf("Hello, %s!\n", name);
+  fprintf(stderr, "greeted %s\n", name);
+  return written;
+}
+
+// Counts `c` in the first `len` bytes of `data`, which need not be terminated.
+__declspec(dllexport) int count_byte(const char *data, size_t len, char c) {
+  int count = 0;
+  for (size_t i = 0; i < len; i++) {
+    count += data[i] == c;
+  }
+  return count;
+}
+
+__declspec(dllexport) int byte_at(const char *s, int index) {
+  return (unsigned char)s[index];
+}
+
+// The length the function was given and one byte, read as it sees them.
+__declspec(dllexport) int counted_byte_at(const char *data, size_t len, int index) {
+  return (int)len * 1000 + (unsigned char)data[index];
+}
+
+// Long double arithmetic; with MSVC, long double is the same as double.
+__declspec(dllexport) long double scale_ld(long double x, int n) { return x * n; }
+
+__declspec(dllexport) long double third_ld(void) { return 1.0L / 3; }
+
+// DLLBRIDGE_TEST_MODE as it was when the DLL was loaded, or "unset".
+static const char *mode_at_load = "unset";
+
+__declspec(dllexport) const char *load_mode(void) { return mode_at_load; }
+
+// Writes a name into a buffer the caller provides.
+__declspec(dllexport) void get_name(char *out) { strcpy(out, "dllbridge"); }
+
+// A value wider than an int, for detecting a too-narrow declared return.
+__declspec(dllexport) long long wide_value(void) { return 0x100000002LL; }
+
+// Returns its argument, for checking how char values round-trip.
+__declspec(dllexport) char char_identity(char c) { return c; }
+
+// Fills in a results struct the caller allocates.
+typedef struct {
+  int sum;
+  char positive;
+  float mean;
+  const char *label;
+  int max;
+} Stats;
+
+__declspec(dllexport) void compute_stats(int a, int b, Stats *out) {
+  out->sum = a + b;
+  out->positive = out->sum > 0;
+  out->mean = (a + b) / 2.0f;
+  out->label = a > b ? "first" : "second";
+  out->max = a > b ? a : b;
+}
+
+// Fills in a struct laid out without padding.
+#pragma pack(push, 1)
+typedef struct {
+  char tag;
+  int value;
+  float ratio;
+  char flag;
+  int last;
+} Packed;
+#pragma pack(pop)
+
+__declspec(dllexport) void fill_packed(int value, Packed *out) {
+  out->tag = 'P';
+  out->value = value;
+  out->ratio = 0.25f;
+  out->flag = 1;
+  out->last = -value;
+}
+
+__declspec(dllexport) int read_first_byte(HANDLE file) {
+  unsigned char byte;
+  DWORD read;
+  if (!ReadFile(file, &byte, 1, &read, NULL) || read != 1) {
+    return -1;
+  }
+  return byte;
+}
+
+// Standard DLL entry point; records the environment seen at load time.
 BOOL WINAPI DllMain(HINSTANCE hinstDLL, DWORD fdwReason, LPVOID lpReserved) {
+  if (fdwReason == DLL_PROCESS_ATTACH) {
+    const char *mode = getenv("DLLBRIDGE_TEST_MODE");
+    if (mode) {
+      mode_at_load = mode;
+    }
+  }
   return TRUE;
 }
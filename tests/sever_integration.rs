@@ -1,18 +1,78 @@
 use std::{
-    io::{Read, Write},
-    net::{SocketAddr, TcpListener},
-    process::{Child, Command},
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::OnceLock,
     thread,
     time::Duration,
 };
 
+/// Builds the C fixture library once per test run and returns its path.
+fn fixture_lib() -> &'static str {
+    static LIB: OnceLock<String> = OnceLock::new();
+    LIB.get_or_init(|| build_fixture("lib_linux.so", &[]))
+}
+
+/// The fixture library built with DWARF debug info.
+fn debug_fixture_lib() -> &'static str {
+    static LIB: OnceLock<String> = OnceLock::new();
+    LIB.get_or_init(|| build_fixture("lib_linux_debug.so", &["-g"]))
+}
+
+fn build_fixture(name: &str, flags: &[&str]) -> String {
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let status = Command::new("cc")
+        .args(["-shared", "-fPIC"])
+        .args(flags)
+        .arg("-o")
+        .arg(&out)
+        .arg("testlib/lib_linux.c")
+        .status()
+        .expect("Couldn't run cc");
+    assert!(status.success(), "Couldn't build testlib/lib_linux.c");
+    out.to_string_lossy().into_owned()
+}
+
+/// The C++ fixture library, for demangling lookups.
+fn cpp_fixture_lib() -> &'static str {
+    static LIB: OnceLock<String> = OnceLock::new();
+    LIB.get_or_init(|| {
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("lib_cpp.so");
+        let status = Command::new("c++")
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&out)
+            .arg("testlib/lib_cpp.cpp")
+            .status()
+            .expect("Couldn't run c++");
+        assert!(status.success(), "Couldn't build testlib/lib_cpp.cpp");
+        out.to_string_lossy().into_owned()
+    })
+}
+
+/// Writes `contents` to a file in the test temp dir and returns its path.
+fn write_temp_file(name: &str, contents: &str) -> String {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, contents).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
 fn start_server() -> (Child, SocketAddr) {
+    start_server_with(&[])
+}
+
+fn start_server_with(extra_args: &[&str]) -> (Child, SocketAddr) {
+    start_server_for(fixture_lib(), extra_args)
+}
+
+fn start_server_for(lib: &str, extra_args: &[&str]) -> (Child, SocketAddr) {
     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
     let addr = listener.local_addr().unwrap();
     drop(listener);
     let child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
-        .arg("testlib/lib_linux.so")
+        .arg(lib)
         .arg(addr.port().to_string())
+        .args(extra_args)
         .spawn()
         .unwrap();
 
@@ -20,6 +80,42 @@ fn start_server() -> (Child, SocketAddr) {
     (child, addr)
 }
 
+fn connect(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+    let mut attempts = 0;
+    let stream = loop {
+        match TcpStream::connect(("127.0.0.1", addr.port())) {
+            Ok(stream) => break stream,
+            Err(_) if attempts < 50 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("Couldn't start listener! {}", e),
+        }
+    };
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+}
+
+fn read_response(reader: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .expect("Couldn`t read from stream");
+    line.trim_end_matches('\n').to_string()
+}
+
+/// The result of a successful reply, without its `OK` status token.
+fn read_result(reader: &mut BufReader<TcpStream>) -> String {
+    let response = read_response(reader);
+    match response.strip_prefix("OK") {
+        Some(result) => result.trim_start_matches(' ').to_string(),
+        None => panic!("expected an OK reply, got {:?}", response),
+    }
+}
+
 #[test]
 fn smoke_test() {
     let (mut child, _addr) = start_server();
@@ -30,19 +126,3831 @@ fn smoke_test() {
 fn hello_world() {
     let (mut child, addr) = start_server();
 
-    let mut stream =
-        std::net::TcpStream::connect(("127.0.0.1", addr.port())).expect("Couldn't start listener!");
+    let (mut stream, mut reader) = connect(addr);
 
     println!("Writing to stream");
     stream
         .write_all(b"call helloworld sig:void ->int\n")
         .expect("Couldnt not write to stream!");
 
-    let mut buf: Vec<u8> = Vec::new();
+    let response = read_response(&mut reader);
+    println!("Response: {:#?}", response);
+    assert_eq!(response, "OK 42");
+
+    child.kill().ok();
+}
+
+#[test]
+fn pipelined_request_ids() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
     stream
-        .read_to_end(&mut buf)
-        .expect("Couldn`t read from stream");
-    println!("Buffer: {:#?}", buf);
+        .write_all(
+            b"id:7 call AddNumbers sig:int,int->int 1 2\nid:8 call AddNumbers sig:int,int->int 20 22\n",
+        )
+        .expect("Couldnt not write to stream!");
+
+    let mut responses = [read_response(&mut reader), read_response(&mut reader)];
+    responses.sort();
+    assert_eq!(responses, ["id:7 OK 3", "id:8 OK 42"]);
+
+    child.kill().ok();
+}
+
+#[test]
+fn successful_replies_start_with_an_ok_status() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 2 3\nid:4 call sleep_ms sig:int->void 1\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 5");
+    assert_eq!(read_response(&mut reader), "id:4 OK");
+    stream
+        .write_all(b"call NoSuchFunction sig:void->int\n")
+        .unwrap();
+    assert!(read_response(&mut reader).starts_with("ERR "));
+    stream.write_all(b"caps\n").unwrap();
+    let caps = read_response(&mut reader);
+    assert!(caps.starts_with("OK caps version="), "{}", caps);
+    assert!(caps.contains(" protocol=2 "), "{}", caps);
+
+    child.kill().ok();
+
+    // Protocol 1 keeps the bare results older clients expect.
+    let (mut child, addr) = start_server_with(&["--protocol", "1"]);
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 2 3\ncaps\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "5");
+    let caps = read_response(&mut reader);
+    assert!(caps.starts_with("caps version="), "{}", caps);
+    assert!(caps.contains(" protocol=1 "), "{}", caps);
+
+    child.kill().ok();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--protocol", "3"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(64));
+}
+
+#[test]
+fn rejects_too_many_args() {
+    let (mut child, addr) = start_server_with(&["--max-args", "2"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 1 2 3\n")
+        .expect("Couldnt not write to stream!");
+
+    let response = read_response(&mut reader);
+    assert!(
+        response.starts_with("ERR E_TOO_MANY_ARGS"),
+        "unexpected response: {}",
+        response
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn zero_limits_are_rejected() {
+    for (flag, message) in [
+        (
+            "--max-signature-length",
+            "--max-signature-length must be at least 1",
+        ),
+        ("--call-timeout", "--call-timeout must be at least 1 ms"),
+        (
+            "--max-call-timeout",
+            "--max-call-timeout must be at least 1 ms",
+        ),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+            .arg(fixture_lib())
+            .arg("0")
+            .args([flag, "0"])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(64), "{}", flag);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with(message), "{}", stderr);
+    }
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn upload_and_call_library() {
+    let (mut child, addr) = start_server_with(&["--allow-upload"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    let body = std::fs::read(fixture_lib()).unwrap();
+    stream
+        .write_all(format!("upload fresh {}\n", base64_encode(&body)).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+
+    stream
+        .write_all(b"call fresh!AddNumbers sig:int,int->int 2 3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    child.kill().ok();
+}
+
+#[test]
+fn upload_disabled_by_default() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"upload fresh AAAA\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR "));
+
+    child.kill().ok();
+}
+
+#[test]
+fn shutdown_removes_uploaded_libraries() {
+    let token = write_temp_file("admin.token", "s3cret\n");
+    let (mut child, addr) = start_server_with(&["--allow-upload", "--admin-token-file", &token]);
+    let uploaded = std::env::temp_dir().join(format!(
+        "dllbridge32-{}-0-fresh.{}",
+        child.id(),
+        std::env::consts::DLL_EXTENSION
+    ));
+
+    let (mut stream, mut reader) = connect(addr);
+
+    let body = std::fs::read(fixture_lib()).unwrap();
+    stream
+        .write_all(format!("upload fresh {}\n", base64_encode(&body)).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+    assert!(uploaded.exists());
+
+    stream
+        .write_all(b"auth s3cret\nadmin shutdown\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+    assert_eq!(read_response(&mut reader), "OK shutting down");
+
+    // The connection stays open, as an idle client's would.
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        assert!(std::time::Instant::now() < deadline, "server did not exit");
+        thread::sleep(Duration::from_millis(20));
+    };
+    assert!(status.success());
+    assert!(!uploaded.exists());
+}
+
+#[test]
+fn uploads_are_written_to_the_configured_temp_dir() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("upload_temp_dir");
+    std::fs::create_dir_all(&dir).unwrap();
+    let (mut child, addr) =
+        start_server_with(&["--allow-upload", "--temp-dir", dir.to_str().unwrap()]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    let body = std::fs::read(fixture_lib()).unwrap();
+    stream
+        .write_all(format!("upload tmpdir {}\n", base64_encode(&body)).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+
+    let uploaded = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name().to_string_lossy().ends_with("-tmpdir.so"));
+    assert!(uploaded, "upload was not written to {}", dir.display());
+
+    stream
+        .write_all(b"call tmpdir!AddNumbers sig:int,int->int 2 3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    child.kill().ok();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--allow-upload", "--temp-dir", "/nonexistent/dllbridge32"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(73));
+}
+
+#[test]
+fn load_failures_name_missing_dependencies() {
+    // A library linked against one that is deleted afterwards.
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("missing_dependency");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("dep.c"), "int dep_value(void) { return 1; }\n").unwrap();
+    std::fs::write(
+        dir.join("needs.c"),
+        "int dep_value(void);\nint needs_dep(void) { return dep_value(); }\n",
+    )
+    .unwrap();
+    let cc = |args: &[&str]| {
+        let status = Command::new("cc")
+            .current_dir(&dir)
+            .args(args)
+            .status()
+            .expect("Couldn't run cc");
+        assert!(status.success(), "cc {:?} failed", args);
+    };
+    cc(&["-shared", "-fPIC", "-o", "libvanished.so", "dep.c"]);
+    cc(&[
+        "-shared",
+        "-fPIC",
+        "-o",
+        "needs.so",
+        "needs.c",
+        "-L.",
+        "-lvanished",
+    ]);
+    std::fs::remove_file(dir.join("libvanished.so")).unwrap();
+    let needs = dir.join("needs.so");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(&needs)
+        .arg("0")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(69));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("dependencies not found: libvanished.so"),
+        "{}",
+        stderr
+    );
+
+    // A dependency found through the library's own `$ORIGIN` runpath is not
+    // reported, though the load fails for the symbol it lacks.
+    std::fs::write(dir.join("other.c"), "int other_value(void) { return 2; }\n").unwrap();
+    cc(&["-shared", "-fPIC", "-o", "libpresent.so", "other.c"]);
+    cc(&[
+        "-shared",
+        "-fPIC",
+        "-o",
+        "needs_present.so",
+        "needs.c",
+        "-L.",
+        "-lpresent",
+        "-Wl,-rpath,$ORIGIN",
+    ]);
+    let output = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(dir.join("needs_present.so"))
+        .arg("0")
+        .arg("--bind-now")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(69));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("undefined symbol: dep_value"), "{}", stderr);
+    assert!(!stderr.contains("dependencies not found"), "{}", stderr);
+
+    let (mut child, addr) = start_server_with(&["--allow-upload"]);
+    let (mut stream, mut reader) = connect(addr);
+    let body = std::fs::read(&needs).unwrap();
+    stream
+        .write_all(format!("upload needs {}\n", base64_encode(&body)).as_bytes())
+        .expect("Couldnt not write to stream!");
+    let response = read_response(&mut reader);
+    assert!(response.starts_with("ERR "), "{}", response);
+    assert!(
+        response.contains("dependencies not found: libvanished.so"),
+        "{}",
+        response
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn bind_now_fails_the_load_on_unresolved_symbols() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("unresolved_symbol");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("lazy.c"),
+        "int vanished_function(void);\n\
+         int present(void) { return 7; }\n\
+         int uses_vanished(void) { return vanished_function(); }\n",
+    )
+    .unwrap();
+    let status = Command::new("cc")
+        .current_dir(&dir)
+        .args(["-shared", "-fPIC", "-Wl,-z,lazy", "-o", "lazy.so", "lazy.c"])
+        .status()
+        .expect("Couldn't run cc");
+    assert!(status.success(), "Couldn't build lazy.so");
+    let lazy = dir.join("lazy.so");
+
+    // Lazy binding, the default, only fails once the symbol is needed.
+    let (mut child, addr) = start_server_for(lazy.to_str().unwrap(), &[]);
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(b"call present sig:void->int\n").unwrap();
+    assert_eq!(read_response(&mut reader), "OK 7");
+    child.kill().ok();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(&lazy)
+        .arg("0")
+        .arg("--bind-now")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(69));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("undefined symbol: vanished_function"),
+        "{}",
+        stderr
+    );
+
+    let (mut child, addr) = start_server_with(&["--allow-upload"]);
+    let (mut stream, mut reader) = connect(addr);
+    let body = base64_encode(&std::fs::read(&lazy).unwrap());
+    stream
+        .write_all(format!("upload bind:now lazy {}\n", body).as_bytes())
+        .unwrap();
+    let response = read_response(&mut reader);
+    assert!(response.starts_with("ERR "), "{}", response);
+    assert!(
+        response.contains("undefined symbol: vanished_function"),
+        "{}",
+        response
+    );
+    stream
+        .write_all(
+            format!(
+                "upload bind:lazy lazy {}\ncall lazy!present sig:void->int\n",
+                body
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK");
+    assert_eq!(read_response(&mut reader), "OK 7");
+    stream
+        .write_all(format!("upload bind:soon lazy {}\n", body).as_bytes())
+        .unwrap();
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR Usage: upload [bind:now|bind:lazy] [scope:global|scope:local] <alias> <base64>"
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn global_scope_lets_later_libraries_resolve_symbols() {
+    // `consumer.so` uses a function it is not linked against, which only a
+    // library loaded earlier with RTLD_GLOBAL can provide.
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("symbol_scope");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("provider.c"),
+        "int provided_value(void) { return 40; }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("consumer.c"),
+        "int provided_value(void);\nint consumer_value(void) { return provided_value() + 2; }\n",
+    )
+    .unwrap();
+    for name in ["provider", "consumer"] {
+        let status = Command::new("cc")
+            .current_dir(&dir)
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(format!("{}.so", name))
+            .arg(format!("{}.c", name))
+            .status()
+            .expect("Couldn't run cc");
+        assert!(status.success(), "Couldn't build {}.so", name);
+    }
+    let provider = base64_encode(&std::fs::read(dir.join("provider.so")).unwrap());
+    let consumer = base64_encode(&std::fs::read(dir.join("consumer.so")).unwrap());
+
+    let (mut child, addr) = start_server_with(&["--allow-upload"]);
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(format!("upload provider {}\n", provider).as_bytes())
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK");
+    stream
+        .write_all(format!("upload bind:now consumer {}\n", consumer).as_bytes())
+        .unwrap();
+    let response = read_response(&mut reader);
+    assert!(
+        response.contains("undefined symbol: provided_value"),
+        "{}",
+        response
+    );
+
+    stream
+        .write_all(format!("upload scope:global provider {}\n", provider).as_bytes())
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK");
+    stream
+        .write_all(format!("upload bind:now consumer {}\n", consumer).as_bytes())
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK");
+    stream
+        .write_all(b"call consumer!consumer_value sig:void->int\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 42");
+    child.kill().ok();
+
+    // --global-symbols does the same for the library the server starts with.
+    let provider_path = dir.join("provider.so");
+    let (mut child, addr) = start_server_for(
+        provider_path.to_str().unwrap(),
+        &["--allow-upload", "--global-symbols"],
+    );
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(format!("upload bind:now consumer {}\n", consumer).as_bytes())
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK");
+    child.kill().ok();
+}
+
+#[test]
+fn metrics_are_served_in_prometheus_format() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let metrics_port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let (mut child, addr) = start_server_with(&["--metrics-port", &metrics_port.to_string()]);
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(
+            b"call AddNumbers sig:int,int->int 2 3\ncall NoSuchFunction sig:void->int\ncaps\n",
+        )
+        .unwrap();
+    for _ in 0..3 {
+        read_response(&mut reader);
+    }
+
+    let scrape = |path: &str| {
+        let mut http = TcpStream::connect(("127.0.0.1", metrics_port)).unwrap();
+        http.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).unwrap();
+        response
+    };
+    let response = scrape("/metrics");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(
+        head.contains("Content-Type: text/plain; version=0.0.4"),
+        "{}",
+        head
+    );
+    for expected in [
+        "# TYPE dllbridge32_requests_total counter",
+        "dllbridge32_requests_total 3",
+        "dllbridge32_calls_total 2",
+        "dllbridge32_errors_total 1",
+        "# TYPE dllbridge32_active_connections gauge",
+        "dllbridge32_active_connections 1",
+        "dllbridge32_connections_total 1",
+        "dllbridge32_libraries 1",
+        "# TYPE dllbridge32_request_duration_seconds histogram",
+        "dllbridge32_request_duration_seconds_bucket{le=\"+Inf\"} 3",
+        "dllbridge32_request_duration_seconds_count 3",
+    ] {
+        assert!(
+            body.lines().any(|line| line == expected),
+            "{}\n{}",
+            expected,
+            body
+        );
+    }
+    assert!(
+        body.contains("\ndllbridge32_request_duration_seconds_sum "),
+        "{}",
+        body
+    );
+    assert!(body.contains("\ndllbridge32_uptime_seconds "), "{}", body);
+
+    assert!(scrape("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    // Closed connections no longer count as active.
+    drop(stream);
+    drop(reader);
+    thread::sleep(Duration::from_millis(100));
+    assert!(scrape("/metrics").contains("\ndllbridge32_active_connections 0\n"));
+
+    child.kill().ok();
+}
+
+#[test]
+fn latin1_string_round_trip() {
+    let (mut child, addr) = start_server_with(&["--string-encoding", "latin1"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    // "café" is 4 bytes in Latin-1 but 5 in UTF-8.
+    stream
+        .write_all("call string_length sig:str->int café\n".as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 4");
+
+    stream
+        .write_all("call echo_string sig:str->str café\n".as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK café");
+
+    stream
+        .write_all("call enc:utf-8 string_length sig:str->int café\n".as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    child.kill().ok();
+}
+
+#[test]
+fn capabilities_report_enabled_features() {
+    let (mut child, addr) = start_server_with(&["--allow-upload", "--max-args", "8"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"caps\n")
+        .expect("Couldnt not write to stream!");
+    let caps = read_result(&mut reader);
+    let fields: Vec<&str> = caps.split_whitespace().collect();
+    assert_eq!(fields[0], "caps");
+    assert!(fields.contains(&"max_args=8"), "{}", caps);
+    let features = fields
+        .iter()
+        .find_map(|f| f.strip_prefix("features="))
+        .unwrap();
+    assert!(features.split(',').any(|f| f == "upload"), "{}", caps);
+    let commands = fields
+        .iter()
+        .find_map(|f| f.strip_prefix("commands="))
+        .unwrap();
+    assert!(commands.split(',').any(|c| c == "call"), "{}", caps);
+
+    child.kill().ok();
+}
+
+#[test]
+fn types_lists_every_type_with_its_width() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"types\n")
+        .expect("Couldnt not write to stream!");
+    let types = read_result(&mut reader);
+    let entries: Vec<&str> = types
+        .strip_prefix("types ")
+        .expect(&types)
+        .split("; ")
+        .collect();
+    for expected in [
+        "int aliases=- width=4 pointer=false",
+        "float aliases=- width=4 pointer=false",
+        "void aliases=- width=0 pointer=false",
+    ] {
+        assert!(entries.contains(&expected), "{}", types);
+    }
+    let pointer = format!(
+        "str aliases=string,char*,cstr width={} pointer=true",
+        std::mem::size_of::<usize>()
+    );
+    assert!(entries.contains(&pointer.as_str()), "{}", types);
+
+    child.kill().ok();
+}
+
+#[test]
+fn unknown_commands_point_to_help() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+    for (request, expected) in [
+        (
+            "frobnicate 1 2",
+            "ERR E_UNKNOWN_CMD unknown command 'frobnicate'; try 'help'",
+        ),
+        (
+            "CALL helloworld",
+            "ERR E_UNKNOWN_CMD unknown command 'CALL'; try 'help'",
+        ),
+    ] {
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+    stream
+        .write_all(b"help\n")
+        .expect("Couldnt not write to stream!");
+    let help = read_result(&mut reader);
+    assert!(help.starts_with("call [modifiers] <function>"));
+    assert!(
+        help.contains(
+            "\tadmin stats | admin config | admin reload | admin unload <alias> | \
+             admin open <path> [read|write|append|readwrite] | admin close <handle> | \
+             admin shutdown\t"
+        ),
+        "{}",
+        help
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn argument_count_errors_show_expected_and_received() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+    for (request, expected) in [
+        (
+            "call echo_string sig:str->str a b",
+            "ERR Expected 1 arguments, got 2: expected (str), got [a,b]",
+        ),
+        (
+            "call divmod sig:int,inout int,out int->void 17",
+            "ERR Expected 2 arguments, got 1: expected (int,inout int), got [17]",
+        ),
+    ] {
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn strings_are_terminated_as_their_type_declares() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+    for (request, expected) in [
+        // A C string ends in a NUL.
+        ("call byte_at sig:cstr,int->int abc 3", "OK 0"),
+        // A counted string gets only its bytes, and its length...
+        ("call counted_byte_at sig:str_len,int->int abc 2", "OK 3099"),
+        // ...unless a terminator is asked for, which the length leaves out.
+        (
+            "call counted_byte_at sig:str_len:nul,int->int abc 3",
+            "OK 3000",
+        ),
+        ("call count_byte sig:str_len:nul,char->int abc 0", "OK 0"),
+        (
+            "call byte_at sig:cstr:nul,int->int abc 3",
+            "ERR cstr:nul is always NUL-terminated; pass a counted string as str_len or str_len:nul",
+        ),
+        (
+            "call counted_byte_at sig:str_len:nul,int->str_len:nul abc 3",
+            "ERR E_BAD_RET Unsupported return type: str_len:nul",
+        ),
+    ] {
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn over_long_signatures_are_refused() {
+    let (mut child, addr) = start_server_with(&["--max-signature-length", "16"]);
+
+    let (mut stream, mut reader) = connect(addr);
+    for (request, expected) in [
+        ("call AddNumbers sig:int,int->int 2 3", "OK 5"),
+        (
+            "call AddNumbers sig:int,int,int,int->int 2 3",
+            "ERR E_SIG_TOO_LONG signature exceeds 16 bytes",
+        ),
+        // Tokens are joined only until the limit is reached.
+        (
+            "call AddNumbers sig:int, int, int ->int 2 3",
+            "ERR E_SIG_TOO_LONG signature exceeds 16 bytes",
+        ),
+        // The signature is refused before the function is even looked up.
+        (
+            "call no_such_function sig:int,int,int,int->int 2 3",
+            "ERR E_SIG_TOO_LONG signature exceeds 16 bytes",
+        ),
+        (
+            "bind AddNumbers sig:int,int,int,int->int",
+            "ERR E_SIG_TOO_LONG signature exceeds 16 bytes",
+        ),
+    ] {
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+    child.kill().ok();
+
+    // The default limit still refuses a signature of thousands of tokens.
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+    let request = format!("call AddNumbers sig:{} ->int 2 3\n", "int, ".repeat(5000));
+    stream
+        .write_all(request.as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_SIG_TOO_LONG signature exceeds 4096 bytes"
+    );
+    child.kill().ok();
+}
+
+#[test]
+fn lenient_args_coerce_between_numeric_types() {
+    let (mut child, addr) = start_server_with(&["--lenient-args"]);
+
+    let (mut stream, mut reader) = connect(addr);
+    for (request, expected) in [
+        (
+            "call AddNumbers sig:int,int->int 3.0 2",
+            "OK 5 coerced=arg[1]:3.0->3",
+        ),
+        (
+            "call AddNumbers sig:int,int->int -3.7 1e1",
+            "OK 7 coerced=arg[1]:-3.7->-3,arg[2]:1e1->10",
+        ),
+        (
+            "call float_identity sig:float->float 0x10",
+            "OK 16 coerced=arg[1]:0x10->16",
+        ),
+        // Arguments that parse as they are get no note.
+        ("call float_identity sig:float->float 2", "OK 2"),
+        ("call AddNumbers sig:int,int->int 2 3", "OK 5"),
+        (
+            "call AddNumbers sig:int,int->int 1e20 2",
+            "ERR E_PARSE Argument parsing error: '1e20' is not a valid int",
+        ),
+    ] {
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+    child.kill().ok();
+
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+    for (request, expected) in [
+        (
+            "call AddNumbers sig:int,int->int 3.0 2",
+            "ERR E_PARSE Argument parsing error: '3.0' is not a valid int",
+        ),
+        (
+            "call float_identity sig:float->float 0x10",
+            "ERR E_PARSE Argument parsing error: '0x10' is not a valid float",
+        ),
+    ] {
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+    child.kill().ok();
+}
+
+#[test]
+fn long_replies_are_gzipped_once_negotiated() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    let addrs = format!("addrs {}\n", vec!["AddNumbers"; 200].join(" "));
+    stream.write_all(addrs.as_bytes()).unwrap();
+    let plain = read_response(&mut reader);
+    assert!(plain.starts_with("OK AddNumbers=0x"), "{}", plain);
+
+    stream.write_all(b"caps compress=deflate,gzip\n").unwrap();
+    let caps = read_response(&mut reader);
+    assert!(caps.ends_with(" compression=gzip"), "{}", caps);
+
+    // Short replies are not worth compressing.
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 2 3\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    stream
+        .write_all(format!("id:7 {}", addrs).as_bytes())
+        .unwrap();
+    let compressed = read_response(&mut reader);
+    let encoded = compressed
+        .strip_prefix("id:7 gzip:")
+        .unwrap_or_else(|| panic!("{}", compressed));
+    assert!(encoded.len() < plain.len() / 4, "{}", compressed);
+    let mut gunzip = Command::new("sh")
+        .args(["-c", "base64 -d | gzip -dc"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Couldn't run gzip");
+    gunzip
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(encoded.as_bytes())
+        .unwrap();
+    let output = gunzip.wait_with_output().unwrap();
+    assert!(output.status.success(), "gzip rejected the reply");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), plain);
+
+    stream.write_all(b"caps compress=none\n").unwrap();
+    assert!(read_response(&mut reader).ends_with(" compression=none"));
+    stream.write_all(addrs.as_bytes()).unwrap();
+    assert_eq!(read_response(&mut reader), plain);
+
+    child.kill().ok();
+}
+
+#[test]
+fn bound_signatures_last_for_the_connection() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"bind AddNumbers sig:int,int->int\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK");
+    stream.write_all(b"call AddNumbers 2 3\n").unwrap();
+    assert_eq!(read_response(&mut reader), "OK 5");
+    stream.write_all(b"call AddNumbers 40 2\n").unwrap();
+    assert_eq!(read_response(&mut reader), "OK 42");
+    stream.write_all(b"describe AddNumbers\n").unwrap();
+    assert!(read_response(&mut reader).ends_with(" source=bind"));
+
+    stream.write_all(b"bind AddNumbers sig:int->\n").unwrap();
+    assert!(read_response(&mut reader).starts_with("ERR "));
+    stream
+        .write_all(b"bind NoSuchFunction sig:void->int\n")
+        .unwrap();
+    assert!(read_response(&mut reader).starts_with("ERR "));
+
+    // Another connection has no bindings.
+    let (mut other, mut other_reader) = connect(addr);
+    other.write_all(b"call AddNumbers 2 3\n").unwrap();
+    assert!(read_response(&mut other_reader).starts_with("ERR "));
+
+    child.kill().ok();
+}
+
+#[test]
+fn cpp_functions_are_found_by_their_demangled_prototype() {
+    let (mut child, addr) = start_server_for(cpp_fixture_lib(), &[]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call cpp:twice sig:int->int 21\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 42");
+    stream
+        .write_all(b"call cpp:add(int,int) sig:int,int->int 2 3\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 5");
+    stream
+        .write_all(b"call cpp:add(float,float) sig:float,float->float 1.5 2\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 3.5");
+    stream
+        .write_all(b"call cpp:same(charconst*,charconst*) sig:str,str->int abc abc\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 1");
+    stream
+        .write_all(b"call cpp:geometry::area(int,int) sig:int,int->int 6 7\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 42");
+
+    stream
+        .write_all(b"describe cpp:geometry::manhattan\n")
+        .unwrap();
+    let describe = read_response(&mut reader);
+    assert!(
+        describe.contains(" export=_ZN8geometry9manhattanEPKNS_5PointES2_ match=demangled "),
+        "{}",
+        describe
+    );
+
+    stream
+        .write_all(b"call cpp:add sig:int,int->int 2 3\n")
+        .unwrap();
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR C++ function add is overloaded: add(float, float), add(int, int); give its parameter types"
+    );
+    stream
+        .write_all(b"call cpp:add(long,long) sig:int,int->int 2 3\n")
+        .unwrap();
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR No C++ function matches add(long,long)"
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn injected_faults_follow_their_schedule() {
+    let (mut child, addr) =
+        start_server_with(&["--fault-inject", "error:E_BUSY@every:3,truncate@once:5"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    let mut replies = Vec::new();
+    for _ in 0..4 {
+        stream
+            .write_all(b"id:1 call AddNumbers sig:int,int->int 20 22\n")
+            .unwrap();
+        replies.push(read_response(&mut reader));
+    }
+    assert_eq!(
+        replies,
+        [
+            "id:1 OK 42",
+            "id:1 OK 42",
+            "id:1 ERR E_BUSY injected fault",
+            "id:1 OK 42"
+        ]
+    );
+
+    // The fifth reply is cut short and the connection closed.
+    stream
+        .write_all(b"id:1 call AddNumbers sig:int,int->int 20 22\n")
+        .unwrap();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "id:1 ");
+
+    // Every connection counts its own requests.
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 1 2\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 3");
+
+    child.kill().ok();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--fault-inject", "error:busy@every:3"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(64));
+}
+
+#[test]
+fn long_replies_arrive_whole_through_small_reads() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg(addr.port().to_string())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (mut stream, reader) = connect(addr);
+    // A reader that takes the reply a few bytes at a time, so the server's
+    // socket buffer fills and its writes are accepted in pieces.
+    let mut reader = BufReader::with_capacity(7, reader.into_inner());
+
+    let names = vec!["AddNumbers"; 20_000].join(" ");
+    for _ in 0..2 {
+        stream
+            .write_all(format!("addrs {}\n", names).as_bytes())
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let reply = read_result(&mut reader);
+        assert_eq!(reply.split(' ').count(), 20_000);
+        assert!(
+            reply
+                .split(' ')
+                .all(|field| field.starts_with("AddNumbers=0x"))
+        );
+    }
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 2 3\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    // A client that leaves mid-reply only ends its own connection.
+    let (mut gone, _) = connect(addr);
+    gone.write_all(format!("addrs {}\n", names).as_bytes())
+        .unwrap();
+    drop(gone);
+    thread::sleep(Duration::from_millis(200));
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 2 3\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    child.kill().ok();
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    child.wait().ok();
+    assert!(stderr.contains(": write failed: "), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn response_prefix_and_suffix_wrap_every_reply_line() {
+    let (mut child, addr) = start_server_with(&[
+        "--response-prefix",
+        "\\x02",
+        "--response-suffix",
+        "<\\x03",
+        "--allow-trace",
+    ]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"id:4 call AddNumbers sig:int,int->int 2 3\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader), "\x02id:4 OK 5<\x03");
+    stream
+        .write_all(b"call NoSuchFunction sig:void->int\n")
+        .unwrap();
+    let error = read_response(&mut reader);
+    assert!(error.starts_with("\x02ERR "), "{:?}", error);
+    assert!(error.ends_with("<\x03"), "{:?}", error);
+
+    // Each line of a multi-line reply is wrapped on its own.
+    stream
+        .write_all(b"call +trace AddNumbers sig:int,int->int 2 3\n")
+        .unwrap();
+    loop {
+        let line = read_response(&mut reader);
+        assert!(
+            line.starts_with('\x02') && line.ends_with("<\x03"),
+            "{:?}",
+            line
+        );
+        if line == "\x025<\x03" {
+            break;
+        }
+    }
+
+    child.kill().ok();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--delimiter", "\\0", "--response-suffix", "end\\0"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(64));
+}
+
+#[test]
+fn per_call_timeout_override() {
+    let (mut child, addr) = start_server_with(&["--call-timeout", "100"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call sleep_ms sig:int->int 300\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR E_TIMEOUT"));
+
+    stream
+        .write_all(b"call timeout:2000 sleep_ms sig:int->int 300\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 300");
+
+    stream
+        .write_all(b"call timeout:999999999 sleep_ms sig:int->int 1\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR "));
+
+    child.kill().ok();
+}
+
+#[test]
+fn blank_lines_are_ignored() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"\n   \n\t\r\ncall helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 42");
+
+    child.kill().ok();
+}
+
+#[test]
+fn pointer_handle_lifecycle() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call counter_create sig:int->ptr 10\n")
+        .expect("Couldnt not write to stream!");
+    let handle = read_result(&mut reader);
+    assert!(handle.starts_with("0x"), "unexpected handle: {}", handle);
+
+    stream
+        .write_all(format!("call counter_add sig:ptr,int->int {} 5\n", handle).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 15");
+
+    stream
+        .write_all(format!("call counter_add sig:ptr,int->int {} 1\n", handle).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 16");
+
+    stream
+        .write_all(format!("call counter_destroy sig:ptr->void {}\n", handle).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+
+    child.kill().ok();
+}
+
+#[test]
+fn caps_string_result_size() {
+    let (mut child, addr) = start_server_with(&["--max-result-bytes", "100"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call repeat_char sig:char,int->str x 1000\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR E_RESULT_TOO_LARGE"));
+
+    stream
+        .write_all(b"call repeat_char sig:char,int->str x 100\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_result(&mut reader), "x".repeat(100));
+
+    child.kill().ok();
+}
+
+#[test]
+fn schema_supplies_calling_convention() {
+    let schema = write_temp_file(
+        "stdcall.schema",
+        "# trusted declarations\nComputeSumStdCall = int,int(stdcall)->int\n",
+    );
+    let (mut child, addr) = start_server_with(&["--schema", &schema]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call ComputeSumStdCall 8 9\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 17");
+
+    child.kill().ok();
+}
+
+#[test]
+fn schema_names_enum_like_results() {
+    let schema = write_temp_file(
+        "enum.schema",
+        "string_length = str->int; enum=0=EMPTY, 2=PAIR\n",
+    );
+    let (mut child, addr) = start_server_with(&["--schema", &schema]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        ("call string_length ab\n", "OK 2 (PAIR)"),
+        ("call +hex string_length ab\n", "OK 2 (0x00000002) (PAIR)"),
+        // A value without a name keeps the plain reply.
+        ("call string_length abc\n", "OK 3"),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+
+    let schema = write_temp_file("enum_str.schema", "echo_string = str->str; enum=0=OK\n");
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--schema", &schema])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(78));
+}
+
+#[test]
+fn schema_constraints_are_checked_before_calling() {
+    let schema = write_temp_file(
+        "constraints.schema",
+        "AddNumbers = int,int->int; arg1=0..255; arg2=1|2|4\n\
+         echo_string = str->str; arg1=red|green\n",
+    );
+    let (mut child, addr) = start_server_with(&["--schema", &schema]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        ("call AddNumbers 10 2\n", "OK 12"),
+        ("call AddNumbers 0xff 4\n", "OK 259"),
+        (
+            "call AddNumbers 256 2\n",
+            "ERR E_CONSTRAINT arg[1] out of range [0,255]",
+        ),
+        (
+            "call AddNumbers 10 3\n",
+            "ERR E_CONSTRAINT arg[2] is not one of 1|2|4",
+        ),
+        // `check` applies the same constraints without calling.
+        ("check AddNumbers 7 1\n", "OK"),
+        (
+            "check AddNumbers -1 1\n",
+            "ERR E_CONSTRAINT arg[1] out of range [0,255]",
+        ),
+        ("call echo_string red\n", "OK red"),
+        (
+            "call echo_string blue\n",
+            "ERR E_CONSTRAINT arg[1] is not one of red|green",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+
+    let schema = write_temp_file(
+        "bad_constraint.schema",
+        "AddNumbers = int,int->int; arg3=0..1\n",
+    );
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--schema", &schema])
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(78));
+}
+
+#[test]
+fn enum_arguments_are_passed_as_their_value() {
+    let schema = write_temp_file(
+        "enums.schema",
+        "@enum Color = 0=RED, 1=GREEN, 0x10=BLUE\n\
+         AddNumbers = int,int->int; arg1=0..16\n",
+    );
+    let (mut child, addr) = start_server_with(&["--schema", &schema]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        ("call AddNumbers arg:Color:BLUE 1\n", "OK 17"),
+        ("call AddNumbers arg:Color:GREEN arg:Color:RED\n", "OK 1"),
+        // Enums apply to any int parameter, with or without a schema entry.
+        (
+            "call ComputeSumStdCall sig:int,int->int arg:Color:BLUE 2\n",
+            "OK 18",
+        ),
+        (
+            "call AddNumbers arg:Color:PURPLE 1\n",
+            "ERR E_ENUM Color has no member 'PURPLE' (expected RED|GREEN|BLUE)",
+        ),
+        (
+            "call AddNumbers arg:Colour:RED 1\n",
+            "ERR E_ENUM unknown enum 'Colour'",
+        ),
+        ("check AddNumbers arg:Color:BLUE 1\n", "OK"),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+
+    let schema = write_temp_file("bad_enum.schema", "@enum Color = 0=RED, 1=RED\n");
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--schema", &schema])
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(78));
+}
+
+#[test]
+fn flag_arguments_are_combined() {
+    let schema = write_temp_file(
+        "flags.schema",
+        "@flags Access = 1=READ, 2=WRITE, 4=EXEC\n\
+         @flags Generic = 0x80000000=GENERIC_READ, 0x40000000=GENERIC_WRITE\n\
+         @enum Color = 0=RED, 1=GREEN\n",
+    );
+    let (mut child, addr) = start_server_with(&["--schema", &schema]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        (
+            "call AddNumbers sig:int,int->int arg:Access:READ|WRITE 0\n",
+            "OK 3",
+        ),
+        (
+            "call AddNumbers sig:int,int->int arg:Access:EXEC|READ|EXEC 0\n",
+            "OK 5",
+        ),
+        (
+            "call +hex AddNumbers sig:int,int->int arg:Generic:GENERIC_READ|GENERIC_WRITE 0\n",
+            "OK -1073741824 (0xC0000000)",
+        ),
+        (
+            "call AddNumbers sig:int,int->int arg:Access:READ|DELETE 0\n",
+            "ERR E_ENUM Access has no member 'DELETE' (expected READ|WRITE|EXEC)",
+        ),
+        (
+            "call AddNumbers sig:int,int->int arg:Color:RED|GREEN 0\n",
+            "ERR E_ENUM Color is not declared with @flags; give one member",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn history_lists_calls_and_replays_them() {
+    let schema = write_temp_file("history.schema", "echo_string = str->str; redact=1\n");
+    let (mut child, addr) = start_server_with(&["--schema", &schema, "--history-size", "3"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        ("history\n", "OK history"),
+        ("call AddNumbers sig:int,int->int 2 3\n", "OK 5"),
+        ("call echo_string s3cret\n", "OK s3cret"),
+        (
+            "call AddNumbers sig:int,int->int 2\n",
+            "ERR Expected 2 arguments, got 1: expected (int,int), got [2]",
+        ),
+        (
+            "history\n",
+            "OK history 1: call AddNumbers sig:int,int->int 2 3 -> OK 5; \
+             2: call echo_string <redacted> -> OK s3cret; \
+             3: call AddNumbers sig:int,int->int 2 -> ERR Expected 2 arguments, got 1: expected (int,int), got [2]",
+        ),
+        ("replay 1\n", "OK 5"),
+        // The replay is recorded, and the oldest call no longer fits.
+        (
+            "history\n",
+            "OK history 2: call echo_string <redacted> -> OK s3cret; \
+             3: call AddNumbers sig:int,int->int 2 -> ERR Expected 2 arguments, got 1: expected (int,int), got [2]; \
+             4: call AddNumbers sig:int,int->int 2 3 -> OK 5",
+        ),
+        ("replay 2\n", "OK s3cret"),
+        (
+            "replay 1\n",
+            "ERR E_HISTORY no call 1 in this connection's history",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    // Each connection has a history of its own.
+    let (mut other, mut other_reader) = connect(addr);
+    other
+        .write_all(b"history\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut other_reader), "OK history");
+
+    child.kill().ok();
+}
+
+#[test]
+fn schema_with_unknown_convention_fails_to_load() {
+    let schema = write_temp_file("pascal.schema", "helloworld = void(pascal)->int\n");
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--schema", &schema])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(78));
+}
+
+/// Reads one id (`Uid` or `Gid`) line of a process's status as (real, effective).
+#[cfg(target_os = "linux")]
+fn process_ids(pid: u32, key: &str) -> (u32, u32) {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).unwrap();
+    let line = status
+        .lines()
+        .find(|l| l.starts_with(&format!("{}:", key)))
+        .unwrap();
+    let ids: Vec<u32> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|id| id.parse().unwrap())
+        .collect();
+    (ids[0], ids[1])
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn drops_privileges_after_binding() {
+    if unsafe { libc::geteuid() } != 0 {
+        println!("skipping: requires root");
+        return;
+    }
+    let (mut child, addr) = start_server_with(&["--user", "65534", "--group", "65534"]);
+
+    // Once the server answers, it has finished starting up.
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 42");
+
+    assert_eq!(process_ids(child.id(), "Uid"), (65534, 65534));
+    assert_eq!(process_ids(child.id(), "Gid"), (65534, 65534));
+
+    child.kill().ok();
+}
+
+#[test]
+fn resolves_many_addresses() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"addrs helloworld no_such_function AddNumbers\n")
+        .expect("Couldnt not write to stream!");
+    let response = read_result(&mut reader);
+    let fields: Vec<&str> = response.split_whitespace().collect();
+    assert_eq!(fields.len(), 3, "{}", response);
+    assert!(fields[0].starts_with("helloworld=0x"), "{}", response);
+    assert_eq!(fields[1], "no_such_function=null");
+    assert!(fields[2].starts_with("AddNumbers=0x"), "{}", response);
+    assert_ne!(
+        fields[0]["helloworld=".len()..],
+        fields[2]["AddNumbers=".len()..]
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn machine_mode_ready_line() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg(port.to_string())
+        .arg("--machine")
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, format!("EVENT ready port={} libs=1\n", port));
+
+    child.kill().ok();
+    child.wait().ok();
+}
+
+#[test]
+fn discard_modifier_skips_return_value() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call counter_create sig:int->ptr 1\n")
+        .expect("Couldnt not write to stream!");
+    let handle = read_result(&mut reader);
+
+    stream
+        .write_all(format!("call +discard counter_add sig:ptr,int->int {} 4\n", handle).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+
+    // The discarded call still ran.
+    stream
+        .write_all(format!("call counter_add sig:ptr,int->int {} 0\n", handle).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    child.kill().ok();
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn default_convention_applies_to_unannotated_signatures() {
+    let (mut child, addr) = start_server_with(&["--default-convention", "win64"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    // AddNumbersWin64 is ms_abi; it only adds up if the default is applied.
+    stream
+        .write_all(b"call AddNumbersWin64 sig:int,int->int 2 3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    // An explicit convention still overrides the default.
+    stream
+        .write_all(b"call AddNumbers sig:int,int(cdecl)->int 20 22\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 42");
+
+    child.kill().ok();
+}
+
+#[test]
+fn invalid_default_convention_is_rejected() {
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0")
+        .args(["--default-convention", "pascal"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(64));
+}
+
+#[test]
+fn access_log_records_each_request() {
+    let log = write_temp_file("access.log", "");
+    let (mut child, addr) = start_server_with(&["--access-log", &log]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"id:3 call AddNumbers sig:int,int->int 1 2\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "id:3 OK 3");
+    stream
+        .write_all(b"call timeout:50 sleep_ms sig:int->void 1000\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR E_TIMEOUT"));
+
+    let contents = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", contents);
+
+    let fields: Vec<&str> = lines[0].split(' ').collect();
+    assert_eq!(fields.len(), 6, "{}", lines[0]);
+    let ts = fields[0].strip_prefix("ts=").unwrap();
+    assert_eq!(ts.len(), "2026-01-01T00:00:00.000Z".len(), "{}", ts);
+    assert!(ts.ends_with('Z') && ts.as_bytes()[10] == b'T', "{}", ts);
+    assert!(fields[1].starts_with("peer=127.0.0.1:"));
+    assert_eq!(&fields[2..5], ["cmd=call", "fn=AddNumbers", "status=ok"]);
+    let latency = fields[5].strip_prefix("latency_us=").unwrap();
+    assert!(latency.parse::<u64>().is_ok());
+
+    assert!(
+        lines[1].contains(" cmd=call fn=sleep_ms status=err error=E_TIMEOUT latency_us="),
+        "{}",
+        lines[1]
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn keepalive_pings_idle_connections() {
+    let (mut child, addr) = start_server_with(&["--keepalive", "100"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    assert_eq!(read_response(&mut reader), "PING");
+    assert_eq!(read_response(&mut reader), "PING");
+
+    // Requests are still answered normally between keepalives.
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 1 2\n")
+        .expect("Couldnt not write to stream!");
+    let mut response = read_response(&mut reader);
+    while response == "PING" {
+        response = read_response(&mut reader);
+    }
+    assert_eq!(response, "OK 3");
+
+    child.kill().ok();
+}
+
+#[test]
+fn array_return_with_out_length() {
+    let schema = write_temp_file("arrays.schema", "make_range = int,ptr->int[out:2]\n");
+    let (mut child, addr) = start_server_with(&["--schema", &schema]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    // The length out-parameter is filled in by the server, so only `n` is sent.
+    stream
+        .write_all(b"call make_range 5\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK [0,1,4,9,16]");
+
+    stream
+        .write_all(b"call make_range 0\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK []");
+
+    stream
+        .write_all(b"call make_range sig:int,int->int[out:2] 3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_BAD_RET Array length parameter 2 must be an out int or ptr parameter"
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn peer_token_injects_client_address() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call record_caller sig:str->str @peer\n")
+        .expect("Couldnt not write to stream!");
+    let own_addr = stream.local_addr().unwrap().to_string();
+    assert_eq!(read_result(&mut reader), own_addr);
+
+    stream
+        .write_all(b"call record_caller sig:str->str @@peer\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK @peer");
+
+    child.kill().ok();
+}
+
+#[test]
+fn accepted_connections_use_nodelay() {
+    let features = |extra_args: &[&str]| {
+        let (mut child, addr) = start_server_with(extra_args);
+        let (mut stream, mut reader) = connect(addr);
+        stream
+            .write_all(b"caps\n")
+            .expect("Couldnt not write to stream!");
+        let caps = read_response(&mut reader);
+        child.kill().ok();
+        caps.split(' ')
+            .find_map(|f| f.strip_prefix("features="))
+            .unwrap()
+            .split(',')
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    assert!(features(&[]).contains(&"nodelay".to_string()));
+    assert!(!features(&["--no-nodelay"]).contains(&"nodelay".to_string()));
+}
+
+/// Calls `request` on two connections at once and returns how long both took.
+fn time_concurrent_calls(addr: SocketAddr, request: &'static [u8]) -> Duration {
+    let started = std::time::Instant::now();
+    let calls: Vec<_> = (0..2)
+        .map(|_| {
+            thread::spawn(move || {
+                let (mut stream, mut reader) = connect(addr);
+                stream
+                    .write_all(request)
+                    .expect("Couldnt not write to stream!");
+                assert_eq!(read_response(&mut reader), "OK 300");
+            })
+        })
+        .collect();
+    for call in calls {
+        call.join().unwrap();
+    }
+    started.elapsed()
+}
+
+#[test]
+fn non_reentrant_functions_are_serialized() {
+    let schema = write_temp_file(
+        "reentrancy.schema",
+        "sleep_ms = int->int; reentrant=false\nwait_ms = int->int\n",
+    );
+    let (mut child, addr) = start_server_with(&["--schema", &schema]);
+
+    let serialized = time_concurrent_calls(addr, b"call sleep_ms 300\n");
+    assert!(serialized >= Duration::from_millis(600), "{:?}", serialized);
+
+    let concurrent = time_concurrent_calls(addr, b"call wait_ms 300\n");
+    assert!(concurrent < Duration::from_millis(550), "{:?}", concurrent);
+
+    child.kill().ok();
+
+    // Declaring the whole library non-reentrant serializes every function.
+    let schema = write_temp_file("library.schema", "@library reentrant=false\n");
+    let (mut child, addr) = start_server_with(&["--schema", &schema]);
+    let serialized = time_concurrent_calls(addr, b"call wait_ms sig:int->int 300\n");
+    assert!(serialized >= Duration::from_millis(600), "{:?}", serialized);
+
+    child.kill().ok();
+}
+
+#[test]
+fn unreadable_return_types_are_refused() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for request in [
+        "call helloworld sig:void->struct\n",
+        "check helloworld sig:void->struct\n",
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(
+            read_response(&mut reader),
+            "ERR E_BAD_RET Unsupported return type: struct"
+        );
+    }
+
+    stream
+        .write_all(b"check make_range sig:int,ptr->char[out:2]\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR E_BAD_RET "));
+
+    stream
+        .write_all(b"check AddNumbers sig:int,int->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+
+    stream
+        .write_all(b"check NoSuchFunction sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR "));
+
+    child.kill().ok();
+}
+
+#[test]
+fn float_bit_patterns_pass_unchanged() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    // A signaling NaN with a payload, which no decimal spelling can carry.
+    stream
+        .write_all(b"call +bits float_bits sig:float->int 0x7fa00001\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_result(&mut reader), 0x7fa00001.to_string());
+
+    stream
+        .write_all(b"call +bits float_identity sig:float->float 0x3f800000\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 0x3f800000");
+
+    stream
+        .write_all(b"call +bits float_bits sig:float->int 1.0\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR "));
+
+    // The pattern of the double 1.0 does not fit a float.
+    stream
+        .write_all(b"call +bits float_bits sig:float->int 0x3FF0000000000000\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_RANGE Argument parsing error: '0x3FF0000000000000' is a bit pattern wider than float (32 bits)"
+    );
+    // Leading zeros do not widen it.
+    stream
+        .write_all(b"call +bits float_bits sig:float->int 0x000000003f800000\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_result(&mut reader), 0x3f800000.to_string());
+
+    child.kill().ok();
+}
+
+#[test]
+fn case_insensitive_symbol_lookup() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call addnumbers sig:int,int->int 1 2\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR "));
+    child.kill().ok();
+
+    let (mut child, addr) = start_server_with(&["--case-insensitive-symbols"]);
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call addnumbers sig:int,int->int 1 2\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 3");
+
+    // An exact match still wins over case-insensitive candidates.
+    stream
+        .write_all(b"call AMBIGUOUS_NAME sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 2");
+
+    stream
+        .write_all(b"call Ambiguous_Name sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR Symbol Ambiguous_Name is ambiguous ignoring case: AMBIGUOUS_NAME, ambiguous_name"
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn opened_files_are_passed_to_functions_as_handles() {
+    let token = write_temp_file("handles.token", "s3cret\n");
+    let data = write_temp_file("handles.txt", "AB");
+    let (mut child, addr) = start_server_with(&["--admin-token-file", &token]);
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"auth s3cret\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+    stream
+        .write_all(format!("admin open {}\n", data).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK fd:1");
+
+    for (request, expected) in [
+        // Each call reads on from where the last one stopped.
+        ("call read_first_byte sig:handle->int fd:1", "OK 65"),
+        ("call read_first_byte sig:fd->int fd:1", "OK 66"),
+        ("call read_first_byte sig:handle->int fd:1", "OK -1"),
+        (
+            "call read_first_byte sig:handle->int 0",
+            "ERR E_HANDLE '0' is not a handle token; open the file with admin open",
+        ),
+        (
+            "call read_first_byte sig:handle->int fd:2",
+            "ERR E_HANDLE unknown handle fd:2",
+        ),
+        ("admin close fd:1", "OK"),
+        (
+            "call read_first_byte sig:handle->int fd:1",
+            "ERR E_HANDLE unknown handle fd:1",
+        ),
+        ("admin close fd:1", "ERR E_HANDLE unknown handle fd:1"),
+        (
+            "call read_first_byte sig:int->handle 1",
+            "ERR E_BAD_RET Unsupported return type: handle",
+        ),
+    ] {
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn admin_commands_require_the_token() {
+    let token = write_temp_file("admin.token", "s3cret\n");
+    let (mut child, addr) = start_server_with(&["--admin-token-file", &token]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for request in ["admin stats\n", "admin shutdown\n", "auth wrong\n"] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert!(read_response(&mut reader).starts_with("ERR E_AUTH "));
+    }
+
+    // Authenticating one connection does not unlock another.
+    let (mut other, mut other_reader) = connect(addr);
+    stream
+        .write_all(b"auth s3cret\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+    other
+        .write_all(b"admin stats\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut other_reader).starts_with("ERR E_AUTH "));
+
+    stream
+        .write_all(b"admin stats\n")
+        .expect("Couldnt not write to stream!");
+    let stats = read_result(&mut reader);
+    assert!(stats.starts_with("stats uptime_s="), "{}", stats);
+    assert!(stats.contains(" connections=2 "), "{}", stats);
+
+    stream
+        .write_all(b"admin shutdown\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK shutting down");
+
+    assert!(wait_for_exit(&mut child).success());
+}
+
+#[test]
+fn admin_config_reports_the_effective_settings() {
+    let token = write_temp_file("config.token", "t0psecret\n");
+    let (mut child, addr) = start_server_with(&[
+        "--admin-token-file",
+        &token,
+        "--max-args",
+        "8",
+        "--call-timeout",
+        "250",
+        "--allow-upload",
+        "--env",
+        "API_KEY=hunter2",
+    ]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream.write_all(b"admin config\n").unwrap();
+    assert!(read_response(&mut reader).starts_with("ERR E_AUTH "));
+
+    stream.write_all(b"auth t0psecret\n").unwrap();
+    assert_eq!(read_response(&mut reader), "OK");
+    stream.write_all(b"admin config\n").unwrap();
+    let config = read_result(&mut reader);
+    let fields: Vec<&str> = config.split_whitespace().collect();
+    assert_eq!(fields[0], "config");
+    for expected in [
+        format!("listen=tcp://127.0.0.1:{}", addr.port()),
+        "max_args=8".into(),
+        "call_timeout_ms=250".into(),
+        "max_call_timeout_ms=60000".into(),
+        "allow_upload=true".into(),
+        "keepalive_ms=-".into(),
+        "env=API_KEY=<redacted>".into(),
+        "admin_token=<redacted>".into(),
+        format!("libraries={}", fixture_lib()),
+    ] {
+        assert!(
+            fields.contains(&expected.as_str()),
+            "{} in {}",
+            expected,
+            config
+        );
+    }
+    assert!(!config.contains("t0psecret"), "{}", config);
+    assert!(!config.contains("hunter2"), "{}", config);
+
+    child.kill().ok();
+}
+
+#[test]
+fn shutdown_command_exits_cleanly() {
+    let token = write_temp_file("shutdown.token", "s3cret\n");
+    let (mut child, addr) = start_server_with(&["--admin-token-file", &token]);
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"shutdown\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_AUTH shutdown requires auth"
+    );
+    // Refused, so the server is still serving.
+    stream
+        .write_all(b"call helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 42");
+
+    stream
+        .write_all(b"auth s3cret\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+    stream
+        .write_all(b"shutdown\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK shutting down");
+
+    assert!(wait_for_exit(&mut child).success());
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_drains_in_flight_requests() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call sleep_ms sig:int->int 300\n")
+        .expect("Couldnt not write to stream!");
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(unsafe { libc::kill(child.id() as i32, libc::SIGTERM) }, 0);
+
+    // The call already running is answered before the server exits.
+    assert_eq!(read_response(&mut reader), "OK 300");
+    assert!(wait_for_exit(&mut child).success());
+}
+
+/// Waits up to five seconds for the server to exit on its own.
+fn wait_for_exit(child: &mut Child) -> std::process::ExitStatus {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        assert!(std::time::Instant::now() < deadline, "server did not exit");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn void_reply_modes() {
+    for (mode, expected) in [
+        (None, "OK"),
+        // Protocol 2 still sends the status token.
+        (Some("empty"), "OK"),
+        (Some("void"), "OK void"),
+    ] {
+        let args: Vec<&str> = mode.map(|m| vec!["--void-reply", m]).unwrap_or_default();
+        let (mut child, addr) = start_server_with(&args);
+
+        let (mut stream, mut reader) = connect(addr);
+
+        stream
+            .write_all(b"call sleep_ms sig:int->void 1\n")
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{:?}", mode);
+        // The reply is still exactly one line, so the next one is not swallowed.
+        stream
+            .write_all(b"call helloworld sig:void->int\n")
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), "OK 42");
+
+        child.kill().ok();
+    }
+}
+
+#[test]
+fn signatures_from_debug_info() {
+    let (mut child, addr) = start_server_for(debug_fixture_lib(), &[]);
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call AddNumbers 2 3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR No signature string provided"
+    );
+    child.kill().ok();
+
+    let (mut child, addr) = start_server_for(debug_fixture_lib(), &["--debug-info"]);
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call AddNumbers 2 3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    stream
+        .write_all(b"call echo_string hi\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK hi");
+
+    stream
+        .write_all(b"check helloworld\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+    child.kill().ok();
+
+    // A library without debug info explains why no signature was found.
+    let (mut child, addr) = start_server_with(&["--debug-info"]);
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call AddNumbers 2 3\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR No signature string provided ("));
+    child.kill().ok();
+}
+
+#[test]
+fn describe_reports_resolution_details() {
+    let schema = write_temp_file("describe.schema", "AddNumbers = int,int->int\n");
+    let (mut child, addr) = start_server_with(&["--schema", &schema, "--case-insensitive-symbols"]);
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"addrs AddNumbers\ndescribe AddNumbers\n")
+        .expect("Couldnt not write to stream!");
+    let address = read_result(&mut reader);
+    let address = address.strip_prefix("AddNumbers=").unwrap().to_string();
+    assert_eq!(
+        read_response(&mut reader),
+        format!(
+            "OK describe function=AddNumbers export=AddNumbers match=exact library={} address={} signature=int,int(cdecl)->int source=schema",
+            fixture_lib(),
+            address
+        )
+    );
+
+    stream
+        .write_all(b"describe helloWorld\n")
+        .expect("Couldnt not write to stream!");
+    let described = read_result(&mut reader);
+    let fields: Vec<&str> = described.split_whitespace().collect();
+    assert!(fields.contains(&"export=helloworld"), "{}", described);
+    assert!(fields.contains(&"match=case-insensitive"), "{}", described);
+    assert!(fields.contains(&"signature=-"), "{}", described);
+    assert!(fields.contains(&"source=-"), "{}", described);
+
+    stream
+        .write_all(b"describe no_such_function\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR "));
+
+    child.kill().ok();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn versioned_library_names_are_resolved() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("sonames");
+    std::fs::create_dir_all(&dir).unwrap();
+    for version in ["2", "10"] {
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(dir.join(format!("libbridgefixture.so.{}", version)))
+            .arg("testlib/lib_linux.c")
+            .status()
+            .expect("Couldn't run cc");
+        assert!(status.success());
+    }
+
+    // Without a version the highest one wins, compared as numbers.
+    for (name, loaded) in [
+        ("libbridgefixture", "libbridgefixture.so.10"),
+        ("libbridgefixture.so.2", "libbridgefixture.so.2"),
+    ] {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+            .arg(name)
+            .arg(port.to_string())
+            .env("LD_LIBRARY_PATH", &dir)
+            .spawn()
+            .unwrap();
+        let (mut stream, mut reader) = connect(([127, 0, 0, 1], port).into());
+        stream
+            .write_all(b"describe helloworld\n")
+            .expect("Couldnt not write to stream!");
+        let described = read_result(&mut reader);
+        let library = format!("library={}", dir.join(loaded).display());
+        assert!(
+            described.split_whitespace().any(|field| field == library),
+            "{}",
+            described
+        );
+        child.kill().ok();
+        child.wait().ok();
+    }
+}
+
+#[test]
+fn hex_modifier_adds_hex_form() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call +hex AddNumbers sig:int,int->int 200 55\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 255 (0x000000FF)");
+
+    stream
+        .write_all(b"call +hex AddNumbers sig:int,int->int -2 1\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK -1 (0xFFFFFFFF)");
+
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 200 55\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 255");
+
+    child.kill().ok();
+}
+
+/// Starts the server on `port` in machine mode and waits for its ready line,
+/// returning `None` if it exits instead.
+fn start_on_port(port: u16, extra_args: &[&str]) -> Option<Child> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg(port.to_string())
+        .arg("--machine")
+        .args(extra_args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    if line.starts_with("EVENT ready") {
+        Some(child)
+    } else {
+        child.wait().ok();
+        None
+    }
+}
+
+#[test]
+fn restarts_rebind_the_same_port() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+
+    for _ in 0..3 {
+        let mut child = start_on_port(port, &[]).expect("Couldn't rebind the port");
+        let (mut stream, mut reader) = connect(addr);
+        stream
+            .write_all(b"call helloworld sig:void->int\n")
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), "OK 42");
+        // The server dies first, leaving its side of the connection in TIME_WAIT.
+        child.kill().ok();
+        child.wait().ok();
+        drop(stream);
+    }
+
+    let mut child = start_on_port(0, &["--no-reuseaddr"]).expect("Couldn't bind");
+    child.kill().ok();
+    child.wait().ok();
+}
+
+#[test]
+fn batch_chains_results_by_reference() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(
+            b"batch call counter_create sig:int->ptr 5; counter_add sig:ptr,int->int $1 4; counter_destroy sig:ptr->void $1\n",
+        )
+        .expect("Couldnt not write to stream!");
+    let response = read_result(&mut reader);
+    let results: Vec<&str> = response.split("; ").collect();
+    assert_eq!(results.len(), 3, "{}", response);
+    assert!(results[0].starts_with("0x"), "{}", response);
+    assert_eq!(&results[1..], ["9", ""]);
+
+    stream
+        .write_all(b"batch helloworld sig:void->int; AddNumbers sig:int,int->int $1 $2\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_BATCH call 2: $2 does not refer to an earlier call"
+    );
+
+    stream
+        .write_all(b"batch echo_string sig:str->str $$1\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK $1");
+
+    stream
+        .write_all(b"batch echo_string sig:str->str a\\;b; echo_string sig:str->str c\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_result(&mut reader), "a\\;b; c");
+
+    child.kill().ok();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pin_cpu_restricts_server_threads() {
+    let (mut child, addr) = start_server_with(&["--pin-cpu", "0"]);
+    // A connection makes the server spawn a handler thread.
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 42");
+
+    let tasks = std::fs::read_dir(format!("/proc/{}/task", child.id())).unwrap();
+    let mut threads = 0;
+    for task in tasks {
+        let status = std::fs::read_to_string(task.unwrap().path().join("status")).unwrap();
+        let allowed = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Cpus_allowed_list:"))
+            .unwrap();
+        assert_eq!(allowed.trim(), "0");
+        threads += 1;
+    }
+    assert!(threads >= 2, "expected a handler thread");
+    child.kill().ok();
+
+    // Pinning is best effort: an unusable CPU is reported, not fatal.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg(port.to_string())
+        .args(["--machine", "--pin-cpu", "1000"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert!(line.starts_with("EVENT ready"), "{}", line);
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    assert!(line.starts_with("Failed to pin to CPUs 1000"), "{}", line);
+    child.kill().ok();
+    child.wait().ok();
+}
+
+#[test]
+fn parameter_modes() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    // `in` is the default and may be spelled out.
+    stream
+        .write_all(b"call AddNumbers sig:in int, in int->int 2 3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 5");
+
+    // `out` parameters take no argument and are read back by position.
+    stream
+        .write_all(b"call divmod sig:int,int,out int,out int->void 17 5\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 3=3 4=2");
+
+    stream
+        .write_all(b"call greeting sig:out str->void\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 1=hello");
+
+    // `inout` parameters start from the client's argument.
+    stream
+        .write_all(b"call increment sig:inout int->void 41\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 1=42");
+
+    stream
+        .write_all(b"call skip_word sig:inout str->void first\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 1=");
+
+    // An array length can be declared as an `out int` as well.
+    stream
+        .write_all(b"call make_range sig:int,out int->int[out:2] 3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK [0,1,4]");
+
+    stream
+        .write_all(b"call divmod sig:int,int,out int,out int->void 17 5 0\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR Expected 2 arguments, got 3: expected (int,int), got [17,5,0]"
+    );
+
+    stream
+        .write_all(b"check increment sig:out void->void\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR void cannot be used as a parameter type"
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn env_is_set_before_loading_the_library() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call load_mode sig:void->str\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK unset");
+    child.kill().ok();
+
+    let (mut child, addr) = start_server_with(&["--env", "DLLBRIDGE_TEST_MODE=fast"]);
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call load_mode sig:void->str\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK fast");
+    child.kill().ok();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .args(["--env", "NO_EQUALS_SIGN"])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn working_dir_is_entered_before_loading_the_library() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("working_dir");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("dllbridge_test.cfg"), "17\n").unwrap();
+
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call read_relative_config sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK -1");
+    child.kill().ok();
+
+    let (mut child, addr) = start_server_with(&["--working-dir", dir.to_str().unwrap()]);
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call read_relative_config sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 17");
+    child.kill().ok();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .args(["--working-dir"])
+        .arg(dir.join("missing"))
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(66));
+}
+
+#[test]
+fn bench_reports_aggregate_timing() {
+    let (mut child, addr) = start_server_with(&["--max-bench-iterations", "500"]);
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"bench AddNumbers sig:int,int->int 2 3 times:200\n")
+        .expect("Couldnt not write to stream!");
+    let response = read_result(&mut reader);
+    let fields: Vec<&str> = response.split_whitespace().collect();
+    assert_eq!(fields[0], "bench", "{}", response);
+    assert_eq!(fields[1], "n=200", "{}", response);
+    let value = |key: &str| -> u128 {
+        fields
+            .iter()
+            .find_map(|f| f.strip_prefix(key))
+            .unwrap_or_else(|| panic!("missing {} in {}", key, response))
+            .parse()
+            .unwrap()
+    };
+    let (min, avg, max, total) = (
+        value("min_ns="),
+        value("avg_ns="),
+        value("max_ns="),
+        value("total_ns="),
+    );
+    assert!(min <= avg && avg <= max, "{}", response);
+    assert!(total >= max && total / 200 == avg, "{}", response);
+
+    stream
+        .write_all(b"bench AddNumbers sig:int,int->int 2 3 times:501\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR Iterations must be between 1 and 500"
+    );
+
+    stream
+        .write_all(b"bench AddNumbers sig:int,int->int 2 times:3\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR Expected 2 arguments, got 1: expected (int,int), got [2]"
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn interior_nul_in_string_argument_is_refused() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call echo_string sig:str->str ab\0c\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_NUL interior null in string arg 1 at byte 2"
+    );
+
+    stream
+        .write_all(b"call echo_string sig:str->str abc\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK abc");
+
+    child.kill().ok();
+}
+
+#[test]
+fn nul_in_function_name_is_a_bad_name() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call hello\0world sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_BAD_NAME function name contains NUL"
+    );
+
+    // A NUL in an argument is reported as such, not as a bad name.
+    stream
+        .write_all(b"call echo_string sig:str->str \0\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_NUL interior null in string arg 1 at byte 0"
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn no_call_mode_validates_without_calling() {
+    let marker = Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_call_marker");
+    std::fs::remove_file(&marker).ok();
+    let (mut child, addr) = start_server_with(&["--no-call"]);
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(format!("call create_file sig:str->int {}\n", marker.display()).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK DRYRUN resolved");
+    assert!(!marker.exists(), "create_file ran in dry-run mode");
+
+    // Everything but the call itself is still checked.
+    stream
+        .write_all(b"call no_such_function sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR "));
+
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 1 x\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR E_PARSE Argument parsing error"));
+
+    stream
+        .write_all(b"caps\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).contains("no-call"));
+    child.kill().ok();
+
+    // The same call has its side effect when calls are enabled.
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(format!("call create_file sig:str->int {}\n", marker.display()).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 0");
+    assert!(marker.exists());
+    child.kill().ok();
+}
+
+#[test]
+fn nul_delimiter_frames_requests_and_replies() {
+    let (mut child, addr) = start_server_with(&["--delimiter", "\\0"]);
+    let (mut stream, mut reader) = connect(addr);
+
+    // Newlines are ordinary whitespace once they no longer end a request.
+    stream
+        .write_all(b"call AddNumbers\nsig:int,int->int 2 3\0id:7 call helloworld sig:void->int\0")
+        .expect("Couldnt not write to stream!");
+    let mut reply = Vec::new();
+    reader.read_until(0, &mut reply).unwrap();
+    assert_eq!(reply, b"OK 5\0");
+    reply.clear();
+    reader.read_until(0, &mut reply).unwrap();
+    assert_eq!(reply, b"id:7 OK 42\0");
+    child.kill().ok();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .args(["--delimiter", ""])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn signatures_spanning_several_tokens() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    let cases: &[(&[u8], &str)] = &[
+        (b"call AddNumbers sig:int,int->int 2 3\n", "OK 5"),
+        (b"call AddNumbers sig:int, int -> int 2 3\n", "OK 5"),
+        // The return type may follow `->` in the next token.
+        (b"call AddNumbers sig:int,int-> int 2 3\n", "OK 5"),
+        (b"call AddNumbers sig: int,int ->int 2 3\n", "OK 5"),
+        (
+            b"call make_range sig:int,ptr->int[out: 2] 3\n",
+            "OK [0,1,4]",
+        ),
+        (b"check AddNumbers sig:int, int -> int\n", "OK"),
+        (
+            b"call AddNumbers sig:int,int 2 3\n",
+            "ERR Malformed signature; no '->' found",
+        ),
+        // The signature ends before the argument.
+        (b"check AddNumbers sig:int,int->int 2\n", "OK"),
+    ];
+    for (request, expected) in cases {
+        stream
+            .write_all(request)
+            .expect("Couldnt not write to stream!");
+        assert_eq!(
+            read_response(&mut reader),
+            *expected,
+            "{}",
+            String::from_utf8_lossy(request)
+        );
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn signature_spacing_does_not_change_the_call() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    // Each group spells one signature with no, some and only spaces; all of
+    // them must parse alike and leave exactly the same arguments.
+    let groups: &[(&str, &[&str], &str, &str)] = &[
+        (
+            "divmod",
+            &[
+                "int,int,out int,out int->void",
+                "int, int, out int, out int -> void",
+                " int , int , out int , out int -> void",
+            ],
+            "17 5",
+            "OK 3=3 4=2",
+        ),
+        (
+            "ComputeSumStdCall",
+            &[
+                "int,int(stdcall)->int",
+                "int,int (stdcall) -> int",
+                " int , int ( stdcall ) -> int",
+            ],
+            "8 9",
+            "OK 17",
+        ),
+        (
+            "make_range",
+            &[
+                "int,ptr->int[out:2]",
+                "int, ptr -> int[out: 2]",
+                " int , ptr -> int [out: 2 ]",
+            ],
+            "4",
+            "OK [0,1,4,9]",
+        ),
+    ];
+    for (function, spellings, args, expected) in groups {
+        for spelling in *spellings {
+            stream
+                .write_all(format!("call {} sig:{} {}\n", function, spelling, args).as_bytes())
+                .expect("Couldnt not write to stream!");
+            assert_eq!(read_response(&mut reader), *expected, "sig:{}", spelling);
+
+            // One argument too many is reported the same way for every spelling.
+            stream
+                .write_all(format!("call {} sig:{} {} 1\n", function, spelling, args).as_bytes())
+                .expect("Couldnt not write to stream!");
+            assert!(
+                read_response(&mut reader).starts_with("ERR Expected "),
+                "sig:{}",
+                spelling
+            );
+        }
+    }
+
+    child.kill().ok();
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupt_on_timeout_ends_a_blocking_sleep() {
+    // With a single call slot, the library stays busy until a timed-out call returns.
+    let limit = [
+        "--call-timeout",
+        "100",
+        "--max-concurrent-calls-per-lib",
+        "1",
+    ];
+    for (interrupt, expected) in [
+        (
+            false,
+            "ERR E_LIB_BUSY 1 calls are already running in the library",
+        ),
+        (true, "OK 5"),
+    ] {
+        let mut args = limit.to_vec();
+        if interrupt {
+            args.push("--interrupt-on-timeout");
+        }
+        let (mut child, addr) = start_server_with(&args);
+        let (mut stream, mut reader) = connect(addr);
+
+        stream
+            .write_all(b"call sleep_ms sig:int->int 5000\n")
+            .expect("Couldnt not write to stream!");
+        assert_eq!(
+            read_response(&mut reader),
+            "ERR E_TIMEOUT call did not complete within 100 ms"
+        );
+        thread::sleep(Duration::from_millis(100));
+        stream
+            .write_all(b"call AddNumbers sig:int,int->int 2 3\n")
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", interrupt);
+
+        child.kill().ok();
+    }
+}
+
+#[test]
+fn calls_beyond_the_library_limit_are_refused() {
+    let (mut child, addr) = start_server_with(&["--max-concurrent-calls-per-lib", "2"]);
+
+    // Two slow calls take both slots while a third arrives.
+    let saturating =
+        thread::spawn(move || time_concurrent_calls(addr, b"call sleep_ms sig:int->int 300\n"));
+    thread::sleep(Duration::from_millis(100));
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call sleep_ms sig:int->int 1\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_LIB_BUSY 2 calls are already running in the library"
+    );
+
+    // Validation does not enter the library and is not limited.
+    stream
+        .write_all(b"check sleep_ms sig:int->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+
+    saturating.join().unwrap();
+    stream
+        .write_all(b"call sleep_ms sig:int->int 1\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 1");
+
+    child.kill().ok();
+}
+
+#[test]
+fn null_terminated_and_counted_strings() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        // `cstr` is the explicit spelling of a null-terminated `str`.
+        ("call string_length sig:cstr->int hello\n", "OK 5"),
+        // The length is inserted after the pointer and covers the text only.
+        ("call count_byte sig:str_len,char->int banana a\n", "OK 3"),
+        ("call count_byte sig:str_len,char->int abc 0\n", "OK 0"),
+        (
+            "call count_byte sig:str_len,char->int\n",
+            "ERR Expected 2 arguments, got 0: expected (str_len,char), got []",
+        ),
+        (
+            "call count_byte sig:int->str_len 1\n",
+            "ERR E_BAD_RET Unsupported return type: str_len",
+        ),
+        (
+            "call count_byte sig:out str_len->int\n",
+            "ERR str_len cannot be an out or inout parameter",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn traced_calls_report_each_step() {
+    let token = write_temp_file("trace.token", "s3cret\n");
+    let (mut child, addr) = start_server_with(&["--admin-token-file", &token]);
+    let (mut stream, mut reader) = connect(addr);
+
+    // Only authenticated clients get a trace without --allow-trace.
+    stream
+        .write_all(b"call +trace AddNumbers sig:int,int->int 8 9\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_AUTH +trace requires auth or --allow-trace"
+    );
+
+    stream
+        .write_all(b"auth s3cret\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+    stream
+        .write_all(b"id:7 call +trace AddNumbers sig:int,int->int 8 9\n")
+        .expect("Couldnt not write to stream!");
+    let header = read_response(&mut reader);
+    let count: usize = header
+        .strip_prefix("id:7 OK TRACE ")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_else(|| panic!("{}", header));
+    let trace: Vec<String> = (0..count).map(|_| read_response(&mut reader)).collect();
+    assert_eq!(trace[0], "signature int,int(cdecl)->int");
+    assert!(
+        trace[1].starts_with("symbol AddNumbers export=AddNumbers address=0x"),
+        "{:?}",
+        trace
+    );
+    assert!(trace[2].starts_with("abi cdecl ffi_abi="), "{:?}", trace);
+    assert!(trace[2].ends_with(" native_args=2"), "{:?}", trace);
+    assert_eq!(trace[3], "arg 1 int 8 raw=0x00000008");
+    assert_eq!(trace[4], "arg 2 int 9 raw=0x00000009");
+    assert_eq!(trace[5], "return int raw=0x0000000000000011");
+    assert_eq!(count, 6);
+    // The usual reply follows the trace.
+    assert_eq!(read_response(&mut reader), "17");
+
+    stream
+        .write_all(b"batch +trace AddNumbers sig:int,int->int 1 2\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_BATCH call 1: +trace cannot be used in a batch"
+    );
+
+    child.kill().ok();
+}
+
+// Only hosts without a `long double` the server can handle have a type to refuse.
+#[cfg(not(any(
+    all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(target_env = "msvc")
+    ),
+    target_env = "msvc",
+    all(target_vendor = "apple", target_arch = "aarch64")
+)))]
+#[test]
+fn unsupported_types_are_refused_before_the_call() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    let unsupported = "ERR E_UNSUPPORTED_TYPE type 'longdouble' not supported on this platform";
+    for request in [
+        "call AddNumbers sig:longdouble,int->int 1 2\n",
+        "call helloworld sig:void->longdouble\n",
+        "check helloworld sig:void->longdouble\n",
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), unsupported, "{}", request);
+    }
+
+    // Types the host cannot represent are not advertised.
+    stream
+        .write_all(b"caps\n")
+        .expect("Couldnt not write to stream!");
+    let caps = read_response(&mut reader);
+    assert!(!caps.contains("longdouble"), "{}", caps);
+
+    child.kill().ok();
+}
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(target_env = "msvc")
+))]
+#[test]
+fn x87_long_doubles_keep_their_precision() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        (
+            "call third_ld sig:void->longdouble\n",
+            "OK 0.333333333333333333342",
+        ),
+        (
+            "call scale_ld sig:longdouble,int->longdouble 0.1 3\n",
+            "OK 0.300000000000000000011",
+        ),
+        // Beyond what a double can tell apart from 1.
+        (
+            "call scale_ld sig:longdouble,int->longdouble 1.000000000000000001 1\n",
+            "OK 1.00000000000000000098",
+        ),
+        (
+            "call scale_ld sig:longdouble,int->longdouble 1e4000 3\n",
+            "OK 3.00000000000000000006e4000",
+        ),
+        (
+            "call scale_ld sig:longdouble,int->longdouble 5e-4940 1\n",
+            "OK 5.00000000001671825532e-4940",
+        ),
+        (
+            "call scale_ld sig:longdouble,int->longdouble -2.5 4\n",
+            "OK -10",
+        ),
+        (
+            "call scale_ld sig:longdouble,int->longdouble 1e5000 1\n",
+            "OK inf",
+        ),
+        (
+            "call scale_ld sig:longdouble,int->longdouble 1.2.3 1\n",
+            "ERR E_PARSE Argument parsing error: '1.2.3' is not a valid longdouble",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn functions_can_be_called_by_address() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"callat 0x1000 sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR Calls by address are disabled; start the server with --allow-raw-pointers"
+    );
+    child.kill().ok();
+
+    let (mut child, addr) = start_server_with(&["--allow-raw-pointers"]);
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"addrs AddNumbers\n")
+        .expect("Couldnt not write to stream!");
+    let response = read_result(&mut reader);
+    let address = response.strip_prefix("AddNumbers=").unwrap();
+
+    for (request, expected) in [
+        (
+            format!("callat {} sig:int,int->int 8 9\n", address),
+            "OK 17",
+        ),
+        (
+            format!("callat timeout:1000 {} sig:int,int->int 1 2\n", address),
+            "OK 3",
+        ),
+        (
+            format!("callat {} 8 9\n", address),
+            "ERR callat requires sig:<signature>",
+        ),
+        (
+            "callat null sig:void->int\n".to_string(),
+            "ERR Invalid address: null",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn startup_failures_have_distinct_exit_codes() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+            .args(args)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap()
+            .code()
+    };
+    let lib = fixture_lib();
+
+    // Usage errors are not worth retrying.
+    assert_eq!(run(&[]), Some(64));
+    assert_eq!(run(&[lib, "0", "--no-such-flag"]), Some(64));
+
+    assert_eq!(run(&["/nonexistent/library.so", "0"]), Some(69));
+
+    // A port that is taken may become free, so a bind failure is told apart.
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port().to_string();
+    assert_eq!(run(&[lib, &port]), Some(70));
+}
+
+#[test]
+fn once_serves_a_single_connection() {
+    let (mut child, addr) = start_server_with(&["--once"]);
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 8 9\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 17");
+    // The server keeps serving the connection until the client leaves.
+    assert!(child.try_wait().unwrap().is_none());
+    assert!(TcpStream::connect(addr).is_err());
+
+    drop(reader);
+    drop(stream);
+    assert!(wait_for_exit(&mut child).success());
+}
+
+#[cfg(unix)]
+#[test]
+fn tcp_and_unix_listeners_serve_side_by_side() {
+    use std::os::unix::net::UnixStream;
+
+    let socket = std::env::temp_dir().join(format!("dllbridge32-{}.sock", std::process::id()));
+    let spec = format!("unix://{}", socket.display());
+    let (mut child, addr) = start_server_with(&["--listen", &spec]);
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call AddNumbers sig:int,int->int 8 9\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 17");
+
+    let mut attempts = 0;
+    let mut unix = loop {
+        match UnixStream::connect(&socket) {
+            Ok(stream) => break stream,
+            Err(_) if attempts < 50 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("Couldn't connect to {}: {}", socket.display(), e),
+        }
+    };
+    let mut unix_reader = BufReader::new(unix.try_clone().unwrap());
+    for (request, expected) in [
+        ("call AddNumbers sig:int,int->int 1 2\n", "OK 3"),
+        // A Unix client has no address to stand in for `@peer`.
+        ("call echo_string sig:str->str @peer\n", "OK -"),
+    ] {
+        unix.write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        let mut line = String::new();
+        unix_reader.read_line(&mut line).unwrap();
+        assert_eq!(line.trim_end(), expected, "{}", request);
+    }
+
+    child.kill().ok();
+    child.wait().ok();
+    std::fs::remove_file(&socket).ok();
+}
+
+#[test]
+fn timed_calls_report_the_call_duration() {
+    let (mut child, addr) = start_server();
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call +time sleep_ms sig:int->int 20\n")
+        .expect("Couldnt not write to stream!");
+    let response = read_result(&mut reader);
+    let time_us: u128 = response
+        .strip_prefix("result=20 time_us=")
+        .and_then(|t| t.parse().ok())
+        .unwrap_or_else(|| panic!("{}", response));
+    assert!((20_000..2_000_000).contains(&time_us), "{}", response);
+
+    // Out-parameters stay part of the result.
+    stream
+        .write_all(b"call +time divmod sig:int,int,out int,out int->void 17 5\n")
+        .expect("Couldnt not write to stream!");
+    let response = read_response(&mut reader);
+    assert!(
+        response.starts_with("OK result=3=3 4=2 time_us="),
+        "{}",
+        response
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn requests_beyond_the_rate_limit_are_throttled() {
+    let (mut child, addr) = start_server_with(&["--rate-limit", "5", "--rate-limit-burst", "3"]);
+
+    let (mut stream, mut reader) = connect(addr);
+    let mut call = || {
+        stream
+            .write_all(b"call helloworld sig:void->int\n")
+            .expect("Couldnt not write to stream!");
+        read_response(&mut reader)
+    };
+
+    // The full bucket absorbs a burst, then the rest is refused.
+    for _ in 0..3 {
+        assert_eq!(call(), "OK 42");
+    }
+    assert!(call().starts_with("ERR E_RATE_LIMITED"));
+
+    // An idle client earns its allowance back.
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(call(), "OK 42");
+
+    // Other connections have buckets of their own.
+    let (mut other, mut other_reader) = connect(addr);
+    other
+        .write_all(b"call helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut other_reader), "OK 42");
+
+    child.kill().ok();
+}
+
+#[test]
+fn interactive_mode_greets_and_prompts() {
+    let (mut child, addr) = start_server_with(&["--interactive"]);
+
+    let (mut stream, mut reader) = connect(addr);
+    let expect_prompt = |reader: &mut BufReader<TcpStream>| {
+        let mut prompt = [0; 13];
+        reader.read_exact(&mut prompt).unwrap();
+        assert_eq!(&prompt, b"dllbridge32> ");
+    };
+
+    assert!(read_response(&mut reader).starts_with("Welcome to dllbridge32"));
+    assert!(read_response(&mut reader).contains("Type ? for a list of commands"));
+    expect_prompt(&mut reader);
+
+    stream
+        .write_all(b"call helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "= 42");
+    expect_prompt(&mut reader);
+
+    stream
+        .write_all(b"call no_such_function sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("error: "));
+    expect_prompt(&mut reader);
+
+    stream
+        .write_all(b"?\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "Commands:");
+    assert!(read_response(&mut reader).starts_with("  call "));
+    let mut line = String::new();
+    while !line.starts_with("  help") {
+        line = read_response(&mut reader);
+    }
+    expect_prompt(&mut reader);
+
+    stream
+        .write_all(b"? check\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "Usage: check <function> [sig:<signature>] [args...]"
+    );
+    expect_prompt(&mut reader);
+
+    // A blank line only prompts again.
+    stream
+        .write_all(b"\n")
+        .expect("Couldnt not write to stream!");
+    expect_prompt(&mut reader);
+
+    child.kill().ok();
+}
+
+#[test]
+fn invalid_utf8_is_answered_and_read_errors_are_logged() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg(addr.port().to_string())
+        .arg("--once")
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call echo_string sig:str->str \xff\xfe\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR E_ENCODING"));
+
+    // The connection survives the bad request.
+    stream
+        .write_all(b"call helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 42");
+
+    // Closing with a reply still unread resets the connection.
+    stream
+        .write_all(b"call helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    thread::sleep(Duration::from_millis(100));
+    drop(reader);
+    drop(stream);
+
+    assert!(wait_for_exit(&mut child).success());
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    assert!(
+        stderr.contains("request is not valid UTF-8 (at byte 30)"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("connection reset by peer"), "{}", stderr);
+}
+
+#[test]
+fn buffer_parameters_are_read_back() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        ("call get_name sig:buf:32:str->void\n", "OK 1=dllbridge"),
+        (
+            "call get_name sig:buf:12->void\n",
+            "OK 1=646c6c627269646765000000",
+        ),
+        (
+            "call get_name sig:buf->void\n",
+            "ERR Invalid buffer parameter: buf (expected buf:<size>[:hex|:str])",
+        ),
+        (
+            "call get_name sig:buf:0->void\n",
+            "ERR Invalid buffer parameter: buf:0 (expected buf:<size>[:hex|:str])",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    stream
+        .write_all(b"call get_name sig:buf:99999999->void\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut reader).starts_with("ERR E_RESULT_TOO_LARGE"));
+
+    child.kill().ok();
+}
+
+#[test]
+fn results_structs_are_read_back_by_field() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    let stats = "out struct{sum:int,positive:char,mean:float,label:str,max:int}";
+    for (request, expected) in [
+        (
+            format!("call compute_stats sig:int,int,{}->void 7 2\n", stats),
+            "OK 3.sum=9 3.positive=1 3.mean=4.5 3.label=first 3.max=7",
+        ),
+        // The fields land at the C compiler's offsets, whatever the spacing.
+        (
+            "call compute_stats sig:int, int, out struct{ sum: int, positive: char, mean: float, label: str, max: int } -> void -4 1\n".to_string(),
+            "OK 3.sum=-3 3.positive=0 3.mean=-1.5 3.label=second 3.max=1",
+        ),
+        (
+            "call compute_stats sig:int,int,struct{sum:int}->void 1 2\n".to_string(),
+            "ERR Struct parameters must be out parameters: struct{sum:int}",
+        ),
+        (
+            "call compute_stats sig:int,int,out struct{sum:int,sum:int}->void 1 2\n".to_string(),
+            "ERR Struct field sum is declared twice",
+        ),
+        (
+            "call compute_stats sig:int,int,out struct{}->void 1 2\n".to_string(),
+            "ERR Invalid struct parameter: struct{} (expected out struct[ pack(<N>)]{<name>:<type>,...})",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn capture_returns_what_a_call_prints() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        (
+            "call +capture greet sig:str->int bob\n",
+            "OK 12 stdout=Hello,\\x20bob!\\n stderr=greeted\\x20bob\\n",
+        ),
+        // Nothing is captured without the modifier, or left over from before.
+        ("call greet sig:str->int eve\n", "OK 12"),
+        (
+            "call +capture AddNumbers sig:int,int->int 2 3\n",
+            "OK 5 stdout= stderr=",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn capture_leaves_the_servers_own_output_alone() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg(addr.port().to_string())
+        .args(["--access-log", "-"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (mut capturing, mut capturing_reader) = connect(addr);
+    capturing
+        .write_all(b"call +capture sleep_ms sig:int->int 300\n")
+        .expect("Couldnt not write to stream!");
+    // Logged while the call above has stdout redirected.
+    thread::sleep(Duration::from_millis(100));
+    let (mut other, mut other_reader) = connect(addr);
+    other
+        .write_all(b"caps\n")
+        .expect("Couldnt not write to stream!");
+    assert!(read_response(&mut other_reader).starts_with("OK caps "));
+
+    assert_eq!(
+        read_response(&mut capturing_reader),
+        "OK 300 stdout= stderr="
+    );
+
+    child.kill().ok();
+    let mut stdout = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    child.wait().ok();
+    assert!(stdout.contains(" cmd=caps fn=- status=ok "), "{}", stdout);
+}
+
+#[test]
+fn packed_structs_use_the_declared_packing() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    let packed = "out struct pack(1){tag:char,value:int,ratio:float,flag:char,last:int}";
+    for (request, expected) in [
+        (
+            format!("call fill_packed sig:int,{}->void 300\n", packed),
+            "OK 2.tag=80 2.value=300 2.ratio=0.25 2.flag=1 2.last=-300",
+        ),
+        // The convention still follows the parameters.
+        (
+            format!("call fill_packed sig:int,{}(cdecl)->void -7\n", packed),
+            "OK 2.tag=80 2.value=-7 2.ratio=0.25 2.flag=1 2.last=7",
+        ),
+        (
+            "call fill_packed sig:int,out struct pack(3){tag:char}->void 1\n".to_string(),
+            "ERR Unsupported struct packing: pack(3) (expected 1, 2, 4, 8 or 16)",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    child.kill().ok();
+}
+
+#[test]
+fn trace_shows_the_full_return_register() {
+    let (mut child, addr) = start_server_with(&["--allow-trace"]);
+
+    let (mut stream, mut reader) = connect(addr);
+
+    // `wide_value` returns a `long long`; declared `int`, the reply is truncated,
+    stream
+        .write_all(b"call +trace wide_value sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    let header = read_response(&mut reader);
+    let count: usize = header
+        .strip_prefix("OK TRACE ")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_else(|| panic!("{}", header));
+    let trace: Vec<String> = (0..count).map(|_| read_response(&mut reader)).collect();
+    // but the raw register still holds the high bits.
+    assert_eq!(
+        trace.last().unwrap(),
+        "return int raw=0x0000000100000002",
+        "{:?}",
+        trace
+    );
+    assert_eq!(read_response(&mut reader), "2");
+
+    child.kill().ok();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn socket_activation_serves_the_inherited_listener() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    // Bound here, as a service manager would, and handed over as fd 3.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = listener.as_raw_fd();
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg("LISTEN_PID=$$ LISTEN_FDS=1 exec \"$0\" \"$@\"")
+        .arg(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg("0");
+    unsafe {
+        command.pre_exec(move || {
+            // dup2 clears close-on-exec on the copy, but not when fd is already 3.
+            let result = if fd == 3 {
+                libc::fcntl(3, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if result < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    };
+    let mut child = command.spawn().unwrap();
+    drop(listener);
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"call helloworld sig:void->int\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 42");
+
+    child.kill().ok();
+    child.wait().ok();
+}
+
+#[test]
+fn integer_arguments_share_one_parser() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    for (request, expected) in [
+        // Boundaries of each width, in every radix.
+        (
+            "call AddNumbers sig:int,int->int 2147483647 0\n",
+            "OK 2147483647",
+        ),
+        (
+            "call AddNumbers sig:int,int->int -2147483648 0\n",
+            "OK -2147483648",
+        ),
+        (
+            "call AddNumbers sig:int,int->int 0x7fffffff -0X10\n",
+            "OK 2147483631",
+        ),
+        ("call AddNumbers sig:int,int->int 0b101 +0o17\n", "OK 20"),
+        (
+            "call AddNumbers sig:int,int->int -0x80000000 0\n",
+            "OK -2147483648",
+        ),
+        (
+            "call AddNumbers sig:int,int->int 2147483648 0\n",
+            "ERR E_RANGE Argument parsing error: '2147483648' is out of range for int (-2147483648 to 2147483647)",
+        ),
+        // u32::MAX is a valid bit pattern, but not an int.
+        (
+            "call AddNumbers sig:int,int->int 0xFFFFFFFF 0\n",
+            "ERR E_RANGE Argument parsing error: '0xFFFFFFFF' is out of range for int (-2147483648 to 2147483647)",
+        ),
+        (
+            "call AddNumbers sig:int,int->int 1 340282366920938463463374607431768211456\n",
+            "ERR E_RANGE Argument parsing error: '340282366920938463463374607431768211456' is out of range for int (-2147483648 to 2147483647)",
+        ),
+        ("call repeat_char sig:char,int->str -128 0\n", "OK"),
+        ("call repeat_char sig:char,int->str 0x41 3\n", "OK AAA"),
+        (
+            "call repeat_char sig:char,int->str -129 1\n",
+            "ERR E_RANGE Argument parsing error: '-129' is out of range for char (-128 to 127)",
+        ),
+        (
+            "call repeat_char sig:char,int->str 128 1\n",
+            "ERR E_RANGE Argument parsing error: '128' is out of range for char (-128 to 127)",
+        ),
+        // Digits outside the radix, a bare prefix or a second sign are no integers.
+        (
+            "call AddNumbers sig:int,int->int 0xZZ 0\n",
+            "ERR E_PARSE Argument parsing error: '0xZZ' is not a valid int",
+        ),
+        (
+            "call AddNumbers sig:int,int->int 0b102 0\n",
+            "ERR E_PARSE Argument parsing error: '0b102' is not a valid int",
+        ),
+        (
+            "call AddNumbers sig:int,int->int 0x 0\n",
+            "ERR E_PARSE Argument parsing error: '0x' is not a valid int",
+        ),
+        (
+            "call AddNumbers sig:int,int->int --1 0\n",
+            "ERR E_PARSE Argument parsing error: '--1' is not a valid int",
+        ),
+        (
+            "call AddNumbers sig:int,int->int 0q12 0\n",
+            "ERR E_PARSE Argument parsing error: '0q12' is not a valid int",
+        ),
+        (
+            "call counter_add sig:ptr,int->int 0xg 0\n",
+            "ERR E_PARSE Argument parsing error: '0xg' is not a valid ptr",
+        ),
+    ] {
+        stream
+            .write_all(request.as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert_eq!(read_response(&mut reader), expected, "{}", request);
+    }
+
+    // Pointers are integers of the host's width.
+    stream
+        .write_all(b"call counter_add sig:ptr,int->int -1 0\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        format!(
+            "ERR E_RANGE Argument parsing error: '-1' is out of range for ptr (0 to {})",
+            usize::MAX
+        )
+    );
+
+    child.kill().ok();
+}
+
+#[test]
+fn unfreed_allocations_are_reported_on_disconnect() {
+    let schema = write_temp_file(
+        "ownership.schema",
+        "counter_create = int->ptr; allocates=true\ncounter_destroy = ptr->void; frees=1\n",
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dllbridge32"))
+        .arg(fixture_lib())
+        .arg(addr.port().to_string())
+        .args(["--once", "--schema", &schema])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (mut stream, mut reader) = connect(addr);
+    let mut counters = Vec::new();
+    for initial in 0..3 {
+        stream
+            .write_all(format!("call counter_create {}\n", initial).as_bytes())
+            .expect("Couldnt not write to stream!");
+        counters.push(read_result(&mut reader));
+    }
+    stream
+        .write_all(format!("call counter_destroy {}\n", counters[1]).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK");
+    drop(reader);
+    drop(stream);
+
+    assert!(wait_for_exit(&mut child).success());
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    let warning = stderr
+        .lines()
+        .find(|line| line.starts_with("WARN "))
+        .unwrap_or_else(|| panic!("{}", stderr));
+    assert!(
+        warning.starts_with("WARN 2 unfreed allocations"),
+        "{}",
+        warning
+    );
+    assert!(warning.contains(&format!("{} from counter_create", counters[0])));
+    assert!(!warning.contains(&counters[1]), "{}", warning);
+}
+
+#[test]
+fn char_signedness_is_configurable() {
+    for (flag, accepted, refused) in [
+        ("--char-signed", ["-56", "-128", "127"], "200"),
+        ("--char-unsigned", ["200", "0", "255"], "-56"),
+    ] {
+        let (mut child, addr) = start_server_with(&[flag]);
+        let (mut stream, mut reader) = connect(addr);
+
+        for value in accepted {
+            stream
+                .write_all(format!("call char_identity sig:char->char {}\n", value).as_bytes())
+                .expect("Couldnt not write to stream!");
+            assert_eq!(read_result(&mut reader), value, "{}", flag);
+        }
+        stream
+            .write_all(format!("call char_identity sig:char->char {}\n", refused).as_bytes())
+            .expect("Couldnt not write to stream!");
+        assert!(
+            read_response(&mut reader).starts_with("ERR E_RANGE"),
+            "{}",
+            flag
+        );
+        // `+hex` shows the bits next to the value in the configured range.
+        stream
+            .write_all(b"call +hex char_identity sig:char->char 0x80\n")
+            .expect("Couldnt not write to stream!");
+        let expected = if flag == "--char-signed" {
+            "ERR E_RANGE Argument parsing error: '0x80' is out of range for char (-128 to 127)"
+        } else {
+            "OK 128 (0x80)"
+        };
+        assert_eq!(read_response(&mut reader), expected);
+
+        child.kill().ok();
+    }
+}
+
+#[test]
+fn pointer_returns_can_report_a_status() {
+    let (mut child, addr) = start_server();
+
+    let (mut stream, mut reader) = connect(addr);
+
+    stream
+        .write_all(b"call counter_try_create sig:int->ptr[status] 5\n")
+        .expect("Couldnt not write to stream!");
+    let reply = read_result(&mut reader);
+    let counter = reply
+        .strip_suffix(" status=ok")
+        .unwrap_or_else(|| panic!("{}", reply));
+    assert!(
+        !counter.trim_start_matches(['0', 'x']).is_empty(),
+        "{}",
+        reply
+    );
+    stream
+        .write_all(format!("call counter_add sig:ptr,int->int {} 1\n", counter).as_bytes())
+        .expect("Couldnt not write to stream!");
+    assert_eq!(read_response(&mut reader), "OK 6");
+
+    stream
+        .write_all(b"call counter_try_create sig:int->ptr[status] -1\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        format!(
+            "OK 0x{:0width$x} status=error",
+            0,
+            width = 2 * size_of::<usize>()
+        )
+    );
+
+    stream
+        .write_all(b"call helloworld sig:void->int[status]\n")
+        .expect("Couldnt not write to stream!");
+    assert_eq!(
+        read_response(&mut reader),
+        "ERR E_BAD_RET [status] requires a ptr return, not int"
+    );
 
     child.kill().ok();
 }